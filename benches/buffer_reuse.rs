@@ -0,0 +1,42 @@
+//! Converting a frame to RGB into the buffer kept between frames against a new one every frame,
+//! `cargo bench --bench buffer_reuse` to run. Frames are the movie still fixture scaled to 1080p
+//! and 4K.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use image::imageops::FilterType;
+use mini_ambilight_bluetooth::sampling::{fill_rgb, frame_from_image};
+
+const FIXTURE: &str = concat!(
+  env!("CARGO_MANIFEST_DIR"),
+  "/tests/fixtures/movie_still.png"
+);
+
+const RESOLUTIONS: [(&str, u32, u32); 2] = [("1080p", 1920, 1080), ("4k", 3840, 2160)];
+
+fn buffer_reuse(c: &mut Criterion) {
+  let image = image::open(FIXTURE).expect("movie still fixture");
+  for (resolution, width, height) in RESOLUTIONS {
+    let scaled = image.resize_exact(width, height, FilterType::Triangle);
+    let (buffer, _, _) = frame_from_image(&scaled);
+    let mut group = c.benchmark_group(format!("fill_rgb/{}", resolution));
+    group.sample_size(20);
+    let mut pixels = Vec::new();
+    group.bench_function("reused", |b| {
+      b.iter(|| {
+        fill_rgb(&mut pixels, &buffer);
+        black_box(&pixels);
+      })
+    });
+    group.bench_function("fresh", |b| {
+      b.iter(|| {
+        let mut pixels = Vec::new();
+        fill_rgb(&mut pixels, &buffer);
+        pixels
+      })
+    });
+    group.finish();
+  }
+}
+
+criterion_group!(benches, buffer_reuse);
+criterion_main!(benches);
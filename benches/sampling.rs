@@ -0,0 +1,55 @@
+//! Time of the sampling algorithms on one frame, `cargo bench` to run. Frames are the movie still
+//! fixture scaled to 1080p and 4K.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use image::imageops::FilterType;
+use mini_ambilight_bluetooth::sampling::{dominant_color_from_buffer, frame_from_image};
+use mini_ambilight_bluetooth::ColorSamplingAlgorithm;
+
+const FIXTURE: &str = concat!(
+  env!("CARGO_MANIFEST_DIR"),
+  "/tests/fixtures/movie_still.png"
+);
+
+const RESOLUTIONS: [(&str, u32, u32); 2] = [("1080p", 1920, 1080), ("4k", 3840, 2160)];
+
+fn algorithms() -> [(&'static str, ColorSamplingAlgorithm); 3] {
+  [
+    (
+      "squared_average",
+      ColorSamplingAlgorithm::SquaredAverage { sample_rate: 0.05 },
+    ),
+    (
+      "most_dominant",
+      ColorSamplingAlgorithm::MostDominant {
+        quality: 2,
+        sorted: false,
+        sort_window: 1,
+      },
+    ),
+    ("vibrancy", ColorSamplingAlgorithm::Vibrancy),
+  ]
+}
+
+fn sampling(c: &mut Criterion) {
+  let image = image::open(FIXTURE).expect("movie still fixture");
+  for (resolution, width, height) in RESOLUTIONS {
+    let scaled = image.resize_exact(width, height, FilterType::Triangle);
+    let (buffer, width, height) = frame_from_image(&scaled);
+    let mut group = c.benchmark_group(resolution);
+    group.sample_size(20);
+    for (name, algorithm) in algorithms() {
+      group.bench_with_input(
+        BenchmarkId::from_parameter(name),
+        &algorithm,
+        |b, algorithm| {
+          b.iter(|| dominant_color_from_buffer(&buffer, width, height, algorithm).unwrap())
+        },
+      );
+    }
+    group.finish();
+  }
+}
+
+criterion_group!(benches, sampling);
+criterion_main!(benches);
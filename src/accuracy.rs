@@ -0,0 +1,129 @@
+use std::error::Error;
+use std::path::Path;
+
+use mini_ambilight_bluetooth::correction::{self, Pipeline, Rounding};
+use mini_ambilight_bluetooth::sampling::{self, Sampler, SamplingConfig};
+
+/// Reference image and the color the light is expected to show for it
+struct Patch {
+  image: String,
+  expected: [u8; 3],
+}
+
+/// Run reference images through sampling and color correction and compare the produced color to
+/// the expected one, failing when any patch is further off than `tolerance` CIEDE2000 ΔE.
+///
+/// `args` is either `<image> <RRGGBB>` for a single patch or `<manifest>`, a text file with one
+/// `<image> <RRGGBB>` pair per line. Image paths in a manifest are relative to the manifest, lines
+/// starting with `#` and empty lines are skipped.
+pub fn verify(
+  args: &[String],
+  pipeline: &Pipeline,
+  config: SamplingConfig,
+  rounding: Rounding,
+  tolerance: f32,
+) -> Result<(), Box<dyn Error>> {
+  let patches = match args {
+    [image, expected] => vec![Patch {
+      image: image.clone(),
+      expected: parse_hex(expected)?,
+    }],
+    [manifest] => load_manifest(Path::new(manifest))?,
+    _ => return Err("Usage: --verify-accuracy <manifest> | <image> <RRGGBB>".into()),
+  };
+
+  println!(
+    "{:<40}{:>10}{:>10}{:>10}  result",
+    "patch", "expected", "produced", "ΔE"
+  );
+  let mut failed = 0;
+  for patch in &patches {
+    // Every patch gets a fresh sampler so smoothing state doesn't leak between unrelated images.
+    let produced = sample_image(&patch.image, &mut Sampler::new(config), pipeline, rounding)?;
+    let delta_e = correction::delta_e(patch.expected, produced);
+    let pass = delta_e <= tolerance;
+    if !pass {
+      failed += 1;
+    }
+    println!(
+      "{:<40}{:>10}{:>10}{:>10.2}  {}",
+      patch.image,
+      hex(patch.expected),
+      hex(produced),
+      delta_e,
+      if pass { "pass" } else { "FAIL" }
+    );
+  }
+
+  if failed > 0 {
+    return Err(
+      format!(
+        "{} of {} patches exceed the ΔE tolerance of {}",
+        failed,
+        patches.len(),
+        tolerance
+      )
+      .into(),
+    );
+  }
+  println!(
+    "All {} patches within the ΔE tolerance of {}",
+    patches.len(),
+    tolerance
+  );
+  Ok(())
+}
+
+fn sample_image(
+  path: &str,
+  sampler: &mut Sampler,
+  pipeline: &Pipeline,
+  rounding: Rounding,
+) -> Result<[u8; 3], Box<dyn Error>> {
+  let image = image::open(path).map_err(|e| format!("Loading {} failed: {}", path, e))?;
+  let (buffer, width, height) = sampling::frame_from_image(&image);
+  let color = sampler.sample(&buffer, width, height)?;
+  Ok(correction::to_bytes(pipeline.apply(color), rounding))
+}
+
+fn load_manifest(path: &Path) -> Result<Vec<Patch>, Box<dyn Error>> {
+  let manifest = std::fs::read_to_string(path)
+    .map_err(|e| format!("Reading {} failed: {}", path.display(), e))?;
+  let base = path.parent().unwrap_or_else(|| Path::new(""));
+  let mut patches = Vec::new();
+  for (number, line) in manifest.lines().enumerate() {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+      continue;
+    }
+    let (image, expected) = line.rsplit_once(char::is_whitespace).ok_or_else(|| {
+      format!(
+        "{}:{}: expected `<image> <RRGGBB>`",
+        path.display(),
+        number + 1
+      )
+    })?;
+    patches.push(Patch {
+      image: base.join(image.trim()).to_string_lossy().into_owned(),
+      expected: parse_hex(expected)
+        .map_err(|e| format!("{}:{}: {}", path.display(), number + 1, e))?,
+    });
+  }
+  if patches.is_empty() {
+    return Err(format!("{} contains no patches", path.display()).into());
+  }
+  Ok(patches)
+}
+
+fn parse_hex(color: &str) -> Result<[u8; 3], String> {
+  let digits = color.trim_start_matches('#');
+  let value = u32::from_str_radix(digits, 16)
+    .ok()
+    .filter(|_| digits.len() == 6)
+    .ok_or_else(|| format!("Invalid color `{}`, expected RRGGBB", color))?;
+  Ok([(value >> 16) as u8, (value >> 8) as u8, value as u8])
+}
+
+fn hex(rgb: [u8; 3]) -> String {
+  format!("#{:02x}{:02x}{:02x}", rgb[0], rgb[1], rgb[2])
+}
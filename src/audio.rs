@@ -0,0 +1,138 @@
+//! Audio analysis of the audio reactive mode, independent of where the samples come from.
+
+use std::f32::consts::PI;
+
+use glam::Vec3;
+use palette::{Hsv, IntoColor, Srgb};
+use serde::Deserialize;
+
+/// Upper edge of the bass band in Hz
+const BASS_CUTOFF: f32 = 250.0;
+/// Lower edge of the treble band in Hz
+const TREBLE_CUTOFF: f32 = 4000.0;
+/// Hue of the treble end of the `hue` mapping, bass is red at 0
+const TREBLE_HUE: f32 = 240.0;
+
+/// RMS level per band, 1 for a full scale signal
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Bands {
+  pub bass: f32,
+  pub mid: f32,
+  pub treble: f32,
+}
+
+impl Bands {
+  pub fn level(&self) -> f32 {
+    self.bass + self.mid + self.treble
+  }
+}
+
+/// Splits mono samples into bass, mid and treble with one-pole filters, cheap enough to run in
+/// the audio callback. Levels accumulate until they are taken.
+#[derive(Debug, Clone)]
+pub struct BandSplitter {
+  bass_alpha: f32,
+  treble_alpha: f32,
+  /// Filter states, lowpassed at the bass and treble cutoffs
+  below_bass: f32,
+  below_treble: f32,
+  /// Sum of squares per band since the last `take`
+  energy: [f32; 3],
+  samples: usize,
+}
+
+impl BandSplitter {
+  pub fn new(sample_rate: u32) -> BandSplitter {
+    BandSplitter {
+      bass_alpha: one_pole_alpha(BASS_CUTOFF, sample_rate),
+      treble_alpha: one_pole_alpha(TREBLE_CUTOFF, sample_rate),
+      below_bass: 0.0,
+      below_treble: 0.0,
+      energy: [0.0; 3],
+      samples: 0,
+    }
+  }
+
+  pub fn push(&mut self, samples: &[f32]) {
+    for &sample in samples {
+      self.below_bass += self.bass_alpha * (sample - self.below_bass);
+      self.below_treble += self.treble_alpha * (sample - self.below_treble);
+      let bands = [
+        self.below_bass,
+        self.below_treble - self.below_bass,
+        sample - self.below_treble,
+      ];
+      for (energy, band) in self.energy.iter_mut().zip(bands) {
+        *energy += band * band;
+      }
+    }
+    self.samples += samples.len();
+  }
+
+  /// Levels of the samples pushed since the last call, silence when there were none
+  pub fn take(&mut self) -> Bands {
+    let count = self.samples.max(1) as f32;
+    let [bass, mid, treble] = self.energy.map(|energy| (energy / count).sqrt());
+    self.energy = [0.0; 3];
+    self.samples = 0;
+    Bands { bass, mid, treble }
+  }
+}
+
+/// Smoothing factor of a one-pole lowpass at `cutoff` Hz
+fn one_pole_alpha(cutoff: f32, sample_rate: u32) -> f32 {
+  1.0 - (-2.0 * PI * cutoff / sample_rate as f32).exp()
+}
+
+/// How band levels become a color, `{ type = "hue" }` in the config
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum AudioMapping {
+  /// `color` with its brightness following the overall level
+  Level { color: [u8; 3] },
+  /// Red for bass through green to blue for treble, brightness following the overall level
+  Hue,
+  /// One color per band, each scaled by its level and added up
+  Bands {
+    bass: [u8; 3],
+    mid: [u8; 3],
+    treble: [u8; 3],
+  },
+}
+
+impl Default for AudioMapping {
+  fn default() -> AudioMapping {
+    AudioMapping::Bands {
+      bass: [255, 0, 0],
+      mid: [0, 255, 0],
+      treble: [0, 0, 255],
+    }
+  }
+}
+
+impl AudioMapping {
+  /// Color in 0...1 for the levels, which are multiplied by `sensitivity` first
+  pub fn color(&self, bands: Bands, sensitivity: f32) -> Vec3 {
+    let scale = |level: f32| (level * sensitivity).min(1.0);
+    let level = scale(bands.level());
+    let color = match *self {
+      AudioMapping::Level { color } => to_vec3(color) * level,
+      AudioMapping::Hue => {
+        // Band centroid, 0 for pure bass and 1 for pure treble
+        let centroid = (0.5 * bands.mid + bands.treble) / bands.level().max(f32::EPSILON);
+        let rgb: Srgb = Hsv::new(centroid * TREBLE_HUE, 1.0, level).into_color();
+        Vec3::new(rgb.red, rgb.green, rgb.blue)
+      }
+      AudioMapping::Bands { bass, mid, treble } => {
+        to_vec3(bass) * scale(bands.bass)
+          + to_vec3(mid) * scale(bands.mid)
+          + to_vec3(treble) * scale(bands.treble)
+      }
+    };
+    color.min(Vec3::ONE)
+  }
+}
+
+fn to_vec3(color: [u8; 3]) -> Vec3 {
+  Vec3::new(color[0] as f32, color[1] as f32, color[2] as f32) / 255.0
+}
@@ -0,0 +1,111 @@
+#![cfg_attr(not(feature = "audio"), allow(dead_code))]
+
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+
+use mini_ambilight_bluetooth::audio::{BandSplitter, Bands};
+
+/// Audio samples analyzed as they arrive, for the audio reactive mode.
+///
+/// Records what the speakers play where the platform supports loopback (WASAPI), otherwise the
+/// default input such as a microphone. Needs the `audio` feature.
+pub struct AudioInput {
+  splitter: Arc<Mutex<BandSplitter>>,
+  /// Recording stops once this is dropped
+  #[cfg(feature = "audio")]
+  _stream: cpal::Stream,
+}
+
+impl AudioInput {
+  pub fn open() -> Result<AudioInput, Box<dyn Error>> {
+    #[cfg(feature = "audio")]
+    {
+      recording::open()
+    }
+    #[cfg(not(feature = "audio"))]
+    {
+      Err("this build lacks the audio feature".into())
+    }
+  }
+
+  /// Band levels since the last call
+  pub fn take(&self) -> Bands {
+    self.splitter.lock().unwrap().take()
+  }
+}
+
+#[cfg(feature = "audio")]
+mod recording {
+  use std::error::Error;
+  use std::sync::{Arc, Mutex};
+
+  use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+  use cpal::{Device, Sample, SampleFormat, Stream, SupportedStreamConfig};
+  use log::{info, warn};
+
+  use super::AudioInput;
+  use mini_ambilight_bluetooth::audio::BandSplitter;
+
+  pub fn open() -> Result<AudioInput, Box<dyn Error>> {
+    let host = cpal::default_host();
+    let loopback = host.default_output_device().and_then(|device| {
+      let config = device.default_output_config().ok()?;
+      Some((device, config))
+    });
+    if let Some((device, config)) = loopback {
+      match record(&device, config) {
+        Ok(input) => return Ok(input),
+        Err(e) => info!("Recording the audio output failed, trying the input: {}", e),
+      }
+    }
+    let device = host
+      .default_input_device()
+      .ok_or("no audio output or input device")?;
+    let config = device.default_input_config()?;
+    record(&device, config)
+  }
+
+  fn record(device: &Device, config: SupportedStreamConfig) -> Result<AudioInput, Box<dyn Error>> {
+    let splitter = Arc::new(Mutex::new(BandSplitter::new(config.sample_rate().0)));
+    let stream = match config.sample_format() {
+      SampleFormat::F32 => build_stream::<f32>(device, &config, splitter.clone()),
+      SampleFormat::I16 => build_stream::<i16>(device, &config, splitter.clone()),
+      SampleFormat::U16 => build_stream::<u16>(device, &config, splitter.clone()),
+    }?;
+    stream.play()?;
+    info!(
+      "Recording audio from {} at {} Hz",
+      device
+        .name()
+        .unwrap_or_else(|_| "unknown device".to_string()),
+      config.sample_rate().0
+    );
+    Ok(AudioInput {
+      splitter,
+      _stream: stream,
+    })
+  }
+
+  fn build_stream<T: Sample>(
+    device: &Device,
+    config: &SupportedStreamConfig,
+    splitter: Arc<Mutex<BandSplitter>>,
+  ) -> Result<Stream, cpal::BuildStreamError> {
+    let channels = config.channels() as usize;
+    let mut mono = Vec::new();
+    device.build_input_stream(
+      &config.config(),
+      move |data: &[T], _: &cpal::InputCallbackInfo| {
+        // Mix the interleaved channels down to mono
+        mono.clear();
+        mono.extend(
+          data
+            .chunks(channels)
+            .map(|frame| frame.iter().map(|s| s.to_f32()).sum::<f32>() / channels as f32),
+        );
+        splitter.lock().unwrap().push(&mono);
+      },
+      |e| warn!("Audio input failed: {}", e),
+    )
+  }
+}
@@ -0,0 +1,68 @@
+use std::time::Duration;
+
+use mini_ambilight_bluetooth::metrics::{Stage, METRICS};
+
+/// Collects the per-stage latencies of a fixed number of frames and reports their percentiles.
+pub struct PipelineBenchmark {
+  frames: usize,
+  samples: Vec<[Duration; Stage::ALL.len()]>,
+}
+
+impl PipelineBenchmark {
+  pub fn new(frames: usize) -> PipelineBenchmark {
+    PipelineBenchmark {
+      frames,
+      samples: Vec::with_capacity(frames),
+    }
+  }
+
+  /// Take the stage latencies of the frame that just finished, returns true once enough frames
+  /// were recorded.
+  pub fn record_frame(&mut self) -> bool {
+    let mut frame = [Duration::ZERO; Stage::ALL.len()];
+    for (latency, stage) in frame.iter_mut().zip(Stage::ALL) {
+      *latency = METRICS.latency(stage);
+    }
+    self.samples.push(frame);
+    self.samples.len() >= self.frames
+  }
+
+  pub fn print(&self) {
+    println!(
+      "Pipeline benchmark over {} frames (milliseconds)",
+      self.samples.len()
+    );
+    println!(
+      "{:<12}{:>10}{:>10}{:>10}{:>10}{:>10}",
+      "stage", "mean", "p50", "p90", "p99", "max"
+    );
+    let mut total = vec![Duration::ZERO; self.samples.len()];
+    for (index, stage) in Stage::ALL.iter().enumerate() {
+      let mut latencies: Vec<Duration> = self.samples.iter().map(|frame| frame[index]).collect();
+      for (total, latency) in total.iter_mut().zip(&latencies) {
+        *total += *latency;
+      }
+      print_row(stage.name(), &mut latencies);
+    }
+    print_row("total", &mut total);
+  }
+}
+
+fn print_row(name: &str, latencies: &mut [Duration]) {
+  if latencies.is_empty() {
+    return;
+  }
+  latencies.sort_unstable();
+  let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+  let percentile = |p: f64| ms(latencies[((latencies.len() - 1) as f64 * p).round() as usize]);
+  let mean = latencies.iter().map(|d| ms(*d)).sum::<f64>() / latencies.len() as f64;
+  println!(
+    "{:<12}{:>10.2}{:>10.2}{:>10.2}{:>10.2}{:>10.2}",
+    name,
+    mean,
+    percentile(0.5),
+    percentile(0.9),
+    percentile(0.99),
+    ms(latencies[latencies.len() - 1])
+  );
+}
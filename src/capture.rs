@@ -0,0 +1,383 @@
+//! Screen capture backends. DXGI desktop duplication on Windows, `scrap` elsewhere with the
+//! `scrap` feature.
+
+use std::fmt::{self, Display};
+use std::time::Duration;
+
+use serde::Deserialize;
+use thiserror::Error;
+
+/// A captured pixel, in the BGRA byte order both backends produce
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct Bgra8 {
+  pub b: u8,
+  pub g: u8,
+  pub r: u8,
+  pub a: u8,
+}
+
+/// Failures of a single capture
+#[derive(Debug, Error)]
+pub enum CaptureError {
+  #[error("access denied, is a protected fullscreen application running?")]
+  AccessDenied,
+  #[error("access to the output was lost, e.g. by a display mode change")]
+  AccessLost,
+  #[error("no new frame before the timeout")]
+  Timeout,
+  #[error("{0}")]
+  Failed(String),
+  #[error("no output matches {target}, available are {available}")]
+  UnknownOutput { target: String, available: String },
+  #[error("several outputs match {target}: {matching}")]
+  AmbiguousOutput { target: String, matching: String },
+}
+
+/// An output that can be captured, as listed by [`outputs`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputInfo {
+  /// Index [`open`] takes
+  pub index: usize,
+  /// Monitor name, like `DELL U2720Q`
+  pub name: String,
+  /// Name of the output on the graphics adapter, like `\\.\DISPLAY1`, where the platform has one
+  pub device: Option<String>,
+  pub size: (usize, usize),
+}
+
+impl Display for OutputInfo {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "{}: {} {}x{}",
+      self.index, self.name, self.size.0, self.size.1
+    )
+  }
+}
+
+/// Output selected in the config, by its index, its monitor or device name, or its resolution
+/// as `WIDTHxHEIGHT`. Indices shift as monitors come and go, names and resolutions don't.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum CaptureTarget {
+  Index(usize),
+  Name(String),
+}
+
+impl Display for CaptureTarget {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      CaptureTarget::Index(index) => write!(f, "{}", index),
+      CaptureTarget::Name(name) => write!(f, "\"{}\"", name),
+    }
+  }
+}
+
+impl CaptureTarget {
+  /// Index of the output this target selects among `outputs`. Indices are taken as they are, a
+  /// name or resolution has to match exactly one output, names ignoring case.
+  pub fn resolve(&self, outputs: &[OutputInfo]) -> Result<usize, CaptureError> {
+    let name = match self {
+      CaptureTarget::Index(index) => return Ok(*index),
+      CaptureTarget::Name(name) => name,
+    };
+    let matching: Vec<&OutputInfo> = match parse_resolution(name) {
+      Some(size) => outputs
+        .iter()
+        .filter(|output| output.size == size)
+        .collect(),
+      None => outputs
+        .iter()
+        .filter(|output| {
+          output.name.eq_ignore_ascii_case(name)
+            || output
+              .device
+              .as_ref()
+              .is_some_and(|device| device.eq_ignore_ascii_case(name))
+        })
+        .collect(),
+    };
+    match matching.as_slice() {
+      [output] => Ok(output.index),
+      [] => Err(CaptureError::UnknownOutput {
+        target: self.to_string(),
+        available: list(outputs.iter()),
+      }),
+      _ => Err(CaptureError::AmbiguousOutput {
+        target: self.to_string(),
+        matching: list(matching.into_iter()),
+      }),
+    }
+  }
+}
+
+/// `WIDTHxHEIGHT` as a size
+fn parse_resolution(value: &str) -> Option<(usize, usize)> {
+  let (width, height) = value.split_once('x')?;
+  Some((width.trim().parse().ok()?, height.trim().parse().ok()?))
+}
+
+fn list<'a>(outputs: impl Iterator<Item = &'a OutputInfo>) -> String {
+  let list: Vec<String> = outputs.map(|output| output.to_string()).collect();
+  if list.is_empty() {
+    "none".to_string()
+  } else {
+    list.join(", ")
+  }
+}
+
+/// The outputs of this platform's capture backend, by index
+pub fn outputs() -> Result<Vec<OutputInfo>, CaptureError> {
+  #[cfg(windows)]
+  {
+    dxgi_outputs()
+  }
+  #[cfg(all(not(windows), feature = "scrap"))]
+  {
+    let displays = scrap::Display::all().map_err(|e| CaptureError::Failed(e.to_string()))?;
+    Ok(
+      displays
+        .iter()
+        .enumerate()
+        .map(|(index, display)| OutputInfo {
+          index,
+          name: format!("Display {}", index),
+          device: None,
+          size: (display.width(), display.height()),
+        })
+        .collect(),
+    )
+  }
+  #[cfg(all(not(windows), not(feature = "scrap")))]
+  {
+    Err(CaptureError::Failed(
+      "no capture backend on this platform, build with the scrap feature".to_string(),
+    ))
+  }
+}
+
+/// The outputs numbered the way dxgcap picks them: per adapter the primary output is 0 and the
+/// others count up from 1, the first adapter with an output of the index wins.
+#[cfg(windows)]
+fn dxgi_outputs() -> Result<Vec<OutputInfo>, CaptureError> {
+  use std::mem::{size_of, zeroed};
+  use std::ptr;
+
+  use winapi::shared::dxgi::{
+    CreateDXGIFactory1, IDXGIAdapter1, IDXGIFactory1, IDXGIOutput, IID_IDXGIFactory1,
+  };
+  use winapi::shared::winerror::SUCCEEDED;
+  use winapi::um::winuser::{GetMonitorInfoW, MONITORINFO, MONITORINFOF_PRIMARY};
+  use wio::com::ComPtr;
+
+  let factory = unsafe {
+    let mut factory = ptr::null_mut();
+    let hr = CreateDXGIFactory1(&IID_IDXGIFactory1, &mut factory);
+    if !SUCCEEDED(hr) {
+      return Err(CaptureError::Failed(format!(
+        "creating the DXGI factory failed: {:x}",
+        hr
+      )));
+    }
+    ComPtr::from_raw(factory as *mut IDXGIFactory1)
+  };
+  let mut outputs: Vec<OutputInfo> = Vec::new();
+  for adapter_index in 0.. {
+    let adapter = unsafe {
+      let mut adapter: *mut IDXGIAdapter1 = ptr::null_mut();
+      if !SUCCEEDED(factory.EnumAdapters1(adapter_index, &mut adapter)) {
+        break;
+      }
+      ComPtr::from_raw(adapter)
+    };
+    let mut secondary = 0;
+    for output_index in 0.. {
+      let desc = unsafe {
+        let mut output: *mut IDXGIOutput = ptr::null_mut();
+        if !SUCCEEDED(adapter.EnumOutputs(output_index, &mut output)) {
+          break;
+        }
+        let output = ComPtr::from_raw(output);
+        let mut desc = zeroed();
+        output.GetDesc(&mut desc);
+        desc
+      };
+      // dxgcap stops at the first output that isn't part of the desktop
+      if desc.AttachedToDesktop == 0 {
+        break;
+      }
+      let primary = unsafe {
+        let mut info: MONITORINFO = zeroed();
+        info.cbSize = size_of::<MONITORINFO>() as u32;
+        GetMonitorInfoW(desc.Monitor, &mut info);
+        info.dwFlags & MONITORINFOF_PRIMARY != 0
+      };
+      let index = if primary {
+        0
+      } else {
+        secondary += 1;
+        secondary
+      };
+      if outputs.iter().any(|output| output.index == index) {
+        continue;
+      }
+      let rect = desc.DesktopCoordinates;
+      let device = wide_string(&desc.DeviceName);
+      outputs.push(OutputInfo {
+        index,
+        name: monitor_name(&desc.DeviceName).unwrap_or_else(|| device.clone()),
+        device: Some(device),
+        size: (
+          (rect.right - rect.left) as usize,
+          (rect.bottom - rect.top) as usize,
+        ),
+      });
+    }
+  }
+  outputs.sort_by_key(|output| output.index);
+  Ok(outputs)
+}
+
+/// Name of the monitor shown on the adapter output `device`, as the display settings list it
+#[cfg(windows)]
+fn monitor_name(device: &[u16]) -> Option<String> {
+  use std::mem::{size_of, zeroed};
+
+  use winapi::um::wingdi::DISPLAY_DEVICEW;
+  use winapi::um::winuser::EnumDisplayDevicesW;
+
+  unsafe {
+    let mut monitor: DISPLAY_DEVICEW = zeroed();
+    monitor.cb = size_of::<DISPLAY_DEVICEW>() as u32;
+    if EnumDisplayDevicesW(device.as_ptr(), 0, &mut monitor, 0) == 0 {
+      return None;
+    }
+    Some(wide_string(&monitor.DeviceString)).filter(|name| !name.is_empty())
+  }
+}
+
+/// A NUL terminated UTF-16 buffer as a string
+#[cfg(windows)]
+fn wide_string(wide: &[u16]) -> String {
+  let len = wide.iter().position(|&c| c == 0).unwrap_or(wide.len());
+  String::from_utf16_lossy(&wide[..len])
+}
+
+/// Something that produces frames of the screen
+pub trait CaptureSource {
+  /// Capture the current frame as rows of pixels, with its width and height
+  fn capture_frame(&mut self) -> Result<(Vec<Bgra8>, (usize, usize)), CaptureError>;
+}
+
+/// Open the capture backend of this platform for the output with the given index. A capture waits
+/// up to `timeout` for the screen to change before failing with [`CaptureError::Timeout`], where
+/// the backend blocks at all.
+pub fn open(
+  output_index: usize,
+  timeout: Duration,
+) -> Result<Box<dyn CaptureSource>, CaptureError> {
+  #[cfg(windows)]
+  {
+    Ok(Box::new(DxgiCapture::new(output_index, timeout)?))
+  }
+  #[cfg(all(not(windows), feature = "scrap"))]
+  {
+    let _ = timeout;
+    Ok(Box::new(ScrapCapture::new(output_index)?))
+  }
+  #[cfg(all(not(windows), not(feature = "scrap")))]
+  {
+    let _ = (output_index, timeout);
+    Err(CaptureError::Failed(
+      "no capture backend on this platform, build with the scrap feature".to_string(),
+    ))
+  }
+}
+
+/// DXGI desktop duplication
+#[cfg(windows)]
+pub struct DxgiCapture {
+  manager: dxgcap::DXGIManager,
+}
+
+#[cfg(windows)]
+impl DxgiCapture {
+  pub fn new(output_index: usize, timeout: Duration) -> Result<DxgiCapture, CaptureError> {
+    let timeout_ms = timeout.as_millis().min(u32::MAX as u128) as u32;
+    let mut manager =
+      dxgcap::DXGIManager::new(timeout_ms).map_err(|e| CaptureError::Failed(e.to_string()))?;
+    manager.set_capture_source_index(output_index);
+    Ok(DxgiCapture { manager })
+  }
+}
+
+#[cfg(windows)]
+impl CaptureSource for DxgiCapture {
+  fn capture_frame(&mut self) -> Result<(Vec<Bgra8>, (usize, usize)), CaptureError> {
+    let (buffer, size) = self.manager.capture_frame().map_err(|e| match e {
+      dxgcap::CaptureError::AccessDenied => CaptureError::AccessDenied,
+      dxgcap::CaptureError::AccessLost => CaptureError::AccessLost,
+      dxgcap::CaptureError::Timeout => CaptureError::Timeout,
+      dxgcap::CaptureError::RefreshFailure => {
+        CaptureError::Failed("refreshing the outputs failed".to_string())
+      }
+      dxgcap::CaptureError::Fail(reason) => CaptureError::Failed(reason.to_string()),
+    })?;
+    let buffer = buffer
+      .into_iter()
+      .map(|pixel| Bgra8 {
+        b: pixel.b,
+        g: pixel.g,
+        r: pixel.r,
+        a: pixel.a,
+      })
+      .collect();
+    Ok((buffer, size))
+  }
+}
+
+/// Capture through the `scrap` crate, for platforms without DXGI
+#[cfg(feature = "scrap")]
+pub struct ScrapCapture {
+  capturer: scrap::Capturer,
+}
+
+#[cfg(feature = "scrap")]
+impl ScrapCapture {
+  pub fn new(output_index: usize) -> Result<ScrapCapture, CaptureError> {
+    let display = scrap::Display::all()
+      .map_err(|e| CaptureError::Failed(e.to_string()))?
+      .into_iter()
+      .nth(output_index)
+      .ok_or_else(|| CaptureError::Failed(format!("no display with index {}", output_index)))?;
+    let capturer =
+      scrap::Capturer::new(display).map_err(|e| CaptureError::Failed(e.to_string()))?;
+    Ok(ScrapCapture { capturer })
+  }
+}
+
+#[cfg(feature = "scrap")]
+impl CaptureSource for ScrapCapture {
+  fn capture_frame(&mut self) -> Result<(Vec<Bgra8>, (usize, usize)), CaptureError> {
+    let (width, height) = (self.capturer.width(), self.capturer.height());
+    let frame = self.capturer.frame().map_err(|e| match e.kind() {
+      std::io::ErrorKind::WouldBlock => CaptureError::Timeout,
+      _ => CaptureError::Failed(e.to_string()),
+    })?;
+    // Rows can be padded, so walk them by the stride instead of the width.
+    let stride = frame.len() / height.max(1);
+    let buffer = frame
+      .chunks(stride)
+      .take(height)
+      .flat_map(|row| row[..width * 4].chunks_exact(4))
+      .map(|pixel| Bgra8 {
+        b: pixel[0],
+        g: pixel[1],
+        r: pixel[2],
+        a: pixel[3],
+      })
+      .collect();
+    Ok((buffer, (width, height)))
+  }
+}
@@ -0,0 +1,1106 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use btleplug::api::{bleuuid::uuid_from_u16, BDAddr};
+use image::imageops::FilterType;
+use log::info;
+use serde::{Deserialize, Deserializer};
+use toml::value::Table;
+use uuid::Uuid;
+
+use crate::hotkey::Hotkey;
+use crate::idle::IdleEffect;
+use crate::schedule::ScheduleWindow;
+use crate::startup::StartupEffect;
+use crate::trace::TraceConfig;
+use mini_ambilight_bluetooth::audio::AudioMapping;
+use mini_ambilight_bluetooth::capture::CaptureTarget;
+use mini_ambilight_bluetooth::correction::{
+  Calibration, ColorPreset, CorrectionSpace, FadeConfig, FadeSpace, Limits, Pipeline, Rounding,
+  SkipMetric, TransformSpec,
+};
+use mini_ambilight_bluetooth::env_config::{self, ResolveError};
+use mini_ambilight_bluetooth::frame_diff::{FrameDiff, IdleBackoff};
+use mini_ambilight_bluetooth::health::RetryPolicy;
+use mini_ambilight_bluetooth::letterbox::Letterbox;
+use mini_ambilight_bluetooth::light::{
+  ExitAction, LightConfig, LightOutput, LightProtocol, WriteOptions, WLED_MAX_LEDS,
+};
+use mini_ambilight_bluetooth::profile::Profile;
+use mini_ambilight_bluetooth::sampling::{ColorSamplingAlgorithm, SamplingConfig, Zone};
+use mini_ambilight_bluetooth::vibrant::{PaletteOptions, VibrancyConfig};
+
+/// File looked up next to the executable when no `--config` is given
+const CONFIG_FILE: &str = "ambilight.toml";
+
+/// Slowest `max_fps` and `output_rate_hz` besides 0, the period of a tiny rate overflows a
+/// `Duration`
+const MIN_RATE_HZ: f32 = 0.01;
+
+/// Command line flags overriding a key, with the key each sets
+const CLI_OVERRIDES: &[(&str, &str)] = &[("--brightness", "brightness"), ("--profile", "profile")];
+
+/// Runtime configuration, loaded from `ambilight.toml`. Every field is optional in the file and
+/// falls back to the value of `Config::default`.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+  /// Address of the light, `aa:bb:cc:dd:ee:ff`
+  #[serde(deserialize_with = "from_str")]
+  pub light_mac: BDAddr,
+  /// Advertised name of the light, matched case-insensitively when no device with `light_mac`
+  /// shows up, so a light can be set up without knowing its address.
+  pub match_name: Option<String>,
+  /// How long to scan for each light before giving up with the devices seen meanwhile.
+  pub scan_timeout_secs: u64,
+  /// Lights with the screen zone each one follows. When empty, the single light configured by
+  /// `light_mac` and `match_name` follows the whole screen.
+  pub lights: Vec<LightConfig>,
+  /// Shortcut for two lights, one each side of the monitor, used when `lights` is empty.
+  pub stereo: Option<StereoConfig>,
+  /// Profile whose lights are driven at startup, instead of `lights`, `stereo` or `light_mac`.
+  pub profile: Option<String>,
+  /// Named sets of lights with their zones, each optionally capping the brightness or showing a
+  /// static color, `[profiles.reading]`.
+  pub profiles: BTreeMap<String, Profile>,
+  /// Global key combination switching to the next profile by name, e.g. `ctrl+alt+o`, Windows
+  /// only.
+  pub profile_hotkey: Option<Hotkey>,
+  #[serde(deserialize_with = "from_str")]
+  pub light_control_uuid: Uuid,
+  /// Bluetooth adapter to use, as numbered by `--list-adapters`.
+  pub adapter_index: usize,
+  /// Whether the lights follow the screen or, with the `audio` feature, the sound.
+  pub source: ColorSource,
+  /// How band levels become a color in the audio mode.
+  pub audio_mapping: AudioMapping,
+  /// Gain on the audio levels before they are mapped, higher reacts more to quiet sound.
+  pub audio_sensitivity: f32,
+  /// Output to capture, by index, monitor name or resolution like `"2560x1440"`.
+  pub capture_device: CaptureTarget,
+  /// Outputs captured together, each light averaging its zone over all of them by
+  /// `capture_weights` or following one with its `monitor`. Empty captures `capture_device` only.
+  pub capture_devices: Vec<CaptureTarget>,
+  /// Weight of each of `capture_devices` in the average, all equal when empty.
+  pub capture_weights: Vec<f32>,
+  /// Capture errors in a row tolerated before giving up, each failed frame is skipped.
+  pub capture_max_failures: u32,
+  /// Keep the last color for up to this many pure black frames in a row, so a brief capture
+  /// hiccup doesn't flash the light off. 0 shows black frames right away.
+  pub black_frame_hold: u32,
+  /// Upper bound of frames captured and sent per second, 0 runs as fast as capture and the light
+  /// allow.
+  pub max_fps: f32,
+  /// Writes per second of a separate output task cross-fading between the computed colors, for
+  /// smoother lights than one write per captured frame. 0 writes once per frame.
+  pub output_rate_hz: f32,
+
+  /// Ready made color correction, `natural` by default. `legacy` is the correction of earlier
+  /// versions.
+  pub color_preset: ColorPreset,
+  /// Color corrections applied in order to every sampled color instead of `color_preset`, see
+  /// `correction::Pipeline::parse` for the available transforms and their parameters.
+  pub color_pipeline: Option<Vec<TransformSpec>>,
+  /// Color model of the lightness and saturation transforms and limits. `hsv` adjusts the value,
+  /// the brightest channel, instead of the lightness, so saturating keeps highlights bright.
+  pub correction_space: CorrectionSpace,
+  /// Quantization of the final color to bytes. `nearest` removes the slight darkening of the
+  /// historic `truncate`, so output is marginally brighter than before.
+  pub color_rounding: Rounding,
+  /// Limits applied after `color_pipeline`, all in 0...1: the lightness never drops below
+  /// `min_lightness`, the saturation never exceeds `max_saturation` and the brightest channel is
+  /// raised to at least `min_brightness`, so dark scenes don't switch the light off. The defaults
+  /// change nothing.
+  pub min_lightness: f32,
+  pub max_saturation: f32,
+  pub min_brightness: f32,
+  /// White balance of the light, applied to the bytes right before they are sent. Each row of the
+  /// matrix gives an output channel as a mix of the input channels, `[[1, 0, 0], [0, 0.85, 0],
+  /// [0, 0, 1]]` tones down a green tint. `calibration_gamma` then raises each channel in 0...1 to
+  /// its power. The identity matrix and no gamma leave colors unchanged.
+  pub calibration_matrix: [[f32; 3]; 3],
+  pub calibration_gamma: Option<[f32; 3]>,
+  /// Bring calibrated colors the light can't show back in range by lowering their saturation,
+  /// keeping the hue, instead of clamping each channel
+  pub gamut_map: bool,
+  /// Shift the final color towards a black body at this many Kelvin, e.g. 3000 for a warm night
+  /// light. 6500 and unset leave colors neutral.
+  pub color_temperature: Option<f32>,
+  /// Time windows overriding the brightness and color temperature or switching the lights off,
+  /// checked once a second. The first window listed wins where they overlap.
+  pub schedule: Vec<ScheduleWindow>,
+  /// Time for the light to get halfway from its current to a new color, 0 disables the fade. The
+  /// fade is computed from the measured frame time, so it looks the same at any frame rate.
+  pub fade_half_life_ms: f32,
+  /// Half-life used while the screen is in motion. It moves from `fade_half_life_ms` on a still
+  /// screen to this value once `motion_full` of the frame difference samples change between frames.
+  pub fade_half_life_motion_ms: Option<f32>,
+  pub motion_full: f32,
+  /// Color space the fade blends in: `rgb` or `oklab`.
+  pub fade_space: FadeSpace,
+  /// Hard limit of how far each channel may move per update, in 0...255 units.
+  pub color_max_delta: Option<f32>,
+  /// A channel jumping by more than this many 0...255 units within one frame counts as a scene
+  /// change and is shown right away, bypassing the fade and `color_max_delta`.
+  pub snap_threshold: Option<f32>,
+  /// Average the color over this many frames before the fade, 1 disables it. The average starts
+  /// over on a scene change detected by `snap_threshold`.
+  pub smoothing_frames: usize,
+  /// A color whose channels all stay within this many 0...255 units of the last color sent to a
+  /// light isn't sent again, 0 only skips identical colors. Also applies to the brightness.
+  pub write_deadzone: u8,
+  /// How the distance to the last color sent is measured, `deltae` compares what the eye sees
+  /// against `write_deadzone_delta_e` instead of the channels against `write_deadzone`.
+  pub skip_metric: SkipMetric,
+  pub write_deadzone_delta_e: f32,
+  /// Have the light acknowledge every BLE write, so dropped packets are noticed and retried at
+  /// the cost of latency.
+  pub write_with_response: bool,
+  /// Tries per BLE write, with a growing delay in between, before the write counts as failed.
+  pub write_attempts: u32,
+  /// Failed writes in a row before a light is dropped, and the backoff of the tries to bring it
+  /// back, reconnecting BLE lights. The other lights keep being written meanwhile.
+  pub light_retry: RetryPolicy,
+  /// Least time between two BLE writes to a light, in milliseconds. Colors computed sooner are
+  /// held and only the latest is sent once the time passed, 0 writes every color right away.
+  pub min_write_interval_ms: u64,
+  /// Read the battery level and the manufacturer, model and firmware strings of BLE lights that
+  /// expose them, logging them and reporting them under `/stats`.
+  pub read_device_info: bool,
+  /// Resend the current color once no write went to a light for this many seconds, for lights
+  /// that dim or disconnect themselves when left alone on a still scene. Unset disables it.
+  pub keep_alive_secs: Option<f32>,
+  pub color_algorithm: ColorSamplingAlgorithm,
+  pub vibrancy: VibrancyConfig,
+  /// Pixels the vibrancy and dual tone palettes leave out, by default transparent and near white
+  /// ones.
+  pub palette: PaletteOptions,
+  /// Train the palette quantizer only every this many frames, or earlier once the downsampled frame
+  /// changed by more than `palette_requantize_change` per channel on average. Frames in between
+  /// reuse the last quantizer, which saves most of the palette work. 1 trains one every frame.
+  pub palette_requantize_frames: usize,
+  pub palette_requantize_change: f32,
+  /// Threads the squared average is computed on, in one pool for all lights and monitors. Unset
+  /// uses rayon's global pool.
+  pub threads: Option<usize>,
+
+  /// Per frame decay of remembered vibrancy colors and the largest bonus a swatch close to one of
+  /// them gets. Only swatches nearly tied with the best one get the bonus, 0 disables the memory.
+  pub color_memory_decay: f64,
+  pub color_memory_bias: f64,
+
+  /// Blend factor of the palette moving average used by the vibrancy algorithm (1 disables it) and
+  /// the RGB distance up to which swatches of consecutive frames count as the same color.
+  pub palette_ema_factor: f64,
+  pub palette_ema_threshold: f64,
+
+  /// Frames where fewer than `min_changed` of the `density`² samples moved by more than
+  /// `pixel_delta` reuse the previous sampled color.
+  pub frame_diff: FrameDiff,
+  /// After `idle_backoff_frames` unchanged frames in a row, each further one sleeps
+  /// `idle_backoff_step_ms` longer before the next capture, up to `max_idle_interval_ms`. The
+  /// first change goes back to full rate, a maximum of 0 never backs off.
+  pub idle_backoff_frames: u32,
+  pub idle_backoff_step_ms: u64,
+  pub max_idle_interval_ms: u64,
+  /// Crop black bars at the frame edges before sampling. Rows and columns with a mean luma of at
+  /// most `threshold` (0...255) count as bar.
+  pub letterbox: Letterbox,
+
+  /// Filter used to downsample frames for the palette based algorithms. Smoothing filters average
+  /// pixels, which is only correct in linear light, so `vibrancy_linear_resize` converts the frame
+  /// to linear before resizing whenever a filter other than `nearest` is used.
+  #[serde(with = "FilterTypeDef")]
+  pub vibrancy_filter: FilterType,
+  /// Scale frames are downsampled to before the palette is extracted, in (0, 1]. Larger keeps
+  /// thin colored regions at the cost of CPU time.
+  pub vibrancy_resize_factor: f32,
+  pub vibrancy_linear_resize: bool,
+
+  /// Brightness of the light in percent, 0...100.
+  pub brightness: u8,
+  /// Scale the brightness with the mean luma of the screen, so dark scenes dim the light while
+  /// keeping their hue. The light never drops below `auto_brightness_min` percent of `brightness`.
+  pub auto_brightness: bool,
+  pub auto_brightness_min: u8,
+
+  /// Command layout of the lights: `{ type = "rgb" }`, or `{ type = "rgbw", white = "extract" }`
+  /// for lights with a white LED. `white` is `extract`, `add` or `off`.
+  pub light_protocol: LightProtocol,
+
+  /// Color sent to the light when the process panics.
+  pub panic_off_color: [u8; 3],
+  /// What the light shows after Ctrl-C: `off`, `black` (fade out) or `leave`.
+  pub on_exit: ExitAction,
+  /// Global key combination pausing and resuming the lights, e.g. `ctrl+alt+p`, Windows only.
+  pub pause_hotkey: Option<Hotkey>,
+  /// What the lights show while paused, the same choices as `on_exit`.
+  pub pause_action: ExitAction,
+
+  /// Show `idle_effect` once the screen didn't change for this many seconds, unset disables it.
+  pub idle_timeout_secs: Option<f32>,
+  /// `{ type = "breathing", color = [r, g, b], period_secs = 6.0 }` or
+  /// `{ type = "rainbow", period_secs = 30.0 }`.
+  pub idle_effect: IdleEffect,
+
+  /// Animation played on all lights once connected: `off`, `rgb_fade` or `hue_sweep`, lasting
+  /// `startup_effect_ms`.
+  pub startup_effect: StartupEffect,
+  pub startup_effect_ms: u64,
+
+  /// Flash each light in turn after connecting so it can be identified.
+  pub boot_flash_enabled: bool,
+  pub boot_flash_color: [u8; 3],
+  pub boot_flash_ms: u64,
+
+  /// Frames measured by `--benchmark-pipeline` before printing the report and exiting.
+  pub benchmark_frames: usize,
+  /// Largest CIEDE2000 ΔE between produced and expected color `--verify-accuracy` accepts.
+  pub accuracy_tolerance: f32,
+  /// How long `--list-devices` scans before printing what it found.
+  pub list_devices_secs: u64,
+  /// Address to serve Prometheus metrics on, requires the `metrics` feature.
+  pub metrics_addr: Option<String>,
+  /// Address to serve the current color and stats as JSON on, e.g. `127.0.0.1:9899`.
+  pub status_addr: Option<String>,
+  /// Broker to publish the color of the first light to, requires the `mqtt` feature.
+  pub mqtt: Option<MqttConfig>,
+  /// OpenRGB server driven as one more light, requires the `openrgb` feature.
+  pub openrgb: Option<OpenRgbConfig>,
+  /// File to record the colors of every frame to, off when unset.
+  pub trace: Option<TraceConfig>,
+}
+
+impl Default for Config {
+  fn default() -> Config {
+    let sampling = SamplingConfig::default();
+    Config {
+      light_mac: BDAddr::from([0xFF, 0xFF, 0x3A, 0x00, 0x02, 0x8F]),
+      match_name: None,
+      scan_timeout_secs: 30,
+      lights: Vec::new(),
+      stereo: None,
+      profile: None,
+      profiles: BTreeMap::new(),
+      profile_hotkey: None,
+      light_control_uuid: uuid_from_u16(0xFFF1),
+      adapter_index: 0,
+      source: ColorSource::Screen,
+      audio_mapping: AudioMapping::default(),
+      audio_sensitivity: 3.0,
+      capture_device: CaptureTarget::Index(1),
+      capture_devices: Vec::new(),
+      capture_weights: Vec::new(),
+      capture_max_failures: 30,
+      black_frame_hold: 0,
+      max_fps: 30.0,
+      output_rate_hz: 0.0,
+      color_preset: ColorPreset::Natural,
+      color_pipeline: None,
+      correction_space: CorrectionSpace::Hsl,
+      color_rounding: Rounding::Nearest,
+      min_lightness: 0.0,
+      max_saturation: 1.0,
+      min_brightness: 0.0,
+      calibration_matrix: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+      calibration_gamma: None,
+      gamut_map: false,
+      color_temperature: None,
+      schedule: Vec::new(),
+      fade_half_life_ms: 100.0,
+      fade_half_life_motion_ms: None,
+      motion_full: 0.5,
+      fade_space: FadeSpace::Rgb,
+      color_max_delta: None,
+      snap_threshold: None,
+      smoothing_frames: 1,
+      write_deadzone: 0,
+      skip_metric: SkipMetric::Rgb,
+      write_deadzone_delta_e: 1.0,
+      write_with_response: false,
+      write_attempts: 3,
+      light_retry: RetryPolicy::default(),
+      min_write_interval_ms: 20,
+      read_device_info: true,
+      keep_alive_secs: None,
+      color_algorithm: sampling.algorithm,
+      vibrancy: sampling.vibrancy,
+      palette: sampling.palette,
+      threads: None,
+      palette_requantize_frames: sampling.requantize_frames,
+      palette_requantize_change: sampling.requantize_change,
+      color_memory_decay: sampling.memory_decay,
+      color_memory_bias: sampling.memory_bias,
+      palette_ema_factor: sampling.palette_ema_factor,
+      palette_ema_threshold: sampling.palette_ema_threshold,
+      frame_diff: FrameDiff {
+        density: 32,
+        pixel_delta: 8,
+        min_changed: 4,
+      },
+      idle_backoff_frames: 60,
+      idle_backoff_step_ms: 10,
+      max_idle_interval_ms: 200,
+      letterbox: Letterbox::default(),
+      vibrancy_filter: sampling.filter,
+      vibrancy_resize_factor: sampling.resize_factor,
+      vibrancy_linear_resize: sampling.linear_resize,
+      brightness: 100,
+      auto_brightness: false,
+      auto_brightness_min: 10,
+      light_protocol: LightProtocol::Rgb,
+      panic_off_color: [0, 0, 0],
+      on_exit: ExitAction::Black,
+      pause_hotkey: None,
+      pause_action: ExitAction::Leave,
+      idle_timeout_secs: None,
+      idle_effect: IdleEffect::Breathing {
+        color: [255, 120, 40],
+        period_secs: 6.0,
+      },
+      startup_effect: StartupEffect::Off,
+      startup_effect_ms: 1500,
+      boot_flash_enabled: false,
+      boot_flash_color: [255, 255, 255],
+      boot_flash_ms: 500,
+      benchmark_frames: 300,
+      accuracy_tolerance: 5.0,
+      list_devices_secs: 10,
+      metrics_addr: None,
+      status_addr: None,
+      mqtt: None,
+      openrgb: None,
+      trace: None,
+    }
+  }
+}
+
+/// Where the colors come from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorSource {
+  #[default]
+  Screen,
+  /// System audio, or the default input where it can't be recorded. Falls back to the screen
+  /// without any audio device.
+  Audio,
+}
+
+/// MQTT output, `{ host = "..", topic = "ambilight/color" }`. Colors are published at most every
+/// `min_interval_ms`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(not(feature = "mqtt"), allow(dead_code))]
+pub struct MqttConfig {
+  pub host: String,
+  #[serde(default = "MqttConfig::default_port")]
+  pub port: u16,
+  pub topic: String,
+  #[serde(default = "MqttConfig::default_client_id")]
+  pub client_id: String,
+  #[serde(default)]
+  pub username: Option<String>,
+  #[serde(default)]
+  pub password: Option<String>,
+  #[serde(default = "MqttConfig::default_min_interval_ms")]
+  pub min_interval_ms: u64,
+}
+
+impl MqttConfig {
+  fn default_port() -> u16 {
+    1883
+  }
+
+  fn default_client_id() -> String {
+    "mini-ambilight-bluetooth".to_string()
+  }
+
+  fn default_min_interval_ms() -> u64 {
+    200
+  }
+}
+
+/// OpenRGB SDK server, `{ addr = "127.0.0.1:6742", devices = [{ index = 0, leds = 30 }] }`.
+/// It is driven as one more light following `zone`, with colors sent at most every
+/// `min_interval_ms`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(not(feature = "openrgb"), allow(dead_code))]
+pub struct OpenRgbConfig {
+  pub addr: String,
+  #[serde(default = "OpenRgbConfig::default_devices")]
+  pub devices: Vec<OpenRgbDevice>,
+  #[serde(default = "OpenRgbConfig::default_min_interval_ms")]
+  pub min_interval_ms: u64,
+  #[serde(default)]
+  pub zone: Zone,
+}
+
+impl OpenRgbConfig {
+  fn default_devices() -> Vec<OpenRgbDevice> {
+    vec![OpenRgbDevice { index: 0, leds: 1 }]
+  }
+
+  fn default_min_interval_ms() -> u64 {
+    50
+  }
+}
+
+/// Controller on the OpenRGB server that follows the ambilight
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(not(feature = "openrgb"), allow(dead_code))]
+pub struct OpenRgbDevice {
+  /// Controller index as listed by the OpenRGB server
+  pub index: u32,
+  /// Number of LEDs on the controller, all of them get the same color
+  pub leds: u16,
+}
+
+/// Two BLE lights, one following the left and one the right edge of the screen,
+/// `{ left = "..", right = "..", left_fraction = 0.2, right_fraction = 0.2 }`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StereoConfig {
+  #[serde(deserialize_with = "from_str")]
+  pub left: BDAddr,
+  #[serde(deserialize_with = "from_str")]
+  pub right: BDAddr,
+  /// Width of the left and right border the lights follow, as a fraction of the frame width
+  #[serde(default = "StereoConfig::default_fraction")]
+  pub left_fraction: f32,
+  #[serde(default = "StereoConfig::default_fraction")]
+  pub right_fraction: f32,
+}
+
+impl StereoConfig {
+  fn default_fraction() -> f32 {
+    0.2
+  }
+
+  /// The two lights, each averaging the pixels of its border with the edge sampling
+  fn lights(&self) -> Vec<LightConfig> {
+    let light = |mac, x, w| LightConfig {
+      mac,
+      match_name: None,
+      zone: Zone {
+        x,
+        y: 0.0,
+        w,
+        h: 1.0,
+      },
+      output: LightOutput::Ble,
+      monitor: None,
+      // Past half the cropped border the edge sampling covers all of it
+      algorithm: Some(ColorSamplingAlgorithm::EdgeAverage {
+        border_fraction: 0.5,
+      }),
+      protocol: None,
+      retry: None,
+    };
+    vec![
+      light(self.left, 0.0, self.left_fraction),
+      light(self.right, 1.0 - self.right_fraction, self.right_fraction),
+    ]
+  }
+}
+
+impl Config {
+  /// Load the file given with `--config <path>`, or `ambilight.toml` next to the executable.
+  /// Without either the defaults are used. `AMBILIGHT_*` environment variables override the file,
+  /// see [`env_config::from_env`], and `--brightness <0-100>` overrides `brightness` over both.
+  pub fn load(args: &[String]) -> Result<Config, Box<dyn Error>> {
+    let explicit = match args.iter().position(|arg| arg == "--config") {
+      Some(index) => Some(PathBuf::from(
+        args.get(index + 1).ok_or("--config expects a path")?,
+      )),
+      None => None,
+    };
+    let path = match explicit {
+      Some(path) => Some(path),
+      None => Some(std::env::current_exe()?.with_file_name(CONFIG_FILE)).filter(|path| {
+        let exists = path.exists();
+        if !exists {
+          info!("No {} found, using the built-in defaults", path.display());
+        }
+        exists
+      }),
+    };
+
+    let table = match &path {
+      Some(path) => {
+        info!("Loading configuration from {}", path.display());
+        Config::read_table(path)?
+      }
+      None => Table::new(),
+    };
+    let vars = std::env::vars_os()
+      .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)));
+    let mut overrides = env_config::from_env(vars, env_config::field_names::<Config>())?;
+    overrides.extend(env_config::from_args(args, CLI_OVERRIDES)?);
+    let config =
+      env_config::resolve(&table, &overrides, Config::validate).map_err(|e| match e {
+        ResolveError::File(e) => match &path {
+          Some(path) => format!("Invalid configuration in {}: {}", path.display(), e),
+          None => format!("Invalid configuration: {}", e),
+        },
+        ResolveError::Override { source, error } => {
+          format!("Invalid configuration from {}: {}", source, error)
+        }
+      })?;
+    Ok(config)
+  }
+
+  /// The file's keys, deserialized only once the overrides are applied
+  fn read_table(path: &Path) -> Result<Table, Box<dyn Error>> {
+    let contents = std::fs::read_to_string(path)
+      .map_err(|e| format!("Reading {} failed: {}", path.display(), e))?;
+    let table =
+      toml::from_str(&contents).map_err(|e| format!("Parsing {} failed: {}", path.display(), e))?;
+    Ok(table)
+  }
+
+  /// Reject values that would only fail, or silently misbehave, once the capture loop runs
+  fn validate(&self) -> Result<(), String> {
+    self.pipeline()?;
+    if !self.write_deadzone_delta_e.is_finite() || self.write_deadzone_delta_e < 0.0 {
+      return Err(format!(
+        "write_deadzone_delta_e must not be negative, got {}",
+        self.write_deadzone_delta_e
+      ));
+    }
+    if self.write_attempts == 0 {
+      return Err("write_attempts must be at least 1".to_string());
+    }
+    if self.scan_timeout_secs == 0 {
+      return Err("scan_timeout_secs must be at least 1".to_string());
+    }
+    if self
+      .trace
+      .as_ref()
+      .is_some_and(|trace| trace.stages.is_empty())
+    {
+      return Err("trace.stages must name at least one stage".to_string());
+    }
+    if !self.audio_sensitivity.is_finite() || self.audio_sensitivity <= 0.0 {
+      return Err(format!(
+        "audio_sensitivity must be positive, got {}",
+        self.audio_sensitivity
+      ));
+    }
+    if !is_rate(self.max_fps) {
+      return Err(format!(
+        "max_fps must be 0 or at least {}, got {}",
+        MIN_RATE_HZ, self.max_fps
+      ));
+    }
+    if !is_rate(self.output_rate_hz) {
+      return Err(format!(
+        "output_rate_hz must be 0 or at least {}, got {}",
+        MIN_RATE_HZ, self.output_rate_hz
+      ));
+    }
+    if self.fade_half_life_ms < 0.0 {
+      return Err(format!(
+        "fade_half_life_ms must not be negative, got {}",
+        self.fade_half_life_ms
+      ));
+    }
+    if let Some(half_life) = self.fade_half_life_motion_ms {
+      if half_life < 0.0 {
+        return Err(format!(
+          "fade_half_life_motion_ms must not be negative, got {}",
+          half_life
+        ));
+      }
+    }
+    if self.brightness > 100 || self.auto_brightness_min > 100 {
+      return Err(format!(
+        "brightness and auto_brightness_min are percentages, got {} and {}",
+        self.brightness, self.auto_brightness_min
+      ));
+    }
+    if self.motion_full <= 0.0 {
+      return Err(format!(
+        "motion_full must be positive, got {}",
+        self.motion_full
+      ));
+    }
+    if let Some(max_delta) = self.color_max_delta {
+      if max_delta <= 0.0 {
+        return Err(format!(
+          "color_max_delta must be positive, got {}",
+          max_delta
+        ));
+      }
+    }
+    if let Some(threshold) = self.snap_threshold {
+      if threshold <= 0.0 {
+        return Err(format!(
+          "snap_threshold must be positive, got {}",
+          threshold
+        ));
+      }
+    }
+    validate_algorithm(&self.color_algorithm)?;
+    if !(0.0..=1.0).contains(&self.color_memory_decay) {
+      return Err(format!(
+        "color_memory_decay must be in 0...1, got {}",
+        self.color_memory_decay
+      ));
+    }
+    if !(self.vibrancy_resize_factor > 0.0 && self.vibrancy_resize_factor <= 1.0) {
+      return Err(format!(
+        "vibrancy_resize_factor must be in (0, 1], got {}",
+        self.vibrancy_resize_factor
+      ));
+    }
+    if self.palette_ema_factor <= 0.0 || self.palette_ema_factor > 1.0 {
+      return Err(format!(
+        "palette_ema_factor must be in (0, 1], got {}",
+        self.palette_ema_factor
+      ));
+    }
+    if let Some(stereo) = &self.stereo {
+      if !self.lights.is_empty() {
+        return Err("Set either stereo or lights, not both".to_string());
+      }
+      for (side, fraction) in [
+        ("left", stereo.left_fraction),
+        ("right", stereo.right_fraction),
+      ] {
+        if !(fraction > 0.0 && fraction <= 1.0) {
+          return Err(format!(
+            "stereo.{}_fraction must be in (0, 1], got {}",
+            side, fraction
+          ));
+        }
+      }
+    }
+    if !self.capture_weights.is_empty() {
+      if self.capture_weights.len() != self.capture_devices.len() {
+        return Err(format!(
+          "capture_weights needs one weight per capture_devices entry, got {} for {}",
+          self.capture_weights.len(),
+          self.capture_devices.len()
+        ));
+      }
+      if let Some(weight) = self
+        .capture_weights
+        .iter()
+        .find(|weight| !weight.is_finite() || **weight <= 0.0)
+      {
+        return Err(format!("capture_weights must be positive, got {}", weight));
+      }
+    }
+    let monitors = self.monitors();
+    let openrgb = self
+      .lights()
+      .into_iter()
+      .filter(|light| light.output == LightOutput::OpenRgb);
+    let profile_lights = self
+      .profiles
+      .values()
+      .flat_map(|profile| profile.lights.iter().cloned());
+    for light in self
+      .lights
+      .iter()
+      .cloned()
+      .chain(openrgb)
+      .chain(profile_lights)
+    {
+      if let Some(algorithm) = &light.algorithm {
+        validate_algorithm(algorithm).map_err(|e| format!("Light {}: {}", light, e))?;
+      }
+      if let Some(protocol) = &light.protocol {
+        validate_protocol(protocol).map_err(|e| format!("Light {}: {}", light, e))?;
+      }
+      if let Some(retry) = &light.retry {
+        validate_retry(retry).map_err(|e| format!("Light {}: retry: {}", light, e))?;
+      }
+      if let Some(monitor) = &light.monitor {
+        if !monitors.iter().any(|(target, _)| target == monitor) {
+          return Err(format!(
+            "Light {} follows monitor {}, which is not captured",
+            light, monitor
+          ));
+        }
+      }
+      let Zone { x, y, w, h } = light.zone;
+      // Allow a little float slack so zones like 0.7 + 0.3 still end on the edge.
+      if x < 0.0 || y < 0.0 || w <= 0.0 || h <= 0.0 || x + w > 1.001 || y + h > 1.001 {
+        return Err(format!(
+          "Zone of light {} must lie within the screen, in fractions 0...1",
+          light
+        ));
+      }
+      match &light.output {
+        LightOutput::Ble if light.mac == BDAddr::default() && light.match_name.is_none() => {
+          return Err("Every BLE light needs a mac or match_name".to_string());
+        }
+        LightOutput::Wled { leds, .. } if *leds == 0 || *leds > WLED_MAX_LEDS => {
+          return Err(format!(
+            "leds of light {} must be in 1...{}, got {}",
+            light, WLED_MAX_LEDS, leds
+          ));
+        }
+        // DNRGB carries the start index in two bytes, the range must end within them.
+        LightOutput::Wled { start, leds, .. } if *start as u32 + *leds as u32 > 1 << 16 => {
+          return Err(format!(
+            "LEDs {}..{} of light {} go past the last WLED index {}",
+            start,
+            *start as u32 + *leds as u32,
+            light,
+            u16::MAX
+          ));
+        }
+        _ => {}
+      }
+    }
+    validate_protocol(&self.light_protocol).map_err(|e| format!("light_protocol: {}", e))?;
+    if let Some(profile) = &self.profile {
+      if !self.profiles.contains_key(profile) {
+        return Err(format!("profile {} is not one of profiles", profile));
+      }
+    }
+    for (name, profile) in &self.profiles {
+      if profile.lights.is_empty() {
+        return Err(format!("Profile {} needs at least one light", name));
+      }
+      if profile
+        .brightness
+        .is_some_and(|brightness| brightness > 100)
+      {
+        return Err(format!(
+          "Brightness of profile {} must be in 0...100, got {:?}",
+          name, profile.brightness
+        ));
+      }
+    }
+    if self.profile_hotkey.is_some() && self.profiles.is_empty() {
+      return Err("profile_hotkey needs profiles to switch between".to_string());
+    }
+    validate_retry(&self.light_retry).map_err(|e| format!("light_retry: {}", e))?;
+    for (name, value) in [
+      ("min_lightness", self.min_lightness),
+      ("max_saturation", self.max_saturation),
+      ("min_brightness", self.min_brightness),
+    ] {
+      if !(0.0..=1.0).contains(&value) {
+        return Err(format!("{} must be in 0...1, got {}", name, value));
+      }
+    }
+    if self
+      .calibration_gamma
+      .is_some_and(|gamma| gamma.iter().any(|g| *g <= 0.0))
+    {
+      return Err("calibration_gamma must be positive".to_string());
+    }
+    let temperatures = self
+      .schedule
+      .iter()
+      .filter_map(|window| window.color_temperature);
+    for kelvin in self.color_temperature.into_iter().chain(temperatures) {
+      if !(1000.0..=40000.0).contains(&kelvin) {
+        return Err(format!(
+          "color_temperature must be in 1000...40000 K, got {}",
+          kelvin
+        ));
+      }
+    }
+    for window in &self.schedule {
+      if window.brightness.is_some_and(|brightness| brightness > 100) {
+        return Err(format!(
+          "Schedule window brightness must be in 0...100, got {:?}",
+          window.brightness
+        ));
+      }
+    }
+    if self
+      .keep_alive_secs
+      .is_some_and(|interval| !interval.is_finite() || interval <= 0.0)
+    {
+      return Err("keep_alive_secs must be positive".to_string());
+    }
+    if self
+      .idle_timeout_secs
+      .is_some_and(|timeout| !timeout.is_finite() || timeout < 0.0)
+    {
+      return Err("idle_timeout_secs must be a non-negative number".to_string());
+    }
+    if self.idle_effect.period_secs() <= 0.0 {
+      return Err(format!(
+        "idle_effect.period_secs must be positive, got {}",
+        self.idle_effect.period_secs()
+      ));
+    }
+    if self.palette_requantize_frames == 0 {
+      return Err("palette_requantize_frames must be at least 1".to_string());
+    }
+    if self.threads == Some(0) {
+      return Err("threads must be at least 1".to_string());
+    }
+    if self.frame_diff.density == 0 {
+      return Err("frame_diff.density must be at least 1".to_string());
+    }
+    Ok(())
+  }
+
+  /// The lights driven at startup, see [`Config::profile_lights`].
+  pub fn lights(&self) -> Vec<LightConfig> {
+    self.profile_lights(self.profile.as_deref())
+  }
+
+  /// The lights of `profile`, without one the configured lights, the `stereo` pair, or the single
+  /// `light_mac` light covering the whole screen, followed by the `[openrgb]` light when set.
+  pub fn profile_lights(&self, profile: Option<&str>) -> Vec<LightConfig> {
+    let profile = profile.and_then(|name| self.profiles.get(name));
+    let mut lights = if let Some(profile) = profile {
+      profile.lights.clone()
+    } else if !self.lights.is_empty() {
+      self.lights.clone()
+    } else if let Some(stereo) = &self.stereo {
+      stereo.lights()
+    } else {
+      vec![LightConfig {
+        mac: self.light_mac,
+        match_name: self.match_name.clone(),
+        zone: Zone::default(),
+        output: LightOutput::default(),
+        monitor: None,
+        algorithm: None,
+        protocol: None,
+        retry: None,
+      }]
+    };
+    if let Some(openrgb) = &self.openrgb {
+      lights.push(LightConfig {
+        mac: BDAddr::default(),
+        match_name: None,
+        zone: openrgb.zone,
+        output: LightOutput::OpenRgb,
+        monitor: None,
+        algorithm: None,
+        protocol: None,
+        retry: None,
+      });
+    }
+    lights
+  }
+
+  /// Command layout of `light`, its own or `light_protocol`
+  pub fn protocol<'a>(&'a self, light: &'a LightConfig) -> &'a LightProtocol {
+    light.protocol.as_ref().unwrap_or(&self.light_protocol)
+  }
+
+  /// When `light` is dropped and tried again, its own policy or `light_retry`
+  pub fn retry_policy(&self, light: &LightConfig) -> RetryPolicy {
+    light.retry.unwrap_or(self.light_retry)
+  }
+
+  /// Outputs to capture, each with its weight in the average
+  pub fn monitors(&self) -> Vec<(CaptureTarget, f32)> {
+    if self.capture_devices.is_empty() {
+      return vec![(self.capture_device.clone(), 1.0)];
+    }
+    self
+      .capture_devices
+      .iter()
+      .enumerate()
+      .map(|(i, target)| {
+        let weight = self.capture_weights.get(i).copied().unwrap_or(1.0);
+        (target.clone(), weight)
+      })
+      .collect()
+  }
+
+  /// How BLE writes are sent and retried
+  pub fn write_options(&self) -> WriteOptions {
+    WriteOptions {
+      with_response: self.write_with_response,
+      attempts: self.write_attempts,
+    }
+  }
+
+  /// Distance to the last color sent up to which a write is skipped, in units of `skip_metric`
+  pub fn skip_threshold(&self) -> f32 {
+    match self.skip_metric {
+      SkipMetric::Rgb => self.write_deadzone as f32,
+      SkipMetric::DeltaE => self.write_deadzone_delta_e,
+    }
+  }
+
+  pub fn keep_alive(&self) -> Option<Duration> {
+    self.keep_alive_secs.map(Duration::from_secs_f32)
+  }
+
+  pub fn idle_timeout(&self) -> Option<Duration> {
+    self.idle_timeout_secs.map(Duration::from_secs_f32)
+  }
+
+  pub fn min_write_interval(&self) -> Duration {
+    Duration::from_millis(self.min_write_interval_ms)
+  }
+
+  pub fn fade_config(&self) -> FadeConfig {
+    FadeConfig {
+      half_life_ms: self.fade_half_life_ms,
+      motion_half_life_ms: self.fade_half_life_motion_ms,
+      motion_full: self.motion_full,
+      space: self.fade_space,
+      max_delta: self.color_max_delta,
+      snap_threshold: self.snap_threshold,
+    }
+  }
+
+  pub fn idle_backoff(&self) -> IdleBackoff {
+    IdleBackoff::new(
+      self.idle_backoff_frames,
+      Duration::from_millis(self.idle_backoff_step_ms),
+      Duration::from_millis(self.max_idle_interval_ms),
+    )
+  }
+
+  /// The correction of `color_pipeline`, or of `color_preset` when there is none
+  pub fn pipeline(&self) -> Result<Pipeline, String> {
+    let transforms = match &self.color_pipeline {
+      Some(transforms) => transforms.clone(),
+      None => self.color_preset.transforms(),
+    };
+    Pipeline::parse(&transforms, self.correction_space)
+  }
+
+  /// The color limits, `None` when they wouldn't change anything
+  pub fn limits(&self) -> Option<Limits> {
+    let limits = Limits {
+      min_lightness: self.min_lightness,
+      max_saturation: self.max_saturation,
+      min_brightness: self.min_brightness,
+      space: self.correction_space,
+    };
+    (limits != Limits::default()).then_some(limits)
+  }
+
+  pub fn calibration(&self) -> Calibration {
+    let calibration = Calibration::from_rows(self.calibration_matrix, self.calibration_gamma)
+      .with_gamut_map(self.gamut_map);
+    match self.color_temperature {
+      Some(kelvin) => calibration.with_temperature(kelvin),
+      None => calibration,
+    }
+  }
+
+  pub fn sampling(&self) -> SamplingConfig {
+    SamplingConfig {
+      algorithm: self.color_algorithm,
+      vibrancy: self.vibrancy,
+      palette: self.palette,
+      filter: self.vibrancy_filter,
+      resize_factor: self.vibrancy_resize_factor,
+      linear_resize: self.vibrancy_linear_resize,
+      palette_ema_factor: self.palette_ema_factor,
+      palette_ema_threshold: self.palette_ema_threshold,
+      memory_decay: self.color_memory_decay,
+      memory_bias: self.color_memory_bias,
+      requantize_frames: self.palette_requantize_frames,
+      requantize_change: self.palette_requantize_change,
+    }
+  }
+}
+
+/// Reject command layouts that can't drive a light
+fn validate_protocol(protocol: &LightProtocol) -> Result<(), String> {
+  match protocol {
+    LightProtocol::Custom { command_template } if command_template.is_empty() => {
+      Err("command_template must not be empty".to_string())
+    }
+    _ => Ok(()),
+  }
+}
+
+/// Reject retry policies that would never drop a light or never try it again
+/// Whether `rate` is off or at least `MIN_RATE_HZ`
+fn is_rate(rate: f32) -> bool {
+  rate == 0.0 || (rate.is_finite() && rate >= MIN_RATE_HZ)
+}
+
+fn validate_retry(policy: &RetryPolicy) -> Result<(), String> {
+  if policy.max_failures == 0 {
+    return Err("max_failures must be at least 1".to_string());
+  }
+  if !policy.retry_secs.is_finite() || policy.retry_secs <= 0.0 {
+    return Err(format!(
+      "retry_secs must be positive, got {}",
+      policy.retry_secs
+    ));
+  }
+  if !policy.max_retry_secs.is_finite() || policy.max_retry_secs < policy.retry_secs {
+    return Err(format!(
+      "max_retry_secs must be at least retry_secs, got {}",
+      policy.max_retry_secs
+    ));
+  }
+  Ok(())
+}
+
+/// Reject sampling parameters the algorithm can't work with
+fn validate_algorithm(algorithm: &ColorSamplingAlgorithm) -> Result<(), String> {
+  match *algorithm {
+    ColorSamplingAlgorithm::SquaredAverage { sample_rate }
+    | ColorSamplingAlgorithm::LinearAverage { sample_rate }
+      if sample_rate <= 0.0 || sample_rate > 1.0 =>
+    {
+      Err(format!(
+        "sample_rate must be in (0, 1], got {}",
+        sample_rate
+      ))
+    }
+    ColorSamplingAlgorithm::EdgeAverage { border_fraction } if border_fraction <= 0.0 => Err(
+      format!("border_fraction must be positive, got {}", border_fraction),
+    ),
+    ColorSamplingAlgorithm::KMeans { sample_rate, .. }
+      if sample_rate <= 0.0 || sample_rate > 1.0 =>
+    {
+      Err(format!(
+        "sample_rate must be in (0, 1], got {}",
+        sample_rate
+      ))
+    }
+    ColorSamplingAlgorithm::KMeans { k: 0, .. } => Err("k must be at least 1".to_string()),
+    ColorSamplingAlgorithm::KMeans { iterations: 0, .. } => {
+      Err("iterations must be at least 1".to_string())
+    }
+    ColorSamplingAlgorithm::HueHistogram { bins: 0 } => Err("bins must be at least 1".to_string()),
+    ColorSamplingAlgorithm::WeightedPalette { swatches: 0 } => {
+      Err("swatches must be at least 1".to_string())
+    }
+    ColorSamplingAlgorithm::MostDominant { quality: 0, .. } => {
+      Err("quality must be at least 1".to_string())
+    }
+    _ => Ok(()),
+  }
+}
+
+fn from_str<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+  D: Deserializer<'de>,
+  T: FromStr,
+  T::Err: Display,
+{
+  let value = String::deserialize(deserializer)?;
+  value.parse().map_err(serde::de::Error::custom)
+}
+
+#[derive(Deserialize)]
+#[serde(remote = "FilterType", rename_all = "snake_case")]
+enum FilterTypeDef {
+  Nearest,
+  Triangle,
+  CatmullRom,
+  Gaussian,
+  Lanczos3,
+}
@@ -0,0 +1,769 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use glam::{Mat3, Vec3};
+use palette::{
+  convert::IntoColorUnclamped, rgb::Rgb, ColorDifference, Hsl, Hsv, IntoColor, Lab, Mix, Oklab,
+  Oklch, Srgb,
+};
+use serde::Deserialize;
+
+/// A single color correction step working on RGB in 0...1
+pub trait ColorTransform {
+  fn apply(&self, color: Vec3) -> Vec3;
+}
+
+/// A transform by name with its parameters, `{ name = "gamma", params = [1.0] }` in the config
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TransformSpec {
+  pub name: String,
+  #[serde(default)]
+  pub params: Vec<f32>,
+}
+
+impl TransformSpec {
+  pub fn new(name: &str, params: &[f32]) -> TransformSpec {
+    TransformSpec {
+      name: name.to_string(),
+      params: params.to_vec(),
+    }
+  }
+}
+
+/// Ordered list of color transforms applied to every sampled color
+pub struct Pipeline {
+  transforms: Vec<Box<dyn ColorTransform>>,
+}
+
+impl Pipeline {
+  /// Build the pipeline from the transform specs, in order, adjusting colors in `space`.
+  ///
+  /// Available transforms:
+  /// - `gamma [gamma]` or `gamma [red, green, blue]` for a gamma per channel
+  /// - `lightness [target, weight]` pulls the lightness, or the HSV value, towards the target
+  /// - `saturation [target, weight]` pulls the saturation towards the target
+  /// - `soft_lightness [target, weight]` and `soft_saturation [target, weight]` pull harder the
+  ///   further the color is from the target, leaving colors close to it almost unchanged
+  /// - `reactivity [chroma, saturation, ...]` sets the saturation from the color's chroma through a
+  ///   piecewise linear curve, given as at least two control points with ascending chroma
+  ///
+  /// A lightness directly followed by a saturation pull is applied in a single conversion to the
+  /// color model and back, as the historic correction did. Separated by another transform, or in
+  /// the opposite order, each pull converts on its own, and the order matters: black saturated
+  /// first has no hue to keep and is only lifted to gray.
+  pub fn parse(spec: &[TransformSpec], space: CorrectionSpace) -> Result<Pipeline, String> {
+    let mut transforms: Vec<Box<dyn ColorTransform>> = Vec::new();
+    let mut index = 0;
+    while index < spec.len() {
+      if let Some(shade) = spec
+        .get(index..index + 2)
+        .and_then(|pair| Shade::parse(&pair[0], &pair[1], space))
+      {
+        transforms.push(Box::new(shade));
+        index += 2;
+        continue;
+      }
+      let spec = &spec[index];
+      transforms.push(parse_transform(&spec.name, &spec.params, space)?);
+      index += 1;
+    }
+    Ok(Pipeline { transforms })
+  }
+
+  /// Append a transform after the configured ones
+  pub fn then(mut self, transform: Box<dyn ColorTransform>) -> Pipeline {
+    self.transforms.push(transform);
+    self
+  }
+
+  pub fn apply(&self, color: Vec3) -> Vec3 {
+    self
+      .transforms
+      .iter()
+      .fold(color, |color, transform| transform.apply(color))
+  }
+}
+
+/// Ready made corrections, `color_preset` in the config
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorPreset {
+  /// Gently lifts dark and washed out colors, leaving colors that are already vivid alone
+  #[default]
+  Natural,
+  /// Strongly saturates, though less than `legacy` on colors close to the targets
+  Vivid,
+  /// No correction at all
+  Neutral,
+  /// The historic correction, a flat pull of every color to half lightness and full saturation
+  Legacy,
+}
+
+impl ColorPreset {
+  pub fn transforms(&self) -> Vec<TransformSpec> {
+    match self {
+      ColorPreset::Natural => vec![
+        TransformSpec::new("soft_lightness", &[0.5, 0.3]),
+        TransformSpec::new("soft_saturation", &[1.0, 0.4]),
+      ],
+      ColorPreset::Vivid => vec![
+        TransformSpec::new("soft_lightness", &[0.5, 0.6]),
+        TransformSpec::new("soft_saturation", &[1.0, 0.9]),
+      ],
+      ColorPreset::Neutral => Vec::new(),
+      ColorPreset::Legacy => vec![
+        TransformSpec::new("gamma", &[1.0]),
+        TransformSpec::new("lightness", &[0.5, 0.9]),
+        TransformSpec::new("saturation", &[1.0, 0.9]),
+      ],
+    }
+  }
+}
+
+/// Color model the lightness and saturation corrections work in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CorrectionSpace {
+  /// Lightness and saturation, the historic behavior. Raising the saturation of a bright color
+  /// also darkens it, as full saturation sits at half lightness.
+  #[default]
+  Hsl,
+  /// Value and saturation, where value is the brightest channel. Saturation changes keep the
+  /// brightness of the LEDs, so highlights stay bright.
+  Hsv,
+}
+
+/// How the final 0...255 float channels are quantized to bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rounding {
+  /// Cut off the fraction, the historic behavior which biases every channel slightly dark
+  Truncate,
+  /// Round to the closest byte, slightly brighter than `Truncate` on average
+  Nearest,
+  Floor,
+  Ceil,
+}
+
+/// Color space the fade between consecutive colors is blended in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FadeSpace {
+  /// Per channel on the sRGB values, the historic behavior
+  Rgb,
+  /// Perceptually even, keeps transitions between complementary colors from passing through gray
+  Oklab,
+}
+
+/// Blend from `previous` towards `target`, keeping the `keep` share of `previous`
+pub fn fade(previous: Vec3, target: Vec3, keep: f32, space: FadeSpace) -> Vec3 {
+  match space {
+    FadeSpace::Rgb => previous * keep + target * (1.0 - keep),
+    FadeSpace::Oklab => {
+      let previous: Oklab = Srgb::new(previous.x, previous.y, previous.z).into_color();
+      let target: Oklab = Srgb::new(target.x, target.y, target.z).into_color();
+      let rgb: Srgb = previous.mix(&target, 1.0 - keep).into_color();
+      Vec3::new(rgb.red, rgb.green, rgb.blue).clamp(Vec3::ZERO, Vec3::ONE)
+    }
+  }
+}
+
+/// How the shown color follows its target each frame, see the matching fields of `config::Config`
+#[derive(Debug, Clone, Copy)]
+pub struct FadeConfig {
+  /// Time to get halfway to a new color on a still screen, 0 disables the fade
+  pub half_life_ms: f32,
+  /// Half-life reached once `motion_full` of the frame difference samples change
+  pub motion_half_life_ms: Option<f32>,
+  pub motion_full: f32,
+  pub space: FadeSpace,
+  /// Largest change per channel and frame, in 0...255 units
+  pub max_delta: Option<f32>,
+  /// Jump straight to targets differing by more than this on any channel, in 0...255 units
+  pub snap_threshold: Option<f32>,
+}
+
+impl FadeConfig {
+  /// Share of the previous color kept this frame. The half-life, moved towards the motion
+  /// half-life by the share of changed samples, is turned into the time constant `tau`, so the
+  /// per frame blend `1 - exp(-dt / tau)` depends on elapsed time, not frame count.
+  pub fn keep(&self, motion: f32, frame_time: Duration) -> f32 {
+    let half_life = match self.motion_half_life_ms {
+      Some(motion_half_life) => {
+        self.half_life_ms
+          + (motion_half_life - self.half_life_ms) * (motion / self.motion_full).min(1.0)
+      }
+      None => self.half_life_ms,
+    };
+    if half_life <= 0.0 {
+      return 0.0;
+    }
+    let tau = half_life / 1000.0 / std::f32::consts::LN_2;
+    (-frame_time.as_secs_f32() / tau).exp()
+  }
+
+  /// Whether `target` is far enough from `previous` to jump to it instead of fading
+  pub fn snaps(&self, previous: Vec3, target: Vec3) -> bool {
+    self
+      .snap_threshold
+      .is_some_and(|threshold| (target - previous).abs().max_element() * 255.0 > threshold)
+  }
+
+  /// Fade from `previous` towards `target` keeping the `keep` share of it, then limit the step to
+  /// `max_delta`
+  pub fn step(&self, previous: Vec3, target: Vec3, keep: f32) -> Vec3 {
+    let color = fade(previous, target, keep, self.space);
+    match self.max_delta {
+      Some(max_delta) => limit_step(previous, color, max_delta / 255.0),
+      None => color,
+    }
+  }
+}
+
+/// Move from `previous` towards `target` by at most `max_delta` per channel.
+fn limit_step(previous: Vec3, target: Vec3, max_delta: f32) -> Vec3 {
+  previous + (target - previous).clamp(Vec3::splat(-max_delta), Vec3::splat(max_delta))
+}
+
+/// How the distance of a color to the one last sent to a light is measured, to skip writes that
+/// wouldn't change what the light shows
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SkipMetric {
+  /// Largest difference of a single channel, in 0...255 units
+  #[default]
+  Rgb,
+  /// CIEDE2000 difference, about 1 being the smallest visible step regardless of how dark or
+  /// saturated the colors are
+  DeltaE,
+}
+
+impl SkipMetric {
+  pub fn distance(&self, a: [u8; 3], b: [u8; 3]) -> f32 {
+    match self {
+      SkipMetric::Rgb => (0..3).map(|c| a[c].abs_diff(b[c])).max().unwrap_or(0) as f32,
+      SkipMetric::DeltaE => delta_e(a, b),
+    }
+  }
+}
+
+/// CIEDE2000 difference between two sRGB colors
+pub fn delta_e(a: [u8; 3], b: [u8; 3]) -> f32 {
+  let lab = |rgb: [u8; 3]| -> Lab {
+    Srgb::new(rgb[0], rgb[1], rgb[2])
+      .into_format::<f32>()
+      .into_color()
+  };
+  lab(a).get_color_difference(&lab(b))
+}
+
+/// Weighted mean of the last `len` colors, the newest counting most. Evens out colors that flip
+/// between close values frame to frame without the long tail of a slow fade.
+#[derive(Debug, Clone)]
+pub struct Smoother {
+  colors: VecDeque<Vec3>,
+  len: usize,
+}
+
+impl Smoother {
+  /// Average over `len` frames, 0 and 1 pass colors through unchanged
+  pub fn new(len: usize) -> Smoother {
+    Smoother {
+      colors: VecDeque::with_capacity(len.max(1)),
+      len: len.max(1),
+    }
+  }
+
+  /// Add the color of this frame and return the mean. The i-th oldest color is weighted i.
+  pub fn push(&mut self, color: Vec3) -> Vec3 {
+    if self.colors.len() == self.len {
+      self.colors.pop_front();
+    }
+    self.colors.push_back(color);
+    let (sum, weights) = self
+      .colors
+      .iter()
+      .zip(1..)
+      .fold((Vec3::ZERO, 0.0), |(sum, weights), (color, weight)| {
+        (sum + *color * weight as f32, weights + weight as f32)
+      });
+    sum / weights
+  }
+
+  /// Forget the earlier colors, e.g. on a scene cut
+  pub fn reset(&mut self) {
+    self.colors.clear();
+  }
+}
+
+/// Convert a corrected 0...1 color to the bytes sent to the light
+pub fn to_bytes(color: Vec3, rounding: Rounding) -> [u8; 3] {
+  let color = (color * 255.0).clamp(Vec3::ZERO, Vec3::splat(255.0));
+  let color = match rounding {
+    // The cast below truncates
+    Rounding::Truncate => color,
+    Rounding::Nearest => color.round(),
+    Rounding::Floor => color.floor(),
+    Rounding::Ceil => color.ceil(),
+  };
+  [color.x as u8, color.y as u8, color.z as u8]
+}
+
+/// White balance of a specific light: a 3x3 matrix and optional per channel gamma applied to the
+/// bytes right before they are sent, to make up for LEDs that don't render colors evenly, e.g. a
+/// green tint. A color temperature `tint` multiplies the result last. The default changes
+/// nothing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Calibration {
+  pub matrix: Mat3,
+  /// Lookup tables raising each channel to the power of its calibration gamma
+  pub gamma: Option<Gamma>,
+  pub tint: Vec3,
+  /// Bring matrix results the light can't show back with [`gamut_map`] instead of clamping
+  pub gamut_map: bool,
+}
+
+impl Default for Calibration {
+  fn default() -> Calibration {
+    Calibration {
+      matrix: Mat3::IDENTITY,
+      gamma: None,
+      tint: Vec3::ONE,
+      gamut_map: false,
+    }
+  }
+}
+
+impl Calibration {
+  /// From the matrix rows, `[[r_from_r, r_from_g, r_from_b], ...]`, as written in the config
+  pub fn from_rows(rows: [[f32; 3]; 3], gamma: Option<[f32; 3]>) -> Calibration {
+    Calibration {
+      matrix: Mat3::from_cols_array_2d(&rows).transpose(),
+      // `Gamma` raises to one over its gamma, the calibration to the gamma itself
+      gamma: gamma.map(|gamma| Gamma::new(gamma.map(|gamma| 1.0 / gamma))),
+      tint: Vec3::ONE,
+      gamut_map: false,
+    }
+  }
+
+  /// Shift white towards the color of a black body at `kelvin`, see [`temperature_tint`]
+  pub fn with_temperature(self, kelvin: f32) -> Calibration {
+    Calibration {
+      tint: temperature_tint(kelvin),
+      ..self
+    }
+  }
+
+  pub fn with_gamut_map(self, gamut_map: bool) -> Calibration {
+    Calibration { gamut_map, ..self }
+  }
+
+  pub fn apply(&self, rgb: [u8; 3]) -> [u8; 3] {
+    let color = Vec3::new(rgb[0] as f32, rgb[1] as f32, rgb[2] as f32) / 255.0;
+    let color = self.matrix * color;
+    let mut color = if self.gamut_map {
+      gamut_map(color)
+    } else {
+      color.clamp(Vec3::ZERO, Vec3::ONE)
+    };
+    if let Some(gamma) = &self.gamma {
+      color = gamma.apply(color);
+    }
+    to_bytes(color * self.tint, Rounding::Nearest)
+  }
+}
+
+/// Bring a color outside of 0...1 into range keeping its Oklch hue. The lightness is clamped
+/// first, then the chroma is reduced until the color fits, where clamping each channel would
+/// shift the hue, e.g. turn a deep blue purple. Colors in range are returned unchanged.
+pub fn gamut_map(color: Vec3) -> Vec3 {
+  let in_gamut = |color: Vec3| {
+    color.cmpge(Vec3::splat(-1e-4)).all() && color.cmple(Vec3::splat(1.0 + 1e-4)).all()
+  };
+  if in_gamut(color) {
+    return color;
+  }
+  // Unclamped, the clamping conversions would clip the channels just like before
+  let oklch: Oklch = Srgb::new(color.x, color.y, color.z).into_color_unclamped();
+  let with_chroma = |chroma: f32| -> Vec3 {
+    let rgb: Srgb = Oklch::new(oklch.l.clamp(0.0, 1.0), chroma, oklch.hue).into_color_unclamped();
+    Vec3::new(rgb.red, rgb.green, rgb.blue)
+  };
+  // Bisect for the largest chroma still in range, gray at this lightness always is
+  let (mut low, mut high) = (0.0, oklch.chroma);
+  for _ in 0..20 {
+    let chroma = (low + high) / 2.0;
+    if in_gamut(with_chroma(chroma)) {
+      low = chroma;
+    } else {
+      high = chroma;
+    }
+  }
+  with_chroma(low).clamp(Vec3::ZERO, Vec3::ONE)
+}
+
+/// Temperature the screen colors are taken to be at, it leaves them unchanged
+pub const NEUTRAL_KELVIN: f32 = 6500.0;
+
+/// Channel multipliers moving white from [`NEUTRAL_KELVIN`] to the color of a black body at
+/// `kelvin`, warmer below and cooler above. The strongest channel stays at 1, so the tint only
+/// ever dims.
+pub fn temperature_tint(kelvin: f32) -> Vec3 {
+  let tint = blackbody(kelvin) / blackbody(NEUTRAL_KELVIN);
+  tint / tint.max_element()
+}
+
+/// Tanner Helland's fit of the black body color in sRGB, 0...1, valid from 1000 to 40000 K
+fn blackbody(kelvin: f32) -> Vec3 {
+  let t = kelvin.clamp(1000.0, 40000.0) / 100.0;
+  let red = if t <= 66.0 {
+    255.0
+  } else {
+    329.69873 * (t - 60.0).powf(-0.13320476)
+  };
+  let green = if t <= 66.0 {
+    99.4708 * t.ln() - 161.11957
+  } else {
+    288.12217 * (t - 60.0).powf(-0.075514846)
+  };
+  let blue = if t >= 66.0 {
+    255.0
+  } else if t <= 19.0 {
+    0.0
+  } else {
+    138.51773 * (t - 10.0).ln() - 305.0448
+  };
+  Vec3::new(red, green, blue).clamp(Vec3::ZERO, Vec3::splat(255.0)) / 255.0
+}
+
+fn parse_transform(
+  name: &str,
+  params: &[f32],
+  space: CorrectionSpace,
+) -> Result<Box<dyn ColorTransform>, String> {
+  let expect = |count: usize| {
+    if params.len() == count {
+      Ok(())
+    } else {
+      Err(format!(
+        "Color transform `{}` takes {} parameters, got {}",
+        name,
+        count,
+        params.len()
+      ))
+    }
+  };
+
+  match name {
+    "gamma" => {
+      let gamma = match params {
+        [gamma] => [*gamma; 3],
+        [red, green, blue] => [*red, *green, *blue],
+        _ => {
+          return Err(format!(
+            "Color transform `gamma` takes 1 or 3 parameters, got {}",
+            params.len()
+          ))
+        }
+      };
+      if let Some(gamma) = gamma.iter().find(|gamma| **gamma <= 0.0) {
+        return Err(format!("Gamma must be positive, got {}", gamma));
+      }
+      Ok(Box::new(Gamma::new(gamma)))
+    }
+    "lightness" | "soft_lightness" => {
+      expect(2)?;
+      Ok(Box::new(Lightness {
+        target: params[0],
+        weight: params[1],
+        soft: name.starts_with("soft_"),
+        space,
+      }))
+    }
+    "saturation" | "soft_saturation" => {
+      expect(2)?;
+      Ok(Box::new(Saturation {
+        target: params[0],
+        weight: params[1],
+        soft: name.starts_with("soft_"),
+        space,
+      }))
+    }
+    "reactivity" => Ok(Box::new(ReactivityCurve::new(params, space)?)),
+    _ => Err(format!("Unknown color transform `{}`", name)),
+  }
+}
+
+/// Gamma per red, green and blue channel, for LEDs whose channels respond differently
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gamma(pub [GammaLut; 3]);
+
+impl Gamma {
+  pub fn new(gamma: [f32; 3]) -> Gamma {
+    Gamma(gamma.map(GammaLut::new))
+  }
+}
+
+impl ColorTransform for Gamma {
+  fn apply(&self, color: Vec3) -> Vec3 {
+    Vec3::new(
+      self.0[0].apply(color.x),
+      self.0[1].apply(color.y),
+      self.0[2].apply(color.z),
+    )
+  }
+}
+
+/// `x^(1/gamma)` precomputed for the 256 byte values, built once so applying gamma costs a lookup
+/// instead of a `powf`. A gamma of 1 skips the table entirely.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GammaLut {
+  table: Option<[f32; 256]>,
+}
+
+impl GammaLut {
+  pub fn new(gamma: f32) -> GammaLut {
+    if gamma == 1.0 {
+      return GammaLut { table: None };
+    }
+    let mut table = [0.0; 256];
+    for (value, entry) in table.iter_mut().enumerate() {
+      *entry = (value as f32 / 255.0).powf(1.0 / gamma);
+    }
+    GammaLut { table: Some(table) }
+  }
+
+  /// Gamma corrected 0...1 value, interpolated linearly between the table entries. Within the
+  /// first steps above black, where the curve is steepest, this is slightly darker than `powf`.
+  pub fn apply(&self, value: f32) -> f32 {
+    let table = match &self.table {
+      Some(table) => table,
+      None => return value,
+    };
+    let position = value.clamp(0.0, 1.0) * 255.0;
+    let index = (position as usize).min(254);
+    let fraction = position - index as f32;
+    table[index] + (table[index + 1] - table[index]) * fraction
+  }
+}
+
+/// Pulls the lightness, or the value in HSV, towards `target`
+pub struct Lightness {
+  pub target: f32,
+  pub weight: f32,
+  /// Scale the weight by the distance to the target, see [`pull`]
+  pub soft: bool,
+  pub space: CorrectionSpace,
+}
+
+impl ColorTransform for Lightness {
+  fn apply(&self, color: Vec3) -> Vec3 {
+    map_shade(color, self.space, |lightness, _| {
+      *lightness = pull(*lightness, self.target, self.weight, self.soft)
+    })
+  }
+}
+
+pub struct Saturation {
+  pub target: f32,
+  pub weight: f32,
+  /// Scale the weight by the distance to the target, see [`pull`]
+  pub soft: bool,
+  pub space: CorrectionSpace,
+}
+
+impl ColorTransform for Saturation {
+  fn apply(&self, color: Vec3) -> Vec3 {
+    map_shade(color, self.space, |_, saturation| {
+      *saturation = pull(*saturation, self.target, self.weight, self.soft)
+    })
+  }
+}
+
+/// A lightness and a saturation pull sharing one conversion to the color model and back
+struct Shade {
+  lightness: Lightness,
+  saturation: Saturation,
+}
+
+impl Shade {
+  /// The pair, if it is a lightness followed by a saturation pull with two parameters each.
+  /// Anything else is left to `parse_transform`, which reports wrong parameter counts.
+  fn parse(
+    lightness: &TransformSpec,
+    saturation: &TransformSpec,
+    space: CorrectionSpace,
+  ) -> Option<Shade> {
+    let params = |spec: &TransformSpec, kind: &str| {
+      let soft = match spec.name.strip_suffix(kind)? {
+        "" => false,
+        "soft_" => true,
+        _ => return None,
+      };
+      match spec.params[..] {
+        [target, weight] => Some((target, weight, soft)),
+        _ => None,
+      }
+    };
+    let (target, weight, soft) = params(lightness, "lightness")?;
+    let lightness = Lightness {
+      target,
+      weight,
+      soft,
+      space,
+    };
+    let (target, weight, soft) = params(saturation, "saturation")?;
+    let saturation = Saturation {
+      target,
+      weight,
+      soft,
+      space,
+    };
+    Some(Shade {
+      lightness,
+      saturation,
+    })
+  }
+}
+
+impl ColorTransform for Shade {
+  fn apply(&self, color: Vec3) -> Vec3 {
+    let (lightness, saturation) = (&self.lightness, &self.saturation);
+    map_shade(color, lightness.space, |l, s| {
+      *l = pull(*l, lightness.target, lightness.weight, lightness.soft);
+      *s = pull(*s, saturation.target, saturation.weight, saturation.soft);
+    })
+  }
+}
+
+/// Floors and ceilings for the corrected color: the lightness, or the value in HSV, stays at
+/// least `min_lightness` and saturation at most `max_saturation`, then the brightest channel is
+/// raised to `min_brightness` keeping the hue. All in 0...1, `Limits::default()` changes nothing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+  pub min_lightness: f32,
+  pub max_saturation: f32,
+  pub min_brightness: f32,
+  pub space: CorrectionSpace,
+}
+
+impl Default for Limits {
+  fn default() -> Limits {
+    Limits {
+      min_lightness: 0.0,
+      max_saturation: 1.0,
+      min_brightness: 0.0,
+      space: CorrectionSpace::Hsl,
+    }
+  }
+}
+
+impl ColorTransform for Limits {
+  fn apply(&self, color: Vec3) -> Vec3 {
+    let color = map_shade(color, self.space, |lightness, saturation| {
+      *lightness = lightness.max(self.min_lightness);
+      *saturation = saturation.min(self.max_saturation);
+    });
+    let brightness = color.max_element();
+    if brightness >= self.min_brightness {
+      color
+    } else if brightness <= 0.0 {
+      Vec3::splat(self.min_brightness)
+    } else {
+      color * (self.min_brightness / brightness)
+    }
+  }
+}
+
+/// Maps the chroma of the sampled color (max - min channel, 0...1) to the output saturation, so
+/// low color content stays close to white and only gets vivid as the screen gets colorful.
+pub struct ReactivityCurve {
+  points: Vec<(f32, f32)>,
+  space: CorrectionSpace,
+}
+
+impl ReactivityCurve {
+  /// Build the curve from flattened `chroma, saturation` pairs, setting the saturation of `space`
+  pub fn new(params: &[f32], space: CorrectionSpace) -> Result<ReactivityCurve, String> {
+    let pairs = params.chunks_exact(2);
+    if params.len() < 4 || !pairs.remainder().is_empty() {
+      return Err(format!(
+        "Reactivity curve takes at least two `chroma, saturation` pairs, got {} parameters",
+        params.len()
+      ));
+    }
+    let points = pairs.map(|point| (point[0], point[1])).collect::<Vec<_>>();
+    if points.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+      return Err("Reactivity curve points must have ascending chroma".to_string());
+    }
+    if points
+      .iter()
+      .any(|&(x, y)| !(0.0..=1.0).contains(&x) || !(0.0..=1.0).contains(&y))
+    {
+      return Err("Reactivity curve points must lie in 0...1".to_string());
+    }
+    Ok(ReactivityCurve { points, space })
+  }
+
+  /// Output saturation for the given chroma, clamped to the first and last point
+  pub fn saturation(&self, chroma: f32) -> f32 {
+    let first = self.points[0];
+    let last = self.points[self.points.len() - 1];
+    if chroma <= first.0 {
+      return first.1;
+    }
+    if chroma >= last.0 {
+      return last.1;
+    }
+    // Only a NaN chroma finds no segment
+    let Some(segment) = self.points.windows(2).find(|pair| chroma <= pair[1].0) else {
+      return last.1;
+    };
+    let ((x0, y0), (x1, y1)) = (segment[0], segment[1]);
+    y0 + (y1 - y0) * (chroma - x0) / (x1 - x0)
+  }
+}
+
+impl ColorTransform for ReactivityCurve {
+  fn apply(&self, color: Vec3) -> Vec3 {
+    let chroma = color.max_element() - color.min_element();
+    let saturation = self.saturation(chroma.clamp(0.0, 1.0));
+    map_shade(color, self.space, |_, value| *value = saturation)
+  }
+}
+
+/// Adjust the color through `f`, which gets the lightness (HSL) or value (HSV) and the
+/// saturation of `space`
+fn map_shade(color: Vec3, space: CorrectionSpace, f: impl FnOnce(&mut f32, &mut f32)) -> Vec3 {
+  let rgb = Rgb::new(color.x, color.y, color.z);
+  let rgb: Rgb = match space {
+    CorrectionSpace::Hsl => {
+      let mut hsl: Hsl = rgb.into_color();
+      f(&mut hsl.lightness, &mut hsl.saturation);
+      hsl.into_color()
+    }
+    CorrectionSpace::Hsv => {
+      let mut hsv: Hsv = rgb.into_color();
+      f(&mut hsv.value, &mut hsv.saturation);
+      hsv.into_color()
+    }
+  };
+  Vec3::new(rgb.red, rgb.green, rgb.blue)
+}
+
+/// Move `x` towards `target` by `weight`. Soft pulls scale the weight by the distance, so a value
+/// at the target stays put, one a full unit away gets the whole weight and the correction fades
+/// in smoothly between them instead of moving every value by the same share.
+fn pull(x: f32, target: f32, weight: f32, soft: bool) -> f32 {
+  let weight = if soft {
+    weight * (target - x).abs().min(1.0)
+  } else {
+    weight
+  };
+  mix(x, target, weight)
+}
+
+fn mix(x: f32, y: f32, weight: f32) -> f32 {
+  (x * x * (1.0 - weight) + y * y * weight).sqrt()
+}
@@ -0,0 +1,63 @@
+use std::collections::VecDeque;
+
+use color_thief::Color;
+use glam::Vec3;
+
+/// Sort key used to order the dominant palette, the lowest key is picked.
+///
+/// The key is `(max² - min²) / max` of the channels, i.e. the chroma `max - min` weighted by
+/// `(max + min) / max`. It is 0 for greys and grows with both colorfulness and brightness, up to
+/// 255 for fully saturated primaries. Computed in floats, as in `u8` the products overflow.
+pub fn sort_key(color: &Color) -> f32 {
+  let color = Vec3::new(color.r as f32, color.g as f32, color.b as f32);
+  let min = color.min_element();
+  let max = color.max_element();
+  (max + min) * (max - min) / max.max(1.0)
+}
+
+/// Averages the sort keys of the dominant palette over the last frames.
+///
+/// When two swatches have nearly the same key the per-frame sort flips between them, so each
+/// candidate is ranked by the mean key of its closest swatch in every remembered frame instead.
+#[derive(Debug, Default)]
+pub struct SortKeyAverager {
+  history: VecDeque<Vec<(Color, f32)>>,
+}
+
+impl SortKeyAverager {
+  /// Pick the swatch with the lowest sort key averaged over the last `window` frames.
+  pub fn select(&mut self, palette: &[Color], window: usize) -> Option<Color> {
+    self.history.push_back(
+      palette
+        .iter()
+        .map(|color| (*color, sort_key(color)))
+        .collect(),
+    );
+    while self.history.len() > window.max(1) {
+      self.history.pop_front();
+    }
+
+    palette.iter().copied().min_by(|a, b| {
+      self
+        .averaged_key(a)
+        .partial_cmp(&self.averaged_key(b))
+        .unwrap_or(std::cmp::Ordering::Equal)
+    })
+  }
+
+  fn averaged_key(&self, color: &Color) -> f32 {
+    let keys = self.history.iter().filter_map(|frame| {
+      frame
+        .iter()
+        .min_by_key(|(swatch, _)| distance_squared(swatch, color))
+        .map(|(_, key)| *key)
+    });
+    let (sum, count) = keys.fold((0.0, 0), |(sum, count), key| (sum + key, count + 1));
+    sum / count.max(1) as f32
+  }
+}
+
+fn distance_squared(a: &Color, b: &Color) -> u32 {
+  let d = |x: u8, y: u8| (x as i32 - y as i32).pow(2) as u32;
+  d(a.r, b.r) + d(a.g, b.g) + d(a.b, b.b)
+}
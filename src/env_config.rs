@@ -0,0 +1,184 @@
+use log::{info, warn};
+use serde::de::{self, DeserializeOwned, Deserializer, Visitor};
+use serde::{forward_to_deserialize_any, Deserialize};
+use toml::value::{Table, Value};
+
+/// Prefix of the environment variables overriding configuration keys
+pub const PREFIX: &str = "AMBILIGHT_";
+
+/// A key set on top of the configuration file, by an `AMBILIGHT_*` variable or a command line flag
+#[derive(Debug, Clone, PartialEq)]
+pub struct Override {
+  /// The variable or flag setting the key, named in errors about its value
+  pub source: String,
+  /// The key, preceded by the tables it is in
+  pub path: Vec<String>,
+  pub value: Value,
+}
+
+impl Override {
+  /// Set the key in `table`, creating the tables on the way
+  pub fn apply(&self, table: &mut Table) -> Result<(), String> {
+    let (last, tables) = self
+      .path
+      .split_last()
+      .ok_or_else(|| format!("{} does not name a configuration key", self.source))?;
+    let mut target = table;
+    for step in tables {
+      let value = target
+        .entry(step.clone())
+        .or_insert_with(|| Value::Table(Table::new()));
+      target = match value {
+        Value::Table(inner) => inner,
+        _ => return Err(format!("{} is not a table", step)),
+      };
+    }
+    target.insert(last.clone(), self.value.clone());
+    Ok(())
+  }
+}
+
+/// Where a configuration was rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResolveError {
+  /// The file fails on its own
+  File(String),
+  /// The file is fine, it fails once the override from `source` is applied
+  Override { source: String, error: String },
+}
+
+/// The keys set by the `AMBILIGHT_*` variables in `vars`, applied on top of the file and below
+/// the command line.
+///
+/// The rest of the variable name is the key in lower case, `AMBILIGHT_BRIGHTNESS` sets
+/// `brightness`, and a double underscore steps into a table, `AMBILIGHT_MQTT__HOST` sets `host`
+/// of `[mqtt]`. Values are read as TOML, falling back to a plain string, so
+/// `AMBILIGHT_LIGHT_MAC=FF:FF:3A:00:02:8F` needs no quotes. Other variables are ignored, and so
+/// are ones whose key is not in `known`, with a warning, so a stray variable doesn't stop the
+/// program.
+pub fn from_env(
+  vars: impl IntoIterator<Item = (String, String)>,
+  known: &[&str],
+) -> Result<Vec<Override>, String> {
+  let mut overrides = Vec::new();
+  for (name, raw) in vars {
+    let Some(key) = name.strip_prefix(PREFIX) else {
+      continue;
+    };
+    let path: Vec<_> = key.split("__").map(str::to_lowercase).collect();
+    if path.iter().any(String::is_empty) {
+      return Err(format!("{} does not name a configuration key", name));
+    }
+    if !known.contains(&path[0].as_str()) {
+      warn!(
+        "Ignoring {}, there is no configuration key {}",
+        name, path[0]
+      );
+      continue;
+    }
+    info!("Setting {} from {}", path.join("."), name);
+    overrides.push(Override {
+      source: name,
+      path,
+      value: parse_value(&raw),
+    });
+  }
+  Ok(overrides)
+}
+
+/// The keys set on the command line, `flags` pairing each flag with the key it sets to the
+/// argument after it. Values are read like those of the variables.
+pub fn from_args(args: &[String], flags: &[(&str, &str)]) -> Result<Vec<Override>, String> {
+  let mut overrides = Vec::new();
+  for (flag, key) in flags {
+    if let Some(index) = args.iter().position(|arg| arg == flag) {
+      let raw = args
+        .get(index + 1)
+        .ok_or_else(|| format!("{} expects a value", flag))?;
+      overrides.push(Override {
+        source: flag.to_string(),
+        path: vec![key.to_string()],
+        value: parse_value(raw),
+      });
+    }
+  }
+  Ok(overrides)
+}
+
+/// Deserialize `table` with the `overrides` applied in order, so later ones win, and `check` the
+/// result. On failure the file is blamed when it fails on its own, otherwise the first override
+/// it fails with.
+pub fn resolve<T: DeserializeOwned>(
+  table: &Table,
+  overrides: &[Override],
+  check: impl Fn(&T) -> Result<(), String>,
+) -> Result<T, ResolveError> {
+  let build = |count: usize| -> Result<T, String> {
+    let mut table = table.clone();
+    for item in &overrides[..count] {
+      item.apply(&mut table)?;
+    }
+    let value: T = Value::Table(table)
+      .try_into()
+      .map_err(|e: toml::de::Error| e.to_string())?;
+    check(&value)?;
+    Ok(value)
+  };
+  let error = match build(overrides.len()) {
+    Ok(value) => return Ok(value),
+    Err(error) => error,
+  };
+  if let Err(error) = build(0) {
+    return Err(ResolveError::File(error));
+  }
+  for (index, item) in overrides.iter().enumerate() {
+    if let Err(error) = build(index + 1) {
+      return Err(ResolveError::Override {
+        source: item.source.clone(),
+        error,
+      });
+    }
+  }
+  Err(ResolveError::File(error))
+}
+
+/// Names of the fields of the struct `T` deserializes from, empty for anything else
+pub fn field_names<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+  let mut fields: &'static [&'static str] = &[];
+  let _ = T::deserialize(FieldNames(&mut fields));
+  fields
+}
+
+/// Deserializer that only records the field names a struct asks for
+struct FieldNames<'a>(&'a mut &'static [&'static str]);
+
+impl<'de> Deserializer<'de> for FieldNames<'_> {
+  type Error = de::value::Error;
+
+  fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+    Err(de::Error::custom("not a struct"))
+  }
+
+  fn deserialize_struct<V: Visitor<'de>>(
+    self,
+    _name: &'static str,
+    fields: &'static [&'static str],
+    _visitor: V,
+  ) -> Result<V::Value, Self::Error> {
+    *self.0 = fields;
+    Err(de::Error::custom("only the field names are read"))
+  }
+
+  forward_to_deserialize_any! {
+    bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf option
+    unit unit_struct newtype_struct seq tuple tuple_struct map enum identifier ignored_any
+  }
+}
+
+/// The TOML value written in `raw`, or `raw` itself as a string
+fn parse_value(raw: &str) -> Value {
+  toml::from_str::<Table>(&format!("value = {}", raw))
+    .ok()
+    .and_then(|mut parsed| parsed.remove("value"))
+    .unwrap_or_else(|| Value::String(raw.to_string()))
+}
@@ -0,0 +1,34 @@
+use btleplug::api::BDAddr;
+use mini_ambilight_bluetooth::capture::CaptureError;
+use mini_ambilight_bluetooth::gatt::ControlLookupError;
+use thiserror::Error;
+
+use mini_ambilight_bluetooth::light::LightError;
+
+/// Failures while setting up or driving the lights
+#[derive(Debug, Error)]
+pub enum AmbilightError {
+  #[error("No bluetooth adapter found, is bluetooth enabled?")]
+  NoAdapter,
+  #[error("adapter_index {index} is out of range, found {count} adapters, see --list-adapters")]
+  AdapterOutOfRange { index: usize, count: usize },
+  #[error("Scan ended without finding the light {0}")]
+  LightNotFound(BDAddr),
+  #[error(
+    "Light {mac} not found within {secs}s; seen devices: {seen}. Is it switched on? \
+     See --list-devices"
+  )]
+  ScanTimeout {
+    mac: BDAddr,
+    secs: u64,
+    seen: String,
+  },
+  #[error(transparent)]
+  ControlLookup(#[from] ControlLookupError),
+  #[error("Capturing error: {0}")]
+  CaptureFailed(CaptureError),
+  #[error(transparent)]
+  BleWrite(#[from] LightError),
+  #[error("Bluetooth error: {0}")]
+  Ble(#[from] btleplug::Error),
+}
@@ -0,0 +1,75 @@
+use std::error::Error;
+
+use glam::Vec3;
+
+use crate::capture::Bgra8;
+use crate::correction::{FadeConfig, Pipeline, Smoother};
+use crate::sampling::Sampler;
+
+/// Everything turning a sampled color into the color shown, see the matching fields of
+/// `config::Config`
+pub struct ColorConfig {
+  pub pipeline: Pipeline,
+  pub fade: FadeConfig,
+}
+
+/// The color path of one light, carried from frame to frame
+#[derive(Debug, Clone)]
+pub struct LightColor {
+  /// Last sampled color, held while the screen stands still
+  pub sampled: Vec3,
+  /// Output of the color correction, before smoothing and fading
+  pub corrected: Vec3,
+  /// Color shown after the last step, RGB in 0...1
+  pub shown: Vec3,
+  pub smoother: Smoother,
+}
+
+impl LightColor {
+  /// Starting from black, smoothing over `smoothing_frames`
+  pub fn new(smoothing_frames: usize) -> LightColor {
+    LightColor {
+      sampled: Vec3::ZERO,
+      corrected: Vec3::ZERO,
+      shown: Vec3::ZERO,
+      smoother: Smoother::new(smoothing_frames),
+    }
+  }
+
+  /// Correct the sampled color and smooth it, then fade the shown color towards it keeping the
+  /// `keep` share. Jumps straight to it instead when `snap` is set or the fade snaps, which also
+  /// restarts the smoothing. Returns whether it jumped.
+  pub fn step(&mut self, config: &ColorConfig, keep: f32, snap: bool) -> bool {
+    let target = config.pipeline.apply(self.sampled);
+    self.corrected = target;
+    let snap = snap || config.fade.snaps(self.shown, target);
+    if snap {
+      self.smoother.reset();
+    }
+    let target = self.smoother.push(target);
+    self.shown = if snap {
+      target
+    } else {
+      config.fade.step(self.shown, target, keep)
+    };
+    snap
+  }
+}
+
+/// Sample the frame and take the color step of [`LightColor::step`], the whole way from a frame
+/// to the color shown. Returns the shown color, RGB in 0...1.
+///
+/// The main loop samples each monitor with a sampler of its own and steps the blended color, so
+/// it calls [`LightColor::step`] directly.
+pub fn compute_color(
+  buffer: &[Bgra8],
+  (width, height): (usize, usize),
+  sampler: &mut Sampler,
+  light: &mut LightColor,
+  keep: f32,
+  config: &ColorConfig,
+) -> Result<Vec3, Box<dyn Error>> {
+  light.sampled = sampler.sample(buffer, width, height)?;
+  light.step(config, keep, false);
+  Ok(light.shown)
+}
@@ -0,0 +1,98 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::capture::Bgra8;
+
+/// Detects whether the screen changed between two frames.
+///
+/// Both frames are sampled on a sparse `density` x `density` grid. A sample counts as changed when
+/// any channel moved by more than `pixel_delta`, and the frame counts as changed once at least
+/// `min_changed` samples did.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FrameDiff {
+  pub density: usize,
+  pub pixel_delta: u8,
+  pub min_changed: usize,
+}
+
+impl FrameDiff {
+  /// Sample the frame on the grid
+  pub fn sample(&self, buffer: &[Bgra8], width: usize, height: usize) -> Vec<[u8; 3]> {
+    let density = self.density.max(1);
+    let mut samples = Vec::with_capacity(density * density);
+    for y in 0..density {
+      for x in 0..density {
+        let px = (x * width + width / 2) / density;
+        let py = (y * height + height / 2) / density;
+        if let Some(pixel) = buffer.get(px + py * width) {
+          samples.push([pixel.r, pixel.g, pixel.b]);
+        }
+      }
+    }
+    samples
+  }
+
+  /// Number of samples that changed by more than the per-pixel delta
+  pub fn changed_samples(&self, previous: &[[u8; 3]], current: &[[u8; 3]]) -> usize {
+    if previous.len() != current.len() {
+      return current.len().max(self.min_changed);
+    }
+    previous
+      .iter()
+      .zip(current)
+      .filter(|(a, b)| (0..3).any(|c| a[c].abs_diff(b[c]) > self.pixel_delta))
+      .count()
+  }
+
+  /// Fraction of samples that changed, a cheap motion measure in 0...1
+  pub fn motion(&self, previous: &[[u8; 3]], current: &[[u8; 3]]) -> f32 {
+    (self.changed_samples(previous, current) as f32 / current.len().max(1) as f32).min(1.0)
+  }
+
+  /// Whether enough samples changed for the frame to count as new
+  pub fn changed(&self, previous: &[[u8; 3]], current: &[[u8; 3]]) -> bool {
+    self.changed_samples(previous, current) >= self.min_changed.max(1)
+  }
+}
+
+/// Sleeps longer and longer between frames while the screen stands still, so a static screen
+/// doesn't keep capturing at full rate.
+///
+/// Once more than `after_frames` frames in a row were unchanged, each further one adds `step` to
+/// the delay, up to `max`. The first changed frame drops it back to zero. A `max` of zero never
+/// backs off.
+#[derive(Debug, Clone)]
+pub struct IdleBackoff {
+  after_frames: u32,
+  step: Duration,
+  max: Duration,
+  static_frames: u32,
+}
+
+impl IdleBackoff {
+  pub fn new(after_frames: u32, step: Duration, max: Duration) -> IdleBackoff {
+    IdleBackoff {
+      after_frames,
+      step,
+      max,
+      static_frames: 0,
+    }
+  }
+
+  /// Count a captured frame, or a capture that timed out without a new one as unchanged
+  pub fn frame(&mut self, changed: bool) {
+    self.static_frames = if changed {
+      0
+    } else {
+      self.static_frames.saturating_add(1)
+    };
+  }
+
+  /// Extra sleep before capturing the next frame
+  pub fn delay(&self) -> Duration {
+    let ramp = self.static_frames.saturating_sub(self.after_frames);
+    self.step.saturating_mul(ramp).min(self.max)
+  }
+}
@@ -0,0 +1,87 @@
+use std::fmt::{self, Display};
+
+use thiserror::Error;
+use uuid::Uuid;
+
+/// What finding the control characteristic needs to know of a GATT characteristic
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CharacteristicInfo {
+  pub uuid: Uuid,
+  pub write: bool,
+  pub write_without_response: bool,
+}
+
+/// The characteristic colors are written to, as an index into the characteristics searched
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlCharacteristic {
+  /// The one with the configured UUID
+  Configured(usize),
+  /// The light lacks the configured UUID, this one is the most likely stand-in
+  Guessed(usize),
+}
+
+/// Why there is no characteristic to write colors to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum ControlLookupError {
+  #[error("Light exposes no characteristics at all, the connection is likely broken")]
+  NoCharacteristics,
+  #[error("Light has no characteristic {0}, check light_control_uuid")]
+  CharacteristicMissing(Uuid),
+}
+
+/// UUID of a 16 bit Bluetooth SIG assigned number
+pub const fn uuid_from_u16(short: u16) -> Uuid {
+  Uuid::from_u128(0x0000_0000_0000_1000_8000_0080_5f9b_34fb | ((short as u128) << 96))
+}
+
+/// Find the characteristic with `control_uuid`, or guess the color characteristic of a light
+/// clone exposing it under another UUID: a writable one from the `0xFFF0` family the usual
+/// firmware uses, otherwise any writable one, preferring write without response.
+pub fn find_control(
+  chars: &[CharacteristicInfo],
+  control_uuid: Uuid,
+) -> Result<ControlCharacteristic, ControlLookupError> {
+  if chars.is_empty() {
+    return Err(ControlLookupError::NoCharacteristics);
+  }
+  if let Some(index) = chars.iter().position(|c| c.uuid == control_uuid) {
+    return Ok(ControlCharacteristic::Configured(index));
+  }
+  let family = (0xFFF0..=0xFFFF).map(uuid_from_u16).collect::<Vec<_>>();
+  chars
+    .iter()
+    .enumerate()
+    .filter(|(_, c)| c.write || c.write_without_response)
+    .max_by_key(|(_, c)| (family.contains(&c.uuid), c.write_without_response))
+    .map(|(index, _)| ControlCharacteristic::Guessed(index))
+    .ok_or(ControlLookupError::CharacteristicMissing(control_uuid))
+}
+
+/// Strings of the standard GATT device information service, each `None` where the light lacks it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceInfo {
+  pub manufacturer: Option<String>,
+  pub model: Option<String>,
+  pub firmware: Option<String>,
+}
+
+impl DeviceInfo {
+  pub fn is_empty(&self) -> bool {
+    self.manufacturer.is_none() && self.model.is_none() && self.firmware.is_none()
+  }
+}
+
+impl Display for DeviceInfo {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let fields = [
+      ("manufacturer", &self.manufacturer),
+      ("model", &self.model),
+      ("firmware", &self.firmware),
+    ];
+    let known: Vec<String> = fields
+      .iter()
+      .filter_map(|(name, value)| value.as_ref().map(|value| format!("{} {}", name, value)))
+      .collect();
+    write!(f, "{}", known.join(", "))
+  }
+}
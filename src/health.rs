@@ -0,0 +1,167 @@
+use std::fmt::{self, Display};
+use std::time::Duration;
+
+use log::{info, warn};
+use serde::Deserialize;
+use tokio::time::Instant;
+
+use crate::light::{LightDriver, LightError};
+
+/// When a failing light is dropped and how often it is tried again,
+/// `{ max_failures = 3, retry_secs = 0.5, max_retry_secs = 30.0 }` in the config
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryPolicy {
+  /// Failed writes in a row before the light is dropped
+  pub max_failures: u32,
+  /// Wait before the first try to bring a dropped light back, doubled after every failed one
+  pub retry_secs: f32,
+  /// Longest wait between two tries
+  pub max_retry_secs: f32,
+}
+
+impl Default for RetryPolicy {
+  fn default() -> RetryPolicy {
+    RetryPolicy {
+      max_failures: 3,
+      retry_secs: 0.5,
+      max_retry_secs: 30.0,
+    }
+  }
+}
+
+impl RetryPolicy {
+  /// Wait before the try after `retries` failed ones
+  pub fn delay(&self, retries: u32) -> Duration {
+    let secs = self.retry_secs * 2f32.powi(retries.min(30) as i32);
+    Duration::from_secs_f32(secs.min(self.max_retry_secs))
+  }
+}
+
+/// Where a light stands, as shown in the status
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HealthState {
+  Healthy,
+  /// The last writes failed, but not enough of them to drop the light
+  Failing,
+  /// Left out of the writes until its next retry is due
+  Dropped,
+}
+
+impl Display for HealthState {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      HealthState::Healthy => write!(f, "healthy"),
+      HealthState::Failing => write!(f, "failing"),
+      HealthState::Dropped => write!(f, "dropped"),
+    }
+  }
+}
+
+/// Connection health of one light, following its writes
+#[derive(Debug, Clone)]
+pub struct LightHealth {
+  policy: RetryPolicy,
+  /// Failed writes since the last one that went through
+  failures: u32,
+  /// Failed tries since the light was dropped
+  retries: u32,
+  /// Set while dropped, on tokio's clock like the other timers
+  retry_at: Option<Instant>,
+}
+
+impl LightHealth {
+  pub fn new(policy: RetryPolicy) -> LightHealth {
+    LightHealth {
+      policy,
+      failures: 0,
+      retries: 0,
+      retry_at: None,
+    }
+  }
+
+  pub fn state(&self) -> HealthState {
+    match (self.retry_at, self.failures) {
+      (Some(_), _) => HealthState::Dropped,
+      (None, 0) => HealthState::Healthy,
+      (None, _) => HealthState::Failing,
+    }
+  }
+
+  /// Failed writes since the last one that went through
+  pub fn failures(&self) -> u32 {
+    self.failures
+  }
+
+  pub fn is_dropped(&self) -> bool {
+    self.retry_at.is_some()
+  }
+
+  /// Whether the light takes part in the next writes, always unless dropped with the retry not
+  /// yet due
+  pub fn is_active(&self) -> bool {
+    match self.retry_at {
+      Some(retry_at) => Instant::now() >= retry_at,
+      None => true,
+    }
+  }
+
+  pub fn record_success(&mut self) {
+    if self.is_dropped() {
+      info!("Light is back after {} failed writes", self.failures);
+    }
+    self.failures = 0;
+    self.retries = 0;
+    self.retry_at = None;
+  }
+
+  /// Count a failed write, dropping the light once `max_failures` are reached and pushing the
+  /// next retry further out for every failed one
+  pub fn record_failure(&mut self) {
+    self.failures += 1;
+    if self.is_dropped() {
+      self.retries += 1;
+    } else if self.failures < self.policy.max_failures {
+      return;
+    }
+    let delay = self.policy.delay(self.retries);
+    warn!(
+      "Dropping light after {} failed writes, trying again in {:?}",
+      self.failures, delay
+    );
+    self.retry_at = Some(Instant::now() + delay);
+  }
+
+  /// Drop the light right away, e.g. when it disconnected
+  pub fn mark_dropped(&mut self) {
+    self.retry_at = Some(Instant::now() + self.policy.delay(self.retries));
+  }
+}
+
+/// One light's part in a round of writes
+pub struct Write<'a> {
+  pub driver: &'a (dyn LightDriver + Send + Sync),
+  pub health: &'a mut LightHealth,
+  pub rgb: [u8; 3],
+  pub brightness: u8,
+}
+
+/// Send each light its color, leaving out the dropped ones until their retry is due, and record
+/// the outcome in their health. All writes go out at once, so a slow or failing light doesn't
+/// hold up the others. Returns the result of every light written, with the key it came with.
+pub async fn write_active<'a, K>(
+  writes: impl IntoIterator<Item = (K, Write<'a>)>,
+) -> Vec<(K, Result<(), LightError>)> {
+  let writes = writes
+    .into_iter()
+    .filter(|(_, write)| write.health.is_active())
+    .map(|(key, write)| async move {
+      let result = write.driver.set_color(write.rgb, write.brightness).await;
+      match result {
+        Ok(()) => write.health.record_success(),
+        Err(_) => write.health.record_failure(),
+      }
+      (key, result)
+    });
+  futures::future::join_all(writes).await
+}
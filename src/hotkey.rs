@@ -0,0 +1,137 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+/// Modifier bits as `RegisterHotKey` takes them
+const MOD_ALT: u32 = 0x0001;
+const MOD_CONTROL: u32 = 0x0002;
+const MOD_SHIFT: u32 = 0x0004;
+const MOD_WIN: u32 = 0x0008;
+
+/// A global key combination, `"ctrl+alt+p"` in the config. Modifiers are `ctrl`, `alt`, `shift`
+/// and `win`, the key a letter, a digit, `f1` to `f24`, `pause`, `scroll_lock` or `space`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Hotkey {
+  pub modifiers: u32,
+  /// Windows virtual key code
+  pub key: u32,
+  text: String,
+}
+
+impl FromStr for Hotkey {
+  type Err = String;
+
+  fn from_str(text: &str) -> Result<Hotkey, String> {
+    let mut modifiers = 0;
+    let mut key = None;
+    for part in text.split('+').map(|part| part.trim().to_lowercase()) {
+      let modifier = match part.as_str() {
+        "ctrl" | "control" => MOD_CONTROL,
+        "alt" => MOD_ALT,
+        "shift" => MOD_SHIFT,
+        "win" | "super" => MOD_WIN,
+        _ => 0,
+      };
+      if modifier != 0 {
+        modifiers |= modifier;
+        continue;
+      }
+      if key.is_some() {
+        return Err(format!("Hotkey `{}` has more than one key", text));
+      }
+      key = Some(
+        virtual_key(&part).ok_or_else(|| format!("Unknown key `{}` in hotkey `{}`", part, text))?,
+      );
+    }
+    Ok(Hotkey {
+      modifiers,
+      key: key.ok_or_else(|| format!("Hotkey `{}` has no key besides the modifiers", text))?,
+      text: text.to_string(),
+    })
+  }
+}
+
+impl TryFrom<String> for Hotkey {
+  type Error = String;
+
+  fn try_from(text: String) -> Result<Hotkey, String> {
+    text.parse()
+  }
+}
+
+impl fmt::Display for Hotkey {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(&self.text)
+  }
+}
+
+fn virtual_key(name: &str) -> Option<u32> {
+  let mut chars = name.chars();
+  match (chars.next(), chars.next()) {
+    // Letters and digits are their upper case ASCII code
+    (Some(c), None) if c.is_ascii_alphanumeric() => return Some(c.to_ascii_uppercase() as u32),
+    _ => {}
+  }
+  if let Some(number) = name.strip_prefix('f').and_then(|n| n.parse::<u32>().ok()) {
+    return (1..=24).contains(&number).then_some(0x70 + number - 1);
+  }
+  match name {
+    "pause" => Some(0x13),
+    "space" => Some(0x20),
+    "scroll_lock" => Some(0x91),
+    _ => None,
+  }
+}
+
+/// Call `on_press` every time the hotkey is pressed, from a thread of its own waiting for it.
+/// `action` says what the hotkey does, for the logs. Other platforms only log that the hotkey is
+/// unavailable.
+pub fn spawn(hotkey: Hotkey, action: &'static str, on_press: impl Fn() + Send + 'static) {
+  #[cfg(windows)]
+  std::thread::spawn(move || windows::listen(hotkey, action, on_press));
+  #[cfg(not(windows))]
+  {
+    let _ = on_press;
+    log::warn!(
+      "Hotkey {} to {} is only supported on Windows",
+      hotkey,
+      action
+    );
+  }
+}
+
+#[cfg(windows)]
+mod windows {
+  use std::ptr;
+
+  use log::{info, warn};
+  use winapi::um::errhandlingapi::GetLastError;
+  use winapi::um::winuser::{GetMessageW, RegisterHotKey, MOD_NOREPEAT, MSG, WM_HOTKEY};
+
+  use super::Hotkey;
+
+  const HOTKEY_ID: i32 = 1;
+
+  /// Register the hotkey for this thread and handle its presses until the process exits
+  pub fn listen(hotkey: Hotkey, action: &str, on_press: impl Fn()) {
+    let modifiers = hotkey.modifiers | MOD_NOREPEAT as u32;
+    // Without a window the presses arrive in the message queue of this thread.
+    if unsafe { RegisterHotKey(ptr::null_mut(), HOTKEY_ID, modifiers, hotkey.key) } == 0 {
+      warn!(
+        "Registering the hotkey {} failed, error {}, is another program using it?",
+        hotkey,
+        unsafe { GetLastError() }
+      );
+      return;
+    }
+    info!("Press {} to {}", hotkey, action);
+    let mut message: MSG = unsafe { std::mem::zeroed() };
+    while unsafe { GetMessageW(&mut message, ptr::null_mut(), 0, 0) } > 0 {
+      if message.message == WM_HOTKEY {
+        on_press();
+      }
+    }
+  }
+}
@@ -0,0 +1,52 @@
+use std::f32::consts::PI;
+use std::time::Duration;
+
+use palette::{Hsv, IntoColor, Srgb};
+use serde::Deserialize;
+
+/// What the lights show once the screen stood still for `idle_timeout_secs`,
+/// `{ type = "breathing", color = [255, 120, 40], period_secs = 6.0 }` in the config
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum IdleEffect {
+  /// Fade `color` slowly up and down, once every `period_secs`
+  Breathing { color: [u8; 3], period_secs: f32 },
+  /// Sweep through every hue at full saturation, once every `period_secs`
+  Rainbow { period_secs: f32 },
+}
+
+/// Lowest intensity of the breathing effect, so the light never goes fully dark
+const BREATHING_MIN: f32 = 0.1;
+
+impl IdleEffect {
+  /// Color of the effect `elapsed` after it started
+  pub fn color(&self, elapsed: Duration) -> [u8; 3] {
+    match *self {
+      IdleEffect::Breathing { color, period_secs } => {
+        let phase = phase(elapsed, period_secs);
+        // Raised cosine, starts at the bottom and peaks halfway through the period
+        let intensity =
+          BREATHING_MIN + (1.0 - BREATHING_MIN) * (0.5 - 0.5 * (2.0 * PI * phase).cos());
+        color.map(|c| (c as f32 * intensity).round() as u8)
+      }
+      IdleEffect::Rainbow { period_secs } => {
+        let rgb: Srgb = Hsv::new(phase(elapsed, period_secs) * 360.0, 1.0, 1.0).into_color();
+        let rgb = rgb.into_format::<u8>();
+        [rgb.red, rgb.green, rgb.blue]
+      }
+    }
+  }
+
+  pub fn period_secs(&self) -> f32 {
+    match *self {
+      IdleEffect::Breathing { period_secs, .. } | IdleEffect::Rainbow { period_secs } => {
+        period_secs
+      }
+    }
+  }
+}
+
+/// Position within the current period, 0...1
+fn phase(elapsed: Duration, period_secs: f32) -> f32 {
+  (elapsed.as_secs_f32() / period_secs).fract()
+}
@@ -0,0 +1,86 @@
+use glam::Vec3;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// Fixed seed of the k-means++ initialization, so the same frame always gives the same color
+const SEED: u64 = 0x5EED;
+
+/// Cluster the points into `k` groups and return the centroid of the largest one.
+///
+/// The initial centroids are picked with k-means++ from a fixed seed, then refined by at most
+/// `iterations` rounds of Lloyd's algorithm, stopping early once no centroid moves.
+pub fn largest_cluster(points: &[Vec3], k: usize, iterations: usize) -> Option<Vec3> {
+  if points.is_empty() {
+    return None;
+  }
+  let mut rng = StdRng::seed_from_u64(SEED);
+  let mut centroids = seed_centroids(points, k.max(1), &mut rng);
+
+  for _ in 0..iterations.max(1) {
+    let mut sums = vec![(Vec3::ZERO, 0); centroids.len()];
+    for point in points {
+      let sum = &mut sums[nearest(&centroids, *point)];
+      sum.0 += *point;
+      sum.1 += 1;
+    }
+    let mut moved = false;
+    for (centroid, (sum, count)) in centroids.iter_mut().zip(sums) {
+      if count > 0 {
+        let mean = sum / count as f32;
+        moved |= mean != *centroid;
+        *centroid = mean;
+      }
+    }
+    if !moved {
+      break;
+    }
+  }
+
+  let mut counts = vec![0; centroids.len()];
+  for point in points {
+    counts[nearest(&centroids, *point)] += 1;
+  }
+  let largest = (0..centroids.len()).max_by_key(|&index| counts[index])?;
+  Some(centroids[largest])
+}
+
+/// k-means++: every further centroid is drawn with probability proportional to its squared
+/// distance from the closest centroid picked so far.
+fn seed_centroids(points: &[Vec3], k: usize, rng: &mut StdRng) -> Vec<Vec3> {
+  let first = points[rng.gen_range(0..points.len())];
+  let mut centroids = vec![first];
+  let mut distances = points
+    .iter()
+    .map(|point| point.distance_squared(first))
+    .collect::<Vec<_>>();
+  while centroids.len() < k {
+    let total = distances.iter().sum::<f32>();
+    // Fewer distinct points than clusters
+    if total <= 0.0 {
+      break;
+    }
+    let mut target = rng.gen::<f32>() * total;
+    let index = distances
+      .iter()
+      .position(|distance| {
+        target -= distance;
+        target <= 0.0
+      })
+      .unwrap_or(points.len() - 1);
+    let centroid = points[index];
+    centroids.push(centroid);
+    for (distance, point) in distances.iter_mut().zip(points) {
+      *distance = distance.min(point.distance_squared(centroid));
+    }
+  }
+  centroids
+}
+
+fn nearest(centroids: &[Vec3], point: Vec3) -> usize {
+  centroids
+    .iter()
+    .map(|centroid| centroid.distance_squared(point))
+    .enumerate()
+    .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal))
+    .map(|(index, _)| index)
+    .unwrap_or(0)
+}
@@ -0,0 +1,107 @@
+use std::borrow::Cow;
+use std::ops::Range;
+
+use serde::Deserialize;
+
+use crate::capture::Bgra8;
+
+/// Pixel stride along a row or column while measuring its luma.
+const SCAN_STEP: usize = 4;
+
+/// Detects black bars at the frame edges, e.g. of 2.39:1 films on a 16:9 screen, so they don't
+/// drag the sampled color towards black.
+///
+/// Rows and columns are scanned from each edge inward until one has a mean luma above
+/// `threshold` (0...255). Everything outside of the first bright row and column is cropped.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Letterbox {
+  pub enabled: bool,
+  pub threshold: u8,
+}
+
+impl Default for Letterbox {
+  fn default() -> Letterbox {
+    Letterbox {
+      enabled: false,
+      threshold: 16,
+    }
+  }
+}
+
+impl Letterbox {
+  /// Content area of the frame as `(x, y, width, height)`, the whole frame when there are no bars,
+  /// detection is disabled or the frame is black throughout.
+  pub fn detect(
+    &self,
+    buffer: &[Bgra8],
+    width: usize,
+    height: usize,
+  ) -> (usize, usize, usize, usize) {
+    let whole = (0, 0, width, height);
+    if !self.enabled || width == 0 || height == 0 || buffer.len() < width * height {
+      return whole;
+    }
+    let threshold = self.threshold as f32;
+    let bright_row = |y: usize| row_luma(buffer, width, y) > threshold;
+
+    let top = match (0..height).find(|&y| bright_row(y)) {
+      Some(top) => top,
+      None => return whole,
+    };
+    let bottom = (top..height).rev().find(|&y| bright_row(y)).unwrap_or(top) + 1;
+    // Columns only within the rows kept, so horizontal bars don't darken them.
+    let bright_column = |x: usize| column_luma(buffer, width, top..bottom, x) > threshold;
+    let left = (0..width).find(|&x| bright_column(x)).unwrap_or(0);
+    let right = (left..width)
+      .rev()
+      .find(|&x| bright_column(x))
+      .unwrap_or(left)
+      + 1;
+    (left, top, right - left, bottom - top)
+  }
+
+  /// Cut the detected content area out of the frame, returns the pixels with their width and
+  /// height. The frame is borrowed without copying when nothing is cropped.
+  pub fn crop<'a>(
+    &self,
+    buffer: &'a [Bgra8],
+    width: usize,
+    height: usize,
+  ) -> (Cow<'a, [Bgra8]>, usize, usize) {
+    let (x, y, content_width, content_height) = self.detect(buffer, width, height);
+    if content_width == width && content_height == height {
+      return (Cow::Borrowed(buffer), width, height);
+    }
+
+    let mut pixels = Vec::with_capacity(content_width * content_height);
+    for row in y..y + content_height {
+      let start = row * width + x;
+      pixels.extend_from_slice(&buffer[start..start + content_width]);
+    }
+    (Cow::Owned(pixels), content_width, content_height)
+  }
+}
+
+/// Rec. 709 luma of a pixel, 0...255
+fn luma(pixel: &Bgra8) -> f32 {
+  0.2126 * pixel.r as f32 + 0.7152 * pixel.g as f32 + 0.0722 * pixel.b as f32
+}
+
+fn row_luma(buffer: &[Bgra8], width: usize, y: usize) -> f32 {
+  let row = &buffer[y * width..(y + 1) * width];
+  mean(row.iter().step_by(SCAN_STEP).map(luma))
+}
+
+fn column_luma(buffer: &[Bgra8], width: usize, rows: Range<usize>, x: usize) -> f32 {
+  mean(
+    rows
+      .step_by(SCAN_STEP)
+      .map(|y| luma(&buffer[x + y * width])),
+  )
+}
+
+fn mean(values: impl Iterator<Item = f32>) -> f32 {
+  let (sum, count) = values.fold((0.0, 0), |(sum, count), value| (sum + value, count + 1));
+  sum / count.max(1) as f32
+}
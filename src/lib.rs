@@ -0,0 +1,32 @@
+//! Screen color sampling and correction behind the ambilight binary, usable on its own.
+//!
+//! [`Sampler`] turns a captured BGRA frame into a single color with one of the
+//! [`ColorSamplingAlgorithm`]s, [`correction::Pipeline`] corrects it. The palette extraction of the
+//! vibrancy algorithm is available directly through [`Palette`] and [`Vibrancy`], and
+//! [`compute_color`] takes a frame all the way to the color shown.
+
+pub mod audio;
+pub mod capture;
+pub mod correction;
+pub mod dominant;
+pub mod env_config;
+pub mod frame_color;
+pub mod frame_diff;
+pub mod gatt;
+pub mod health;
+pub mod kmeans;
+pub mod letterbox;
+pub mod light;
+pub mod metrics;
+pub mod named;
+pub mod panic_hook;
+pub mod profile;
+pub mod sampling;
+mod srgb;
+pub mod status;
+pub mod vibrant;
+
+pub use frame_color::{compute_color, ColorConfig, LightColor};
+pub use named::nearest_named_color;
+pub use sampling::{ColorSamplingAlgorithm, Sampler, SamplingConfig, Zone};
+pub use vibrant::{Palette, Vibrancy, VibrancyConfig};
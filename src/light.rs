@@ -0,0 +1,507 @@
+use std::fmt::{self, Display};
+use std::net::UdpSocket;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use btleplug::api::{BDAddr, Characteristic, Peripheral as _, WriteType};
+use btleplug::platform::Peripheral;
+use log::warn;
+use serde::de::{self, Deserializer};
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::capture::CaptureTarget;
+use crate::health::RetryPolicy;
+use crate::metrics::{Stage, METRICS};
+use crate::sampling::{ColorSamplingAlgorithm, Zone};
+
+/// Brightness byte the light has always been driven with
+pub const FULL_BRIGHTNESS: u8 = 0x64;
+
+/// What the light shows once the program exits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExitAction {
+  /// Zero brightness
+  Off,
+  /// Fade from the last color to black
+  Black,
+  /// Keep the last color
+  Leave,
+}
+
+/// Where a light's colors are sent, `{ type = "wled", addr = "192.168.1.50:21324", leds = 60 }`
+/// in the config
+#[derive(Debug, Clone, PartialEq, Eq, Default, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum LightOutput {
+  /// The BLE light at `mac`
+  #[default]
+  Ble,
+  /// A WLED controller, filling `leds` LEDs from index `start` through the UDP realtime protocol
+  Wled {
+    addr: String,
+    #[serde(default)]
+    start: u16,
+    leds: u16,
+  },
+  /// The OpenRGB server of the `[openrgb]` table, which adds this light itself
+  #[serde(skip)]
+  OpenRgb,
+}
+
+/// One light of a multi-light setup, `{ mac = "..", zone = { x = 0.0, y = 0.0, w = 0.5, h = 1.0 } }`.
+/// `mac` is only needed for BLE lights.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LightConfig {
+  #[serde(default, deserialize_with = "mac_from_str")]
+  pub mac: BDAddr,
+  #[serde(default)]
+  pub match_name: Option<String>,
+  #[serde(default)]
+  pub zone: Zone,
+  #[serde(default)]
+  pub output: LightOutput,
+  /// Capture device this light follows alone, instead of the average of `capture_devices`,
+  /// written the same as there
+  #[serde(default)]
+  pub monitor: Option<CaptureTarget>,
+  /// Sampling algorithm of this light instead of `color_algorithm`
+  #[serde(default)]
+  pub algorithm: Option<ColorSamplingAlgorithm>,
+  /// Command layout of this light instead of `light_protocol`
+  #[serde(default)]
+  pub protocol: Option<LightProtocol>,
+  /// When this light is dropped and tried again, instead of `light_retry`
+  #[serde(default)]
+  pub retry: Option<RetryPolicy>,
+}
+
+impl LightConfig {
+  /// Whether `other` drives the same device, whatever zone or sampling it uses
+  pub fn same_device(&self, other: &LightConfig) -> bool {
+    self.output == other.output
+      && (self.output != LightOutput::Ble
+        || (self.mac == other.mac && self.match_name == other.match_name))
+  }
+}
+
+impl Display for LightConfig {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match &self.output {
+      LightOutput::Ble => write!(f, "{}", self.mac),
+      LightOutput::Wled { addr, .. } => write!(f, "WLED {}", addr),
+      LightOutput::OpenRgb => write!(f, "OpenRGB"),
+    }
+  }
+}
+
+fn mac_from_str<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BDAddr, D::Error> {
+  let value = String::deserialize(deserializer)?;
+  value.parse().map_err(de::Error::custom)
+}
+
+/// Byte layout of the color command, `{ type = "rgbw", white = "extract" }` in the config
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum LightProtocol {
+  /// `[0x01, r, g, b, brightness]`
+  Rgb,
+  /// `[0x01, r, g, b, w, brightness]` for lights with a white LED
+  Rgbw { white: WhiteMode },
+  /// Any other firmware, e.g. `["0x56", "{r}", "{g}", "{b}", "0x00", "0xf0", "0xaa"]`
+  Custom {
+    command_template: Vec<TemplateToken>,
+  },
+}
+
+/// One byte of a custom command template: a literal in hex (`0x56`) or decimal, or one of the
+/// placeholders `{r}`, `{g}`, `{b}`, `{brightness}` and `{checksum}`, the low byte of the sum of
+/// all bytes before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum TemplateToken {
+  Byte(u8),
+  Red,
+  Green,
+  Blue,
+  Brightness,
+  Checksum,
+}
+
+impl TryFrom<String> for TemplateToken {
+  type Error = String;
+
+  fn try_from(token: String) -> Result<TemplateToken, String> {
+    let token = token.trim();
+    let parsed = match token {
+      "{r}" => Some(TemplateToken::Red),
+      "{g}" => Some(TemplateToken::Green),
+      "{b}" => Some(TemplateToken::Blue),
+      "{brightness}" => Some(TemplateToken::Brightness),
+      "{checksum}" => Some(TemplateToken::Checksum),
+      _ => match token
+        .strip_prefix("0x")
+        .or_else(|| token.strip_prefix("0X"))
+      {
+        Some(hex) => u8::from_str_radix(hex, 16).ok(),
+        None => token.parse().ok(),
+      }
+      .map(TemplateToken::Byte),
+    };
+    parsed.ok_or_else(|| {
+      format!(
+        "Invalid command template token {:?}, expected a byte like 0x56 or one of {{r}}, {{g}}, \
+         {{b}}, {{brightness}}, {{checksum}}",
+        token
+      )
+    })
+  }
+}
+
+/// How the white channel of an RGBW light is derived from the color
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WhiteMode {
+  /// `w = min(r, g, b)`, subtracted from the color so the white LED carries the gray part
+  Extract,
+  /// `w = min(r, g, b)` on top of the unchanged color, brighter but less saturated
+  Add,
+  /// Leave the white LED off
+  Off,
+}
+
+impl LightProtocol {
+  /// The command setting the light to `rgb` at `brightness` percent
+  pub fn command(&self, rgb: [u8; 3], brightness: u8) -> Vec<u8> {
+    match self {
+      LightProtocol::Rgb => vec![0x01, rgb[0], rgb[1], rgb[2], brightness],
+      LightProtocol::Rgbw { white } => {
+        let w = rgb.into_iter().min().unwrap_or(0);
+        let (rgb, w) = match white {
+          WhiteMode::Extract => (rgb.map(|c| c - w), w),
+          WhiteMode::Add => (rgb, w),
+          WhiteMode::Off => (rgb, 0),
+        };
+        vec![0x01, rgb[0], rgb[1], rgb[2], w, brightness]
+      }
+      LightProtocol::Custom { command_template } => {
+        let mut command = Vec::with_capacity(command_template.len());
+        for token in command_template {
+          let byte = match token {
+            TemplateToken::Byte(byte) => *byte,
+            TemplateToken::Red => rgb[0],
+            TemplateToken::Green => rgb[1],
+            TemplateToken::Blue => rgb[2],
+            TemplateToken::Brightness => brightness,
+            TemplateToken::Checksum => command
+              .iter()
+              .fold(0u8, |sum, byte| sum.wrapping_add(*byte)),
+          };
+          command.push(byte);
+        }
+        command
+      }
+    }
+  }
+}
+
+#[derive(Debug, Error)]
+pub enum LightError {
+  #[error("Writing to the light failed: {0}")]
+  Ble(#[from] btleplug::Error),
+  #[error("Sending to WLED failed: {0}")]
+  Udp(#[from] std::io::Error),
+  #[error("Sending to OpenRGB failed: {0}")]
+  OpenRgb(std::io::Error),
+}
+
+/// Output the final color goes to
+#[async_trait]
+pub trait LightDriver {
+  async fn set_color(&self, rgb: [u8; 3], brightness: u8) -> Result<(), LightError>;
+
+  /// Wait until colors held back by the driver are written
+  async fn flush(&self) -> Result<(), LightError> {
+    Ok(())
+  }
+}
+
+/// Delay before the first retry of a failed write, doubled for every further one
+const WRITE_RETRY_DELAY: Duration = Duration::from_millis(20);
+
+/// How BLE writes are sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WriteOptions {
+  /// Wait for the light to acknowledge each write, slower but failures are never silent
+  pub with_response: bool,
+  /// Tries per write, including the first, before the light counts as disconnected
+  pub attempts: u32,
+}
+
+/// Where a [`BleLightDriver`] sends its commands, the control characteristic of a connected
+/// light outside of tests
+#[async_trait]
+pub trait CommandWriter {
+  async fn write_command(
+    &self,
+    command: &[u8],
+    write_type: WriteType,
+  ) -> Result<(), btleplug::Error>;
+}
+
+#[async_trait]
+impl CommandWriter for (Peripheral, Characteristic) {
+  async fn write_command(
+    &self,
+    command: &[u8],
+    write_type: WriteType,
+  ) -> Result<(), btleplug::Error> {
+    self.0.write(&self.1, command, write_type).await
+  }
+}
+
+/// The BLE light, written through its control characteristic
+pub struct BleLightDriver<W = (Peripheral, Characteristic)> {
+  writer: W,
+  protocol: LightProtocol,
+  options: WriteOptions,
+}
+
+impl BleLightDriver {
+  pub fn new(
+    light: Peripheral,
+    cmd_char: Characteristic,
+    protocol: LightProtocol,
+    options: WriteOptions,
+  ) -> BleLightDriver {
+    BleLightDriver::with_writer((light, cmd_char), protocol, options)
+  }
+}
+
+impl<W: CommandWriter> BleLightDriver<W> {
+  pub fn with_writer(
+    writer: W,
+    protocol: LightProtocol,
+    options: WriteOptions,
+  ) -> BleLightDriver<W> {
+    BleLightDriver {
+      writer,
+      protocol,
+      options,
+    }
+  }
+}
+
+#[async_trait]
+impl<W: CommandWriter + Send + Sync> LightDriver for BleLightDriver<W> {
+  async fn set_color(&self, rgb: [u8; 3], brightness: u8) -> Result<(), LightError> {
+    let start = Instant::now();
+    let command = self.protocol.command(rgb, brightness);
+    METRICS.record(Stage::Encode, start.elapsed());
+    let write_type = if self.options.with_response {
+      WriteType::WithResponse
+    } else {
+      WriteType::WithoutResponse
+    };
+    let mut delay = WRITE_RETRY_DELAY;
+    let mut attempt = 1;
+    loop {
+      match self.writer.write_command(&command, write_type).await {
+        Ok(()) => return Ok(()),
+        Err(e) if attempt < self.options.attempts => {
+          warn!(
+            "Write {} of {} failed, retrying in {:?}: {}",
+            attempt, self.options.attempts, delay, e
+          );
+          tokio::time::sleep(delay).await;
+          delay *= 2;
+          attempt += 1;
+        }
+        Err(e) => return Err(e.into()),
+      }
+    }
+  }
+}
+
+/// Spaces the writes of a driver at least `interval` apart, however fast colors come in.
+///
+/// A color arriving sooner is held and written once the interval passed, replaced by any later
+/// color in the meantime, so only the latest one goes out. A held write failing is reported by
+/// the next call.
+pub struct RateLimitedDriver {
+  inner: Arc<dyn LightDriver + Send + Sync>,
+  interval: Duration,
+  state: Arc<Mutex<RateLimitState>>,
+}
+
+#[derive(Default)]
+struct RateLimitState {
+  /// On tokio's clock, like the sleeps of the flush task
+  last_write: Option<tokio::time::Instant>,
+  /// Latest held color and brightness
+  pending: Option<([u8; 3], u8)>,
+  /// Whether a task is waiting to write `pending`
+  flushing: bool,
+  error: Option<LightError>,
+}
+
+impl RateLimitedDriver {
+  pub fn new(inner: Arc<dyn LightDriver + Send + Sync>, interval: Duration) -> RateLimitedDriver {
+    RateLimitedDriver {
+      inner,
+      interval,
+      state: Arc::default(),
+    }
+  }
+
+  /// Write the held color after `wait`, and keep writing whatever arrives meanwhile one interval
+  /// apart until nothing is held anymore
+  fn spawn_flush(&self, wait: Duration) {
+    let inner = self.inner.clone();
+    let state = self.state.clone();
+    let interval = self.interval;
+    tokio::spawn(async move {
+      let mut wait = wait;
+      loop {
+        tokio::time::sleep(wait).await;
+        let (rgb, brightness) = {
+          let mut state = state.lock().unwrap();
+          match state.pending.take() {
+            Some(pending) => {
+              state.last_write = Some(tokio::time::Instant::now());
+              pending
+            }
+            None => {
+              state.flushing = false;
+              return;
+            }
+          }
+        };
+        if let Err(e) = inner.set_color(rgb, brightness).await {
+          state.lock().unwrap().error = Some(e);
+        }
+        wait = interval;
+      }
+    });
+  }
+}
+
+#[async_trait]
+impl LightDriver for RateLimitedDriver {
+  async fn set_color(&self, rgb: [u8; 3], brightness: u8) -> Result<(), LightError> {
+    {
+      let mut state = self.state.lock().unwrap();
+      if let Some(e) = state.error.take() {
+        return Err(e);
+      }
+      if state.flushing {
+        state.pending = Some((rgb, brightness));
+        return Ok(());
+      }
+      let wait = state
+        .last_write
+        .and_then(|last| self.interval.checked_sub(last.elapsed()));
+      if let Some(wait) = wait {
+        state.pending = Some((rgb, brightness));
+        state.flushing = true;
+        drop(state);
+        self.spawn_flush(wait);
+        return Ok(());
+      }
+      state.last_write = Some(tokio::time::Instant::now());
+    }
+    self.inner.set_color(rgb, brightness).await
+  }
+
+  async fn flush(&self) -> Result<(), LightError> {
+    loop {
+      {
+        let mut state = self.state.lock().unwrap();
+        if !state.flushing {
+          return match state.error.take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+          };
+        }
+      }
+      tokio::time::sleep(self.interval).await;
+    }
+  }
+}
+
+/// WLED packet types of the UDP realtime protocol
+const WLED_DRGB: u8 = 2;
+const WLED_DNRGB: u8 = 4;
+/// Realtime timeout byte telling WLED to stay in realtime mode until the next packet, since
+/// unchanged colors aren't resent
+const WLED_NO_TIMEOUT: u8 = 255;
+/// Most LEDs a single DNRGB packet can carry
+pub const WLED_MAX_LEDS: u16 = 489;
+
+/// A range of LEDs on a WLED controller, all set to the same color
+pub struct WledLightDriver {
+  socket: UdpSocket,
+  start: u16,
+  leds: u16,
+}
+
+impl WledLightDriver {
+  pub fn new(addr: &str, start: u16, leds: u16) -> Result<WledLightDriver, LightError> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.connect(addr)?;
+    socket.set_nonblocking(true)?;
+    Ok(WledLightDriver {
+      socket,
+      start,
+      leds,
+    })
+  }
+
+  /// DRGB when the range starts at the first LED, DNRGB with the start index otherwise
+  fn packet(&self, rgb: [u8; 3]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(4 + 3 * self.leds as usize);
+    if self.start == 0 {
+      packet.extend_from_slice(&[WLED_DRGB, WLED_NO_TIMEOUT]);
+    } else {
+      packet.extend_from_slice(&[WLED_DNRGB, WLED_NO_TIMEOUT]);
+      packet.extend_from_slice(&self.start.to_be_bytes());
+    }
+    for _ in 0..self.leds {
+      packet.extend_from_slice(&rgb);
+    }
+    packet
+  }
+}
+
+#[async_trait]
+impl LightDriver for WledLightDriver {
+  async fn set_color(&self, rgb: [u8; 3], brightness: u8) -> Result<(), LightError> {
+    let start = Instant::now();
+    // The protocol has no brightness, so it is applied to the color itself.
+    let scale = brightness.min(FULL_BRIGHTNESS) as u16;
+    let rgb = rgb.map(|c| (c as u16 * scale / FULL_BRIGHTNESS as u16) as u8);
+    let packet = self.packet(rgb);
+    METRICS.record(Stage::Encode, start.elapsed());
+    // A datagram this small never blocks in practice. Should it, the write fails and the color
+    // is sent again next frame.
+    self.socket.send(&packet)?;
+    Ok(())
+  }
+}
+
+/// Discards every color, used by `--dry-run` to run without any light. Commands are still
+/// encoded so the metrics match a real run.
+pub struct NullLightDriver(pub LightProtocol);
+
+#[async_trait]
+impl LightDriver for NullLightDriver {
+  async fn set_color(&self, rgb: [u8; 3], brightness: u8) -> Result<(), LightError> {
+    let start = Instant::now();
+    let _ = self.0.command(rgb, brightness);
+    METRICS.record(Stage::Encode, start.elapsed());
+    Ok(())
+  }
+}
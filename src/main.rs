@@ -1,174 +1,1592 @@
 use btleplug::{
   api::{
-    bleuuid::uuid_from_u16, Central, CentralEvent, Manager as _, Peripheral as _, ScanFilter,
-    WriteType,
+    bleuuid::uuid_from_u16, Central, CentralEvent, CharPropFlags, Characteristic, Manager as _,
+    Peripheral as _, ScanFilter,
   },
-  platform::Manager,
+  platform::{Adapter, Manager, Peripheral},
 };
-use color_thief::get_palette;
-use dxgcap::DXGIManager;
-use futures::stream::StreamExt;
+use config::ColorSource;
+use error::AmbilightError;
+use futures::{stream::StreamExt, FutureExt};
 use glam::*;
+use mini_ambilight_bluetooth::light::{
+  self, BleLightDriver, ExitAction, LightDriver, LightOutput, NullLightDriver, RateLimitedDriver,
+  WledLightDriver, FULL_BRIGHTNESS,
+};
 
-use image::{
-  imageops::FilterType,
-  DynamicImage::{self, ImageRgb8},
-  ImageBuffer,
+use log::{debug, error, info, warn};
+use mini_ambilight_bluetooth::capture::{self, CaptureError, CaptureTarget};
+use mini_ambilight_bluetooth::gatt::{
+  self, CharacteristicInfo, ControlCharacteristic, ControlLookupError, DeviceInfo,
+};
+use mini_ambilight_bluetooth::health::{self, LightHealth};
+use mini_ambilight_bluetooth::metrics::{self, Stage, METRICS};
+use mini_ambilight_bluetooth::{
+  correction, nearest_named_color, sampling, vibrant, ColorConfig, LightColor,
 };
-use palette::{rgb::Rgb, FromColor, Hsl, IntoColor};
+use mini_ambilight_bluetooth::{profile, status};
+use startup::StartupEffect;
+use std::collections::BTreeSet;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
 use uuid::Uuid;
 
-mod vibrant;
+mod accuracy;
+mod audio_input;
+mod benchmark;
+mod config;
+mod error;
+mod hotkey;
+mod idle;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+#[cfg(feature = "openrgb")]
+mod openrgb;
+mod pacing;
+mod panic_guard;
+mod scan;
+mod schedule;
+mod startup;
+mod trace;
+
+/// Standard GATT battery level characteristic, only present on battery powered variants.
+const BATTERY_LEVEL_UUID: Uuid = uuid_from_u16(0x2A19);
+const BATTERY_POLL_INTERVAL: Duration = Duration::from_secs(60);
+/// Standard GATT device information strings
+const MANUFACTURER_NAME_UUID: Uuid = uuid_from_u16(0x2A29);
+const MODEL_NUMBER_UUID: Uuid = uuid_from_u16(0x2A24);
+const FIRMWARE_REVISION_UUID: Uuid = uuid_from_u16(0x2A26);
+
+const DISCOVERY_ATTEMPTS: usize = 3;
+const DISCOVERY_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Steps and duration of the fade to black when exiting with `on_exit = "black"`.
+const EXIT_FADE_STEPS: u32 = 10;
+const EXIT_FADE_DURATION: Duration = Duration::from_millis(500);
+
+/// How long a capture waits for the screen to change. With several monitors a still one must not
+/// hold up the others, so each only gets a short wait.
+const CAPTURE_TIMEOUT: Duration = Duration::from_secs(1000);
+const MULTI_MONITOR_CAPTURE_TIMEOUT: Duration = Duration::from_millis(10);
+
+/// How often the active schedule window is looked up
+const SCHEDULE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often the pause hotkey is checked for a resume while paused
+const PAUSE_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Frame rate of the audio mode when `max_fps` is 0, sound arrives continuously so nothing else
+/// paces the loop.
+const AUDIO_MAX_FPS: f32 = 60.0;
 
-const LIGHT_MAC: u64 = 0xFFFF3A00028F;
-const LIGHT_CONTROL_UUID: Uuid = uuid_from_u16(0xFFF1);
+/// Time between the colors of the startup effect.
+const STARTUP_EFFECT_STEP: Duration = Duration::from_millis(30);
 
-const CAPTURE_DEVICE: usize = 1;
+/// How often the number of writes skipped by the write deadzone is logged.
+const WRITE_SKIP_LOG_INTERVAL: Duration = Duration::from_secs(60);
 
-const COLOR_GAMMA: f32 = 1.0;
-const COLOR_FADE: f32 = 0.8;
-const COLOR_CORRECT_LIGHT: f32 = 0.9;
-const COLOR_CORRECT_SATURATION: f32 = 0.9;
-/*const COLOR_ALGORITHM: ColorSamplingAlgorithm = ColorSamplingAlgorithm::MostDominant {
-  quality: 2,
-  sorted: true,
-};*/
-const COLOR_ALGORITHM: ColorSamplingAlgorithm = ColorSamplingAlgorithm::Vibrancy;
+/// How long to keep scanning for the configured address after a device matched by name.
+const NAME_MATCH_GRACE: Duration = Duration::from_secs(3);
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-  println!("Starting up and initializing bluetooth connection to light");
-  println!("================================================");
-  let manager = Manager::new().await.unwrap();
-  let adapters = manager.adapters().await?;
-  let central = adapters.into_iter().next().unwrap();
-  let mut events = central.events().await.unwrap();
-  central.start_scan(ScanFilter::default()).await?;
+  env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+  let args = std::env::args().collect::<Vec<_>>();
+  // Shared with the reconnect tasks
+  let config = Arc::new(config::Config::load(&args)?);
+  let mut color_pipeline = config.pipeline()?;
+  if let Some(limits) = config.limits() {
+    color_pipeline = color_pipeline.then(Box::new(limits));
+  }
+  let color_config = ColorConfig {
+    pipeline: color_pipeline,
+    fade: config.fade_config(),
+  };
+  let mut calibration = config.calibration();
+  if let Some(index) = args.iter().position(|arg| arg == "--verify-accuracy") {
+    return accuracy::verify(
+      &args[index + 1..],
+      &color_config.pipeline,
+      config.sampling(),
+      config.color_rounding,
+      config.accuracy_tolerance,
+    );
+  }
 
-  println!("Waiting for bluetooth light to be discovered...");
-  let mut light = None;
-  while let Some(event) = events.next().await {
-    if let CentralEvent::DeviceDiscovered(id) = event {
-      let peripheral = central.peripheral(&id).await.unwrap();
-      if peripheral.address() == LIGHT_MAC.try_into().unwrap() {
-        println!("Found light at {:?}", id);
-        light = Some(peripheral);
-        break;
+  if args.iter().any(|arg| arg == "--list-adapters") {
+    let manager = Manager::new().await?;
+    return scan::list_adapters(&manager.adapters().await?).await;
+  }
+  if args.iter().any(|arg| arg == "--list-devices") {
+    let manager = Manager::new().await?;
+    let central = select_adapter(&manager, config.adapter_index).await?;
+    return scan::list_devices(&central, Duration::from_secs(config.list_devices_secs)).await;
+  }
+
+  let dry_run = args.iter().any(|arg| arg == "--dry-run");
+  if dry_run {
+    info!("Dry run, colors are only logged");
+  } else {
+    info!("Starting up and initializing bluetooth connection to light");
+  }
+  let panic_lights = panic_guard::SharedLights::default();
+  panic_guard::install(panic_lights.clone());
+  #[cfg(feature = "metrics")]
+  if let Some(addr) = &config.metrics_addr {
+    let listener = tokio::net::TcpListener::bind(addr.parse::<std::net::SocketAddr>()?)
+      .await
+      .map_err(|e| format!("Serving metrics on {} failed: {}", addr, e))?;
+    tokio::spawn(async move {
+      if let Err(e) = metrics::serve(listener).await {
+        error!("Metrics endpoint stopped: {}", e);
       }
+    });
+  }
+  #[cfg(not(feature = "metrics"))]
+  if config.metrics_addr.is_some() {
+    warn!("Metrics address configured but the `metrics` feature is disabled");
+  }
 
-      println!("Found unknown device at {:?}", id);
+  // Lights of the other profiles may be connected later on.
+  let profile_lights = config.profiles.values().flat_map(|profile| &profile.lights);
+  let needs_adapter = config
+    .lights()
+    .iter()
+    .chain(profile_lights)
+    .any(|light| light.output == LightOutput::Ble);
+  let central = if dry_run || !needs_adapter {
+    None
+  } else {
+    let manager = Manager::new().await?;
+    Some(select_adapter(&manager, config.adapter_index).await?)
+  };
+  // One pool for the samplers of every light and monitor
+  let pool = config.threads.and_then(|threads| {
+    match rayon::ThreadPoolBuilder::new().num_threads(threads).build() {
+      Ok(pool) => Some(Arc::new(pool)),
+      Err(e) => {
+        warn!(
+          "Creating the sampling thread pool failed, using the global one: {}",
+          e
+        );
+        None
+      }
     }
+  });
+  let mut lights = Vec::new();
+  for light_config in config.lights() {
+    let connected = match (&central, &light_config.output) {
+      (Some(central), LightOutput::Ble) => {
+        let mut connected = connect_light(central, &light_config, &config).await?;
+        if config.read_device_info {
+          match &connected.battery_char {
+            Some(battery_char) => {
+              connected.battery_level = read_battery_level(&connected.light, battery_char).await
+            }
+            None => info!("Light does not report a battery level"),
+          }
+          if connected.device_info.is_empty() {
+            info!("Light does not report device information");
+          } else {
+            info!("Light {}: {}", light_config, connected.device_info);
+          }
+        }
+        Some(connected)
+      }
+      _ => None,
+    };
+    lights.push(ZoneLight::new(
+      light_config,
+      connected,
+      &config,
+      pool.as_ref(),
+      dry_run,
+    )?);
   }
-  central.stop_scan().await?;
+  set_panic_lights(&panic_lights, &lights, &config);
+  let status = match &config.status_addr {
+    Some(addr) => {
+      let status = status::SharedStatus::default();
+      let listener = tokio::net::TcpListener::bind(addr.parse::<std::net::SocketAddr>()?)
+        .await
+        .map_err(|e| format!("Serving status on {} failed: {}", addr, e))?;
+      let served = status.clone();
+      tokio::spawn(async move {
+        if let Err(e) = status::serve(listener, served).await {
+          error!("Status endpoint stopped: {}", e);
+        }
+      });
+      Some(status)
+    }
+    None => None,
+  };
+  let mut events = match &central {
+    Some(central) => Some(central.events().await?),
+    None => None,
+  };
+  let (reconnected_sender, mut reconnected) = mpsc::unbounded_channel::<(usize, ConnectedLight)>();
 
-  let light = light.unwrap();
-  light.connect().await?;
-  light.discover_services().await?;
-  let chars = light.characteristics();
-  println!("Found characteristics in light: {:#?}", chars);
-  let cmd_char = chars.iter().find(|c| c.uuid == LIGHT_CONTROL_UUID).unwrap();
+  if config.boot_flash_enabled {
+    identify_lights(
+      &lights,
+      config.boot_flash_color,
+      Duration::from_millis(config.boot_flash_ms),
+    )
+    .await?;
+  }
+  if config.startup_effect != StartupEffect::Off && !dry_run {
+    play_startup_effect(
+      &lights,
+      config.startup_effect,
+      Duration::from_millis(config.startup_effect_ms),
+    )
+    .await?;
+  }
 
-  println!("Start capturing frames and set light");
-  let mut dxgi = DXGIManager::new(1000000)?;
-  dxgi.set_capture_source_index(CAPTURE_DEVICE);
-  //dxgi.acquire_output_duplication().unwrap();
+  let audio = match config.source {
+    ColorSource::Audio => match audio_input::AudioInput::open() {
+      Ok(audio) => Some(audio),
+      Err(e) => {
+        warn!("No audio input, capturing the screen instead: {}", e);
+        None
+      }
+    },
+    ColorSource::Screen => None,
+  };
+  let mut monitors = Vec::new();
+  // One per monitor, finding the warm and cool tone of the whole frame for all dual tone lights
+  let mut tone_samplers = Vec::new();
+  match audio {
+    Some(_) => info!("Start following the audio and set light"),
+    None => {
+      info!("Start capturing frames and set light");
+      let devices = config.monitors();
+      let timeout = if devices.len() > 1 {
+        MULTI_MONITOR_CAPTURE_TIMEOUT
+      } else {
+        CAPTURE_TIMEOUT
+      };
+      // Only list the outputs when one is selected by name or resolution
+      let outputs = if devices
+        .iter()
+        .any(|(target, _)| !matches!(target, CaptureTarget::Index(_)))
+      {
+        capture::outputs().map_err(AmbilightError::CaptureFailed)?
+      } else {
+        Vec::new()
+      };
+      for (target, weight) in devices {
+        let index = target
+          .resolve(&outputs)
+          .map_err(AmbilightError::CaptureFailed)?;
+        if let CaptureTarget::Name(_) = target {
+          info!("Capturing {} from output {}", target, index);
+        }
+        monitors.push(Monitor {
+          target,
+          weight,
+          capture: capture::open(index, timeout).map_err(AmbilightError::CaptureFailed)?,
+          frame: None,
+        });
+        tone_samplers.push(match &pool {
+          Some(pool) => sampling::Sampler::new(config.sampling()).with_pool(pool.clone()),
+          None => sampling::Sampler::new(config.sampling()),
+        });
+      }
+    }
+  }
 
-  let mut previous_pixel = Vec3::ZERO;
+  let mut previous_frame: Option<Vec<[u8; 3]>> = None;
+  #[cfg(not(feature = "openrgb"))]
+  if config.openrgb.is_some() {
+    warn!("openrgb is configured, but this build lacks the openrgb feature");
+  }
+  #[cfg(feature = "mqtt")]
+  let mut mqtt = config.mqtt.as_ref().map(mqtt::MqttOutput::new);
+  #[cfg(not(feature = "mqtt"))]
+  if config.mqtt.is_some() {
+    warn!("mqtt is configured, but this build lacks the mqtt feature");
+  }
+  let trace = match &config.trace {
+    Some(trace) => Some(trace::TraceRecorder::new(trace).await?),
+    None => None,
+  };
+  let mut fps_window = (Instant::now(), 0);
+  let mut battery_polled = Instant::now();
+  let mut skip_window = (Instant::now(), 0, 0);
+  let mut benchmark = args
+    .iter()
+    .any(|arg| arg == "--benchmark-pipeline")
+    .then(|| benchmark::PipelineBenchmark::new(config.benchmark_frames));
+  if benchmark.is_some() {
+    info!(
+      "Benchmarking pipeline over {} frames",
+      config.benchmark_frames
+    );
+  }
+  // Delay keeps the frame budget after a slow frame instead of bursting to catch up.
+  let max_fps = match &audio {
+    Some(_) if config.max_fps == 0.0 => AUDIO_MAX_FPS,
+    _ => config.max_fps,
+  };
+  let mut pacer = (max_fps > 0.0).then(|| {
+    let mut pacer = tokio::time::interval(Duration::from_secs_f32(1.0 / max_fps));
+    pacer.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    pacer
+  });
+  let (write_outcomes_sender, mut write_outcomes) = mpsc::unbounded_channel();
+  let mut paced = (config.output_rate_hz > 0.0).then(|| {
+    let (targets, receiver) = tokio::sync::watch::channel(Vec::new());
+    let task = pacing::spawn(
+      config.output_rate_hz,
+      config.keep_alive(),
+      receiver,
+      write_outcomes_sender,
+    );
+    (targets, task)
+  });
+  let shutdown = tokio::signal::ctrl_c();
+  tokio::pin!(shutdown);
+  let mut frame_start = Instant::now();
+  let mut capture_failures = 0;
+  let mut black_frames = 0;
+  let mut last_change = Instant::now();
+  let mut idle = false;
+  let idle_timeout = config.idle_timeout();
+  let mut backoff = config.idle_backoff();
+  let paused = Arc::new(AtomicBool::new(false));
+  if let Some(hotkey) = &config.pause_hotkey {
+    let paused = paused.clone();
+    hotkey::spawn(hotkey.clone(), "pause or resume", move || {
+      paused.fetch_xor(true, Ordering::SeqCst);
+    });
+  }
+  let mut was_paused = false;
+  let profile_presses = Arc::new(AtomicUsize::new(0));
+  if let Some(hotkey) = &config.profile_hotkey {
+    let presses = profile_presses.clone();
+    hotkey::spawn(hotkey.clone(), "switch profiles", move || {
+      presses.fetch_add(1, Ordering::SeqCst);
+    });
+  }
+  let mut profile_switches = 0;
+  let mut active_profile = config.profile.clone();
+  let mut snap_next = false;
+  let mut window: Option<schedule::ScheduleWindow> = None;
+  let mut schedule_checked: Option<Instant> = None;
   loop {
-    let (buffer, (width, height)) = dxgi
-      .capture_frame()
-      .map_err(|e| format!("Capturing error: {:?}", e))?;
-
-    let color = match COLOR_ALGORITHM {
-      ColorSamplingAlgorithm::SquaredAverage { sample_rate } => {
-        let sample_width = (width as f32 * sample_rate) as usize;
-        let step_x = width / sample_width;
-        let sample_height = (width as f32 * sample_rate) as usize;
-        let step_y = height / sample_height;
-        let mut sampled_color = Vec3::ZERO;
-        let mut samples = 0;
-        for x in 0..sample_width {
-          for y in 0..sample_height {
-            let i = (x * step_x) + width * (y * step_y);
-            let bgra = buffer[i];
-            sampled_color += Vec3::new(
-              (bgra.r as f32).powf(2.0),
-              (bgra.g as f32).powf(2.0),
-              (bgra.b as f32).powf(2.0),
-            );
-            samples += 1;
-          }
+    if (&mut shutdown).now_or_never().is_some() {
+      break;
+    }
+    let check_schedule = !config.schedule.is_empty()
+      && schedule_checked.is_none_or(|checked| checked.elapsed() >= SCHEDULE_CHECK_INTERVAL);
+    if check_schedule {
+      schedule_checked = Some(Instant::now());
+      let active = schedule::active(&config.schedule, chrono::Local::now().time()).cloned();
+      if active != window {
+        match &active {
+          Some(active) => info!(
+            "Entering the schedule window {} to {}",
+            active.from.format("%H:%M"),
+            active.to.format("%H:%M")
+          ),
+          None => info!("Leaving the schedule window"),
+        }
+        calibration = scheduled_calibration(&config, active.as_ref());
+        if active.as_ref().is_some_and(|active| !active.enabled) {
+          switch_off(
+            &mut lights,
+            paced.as_ref().map(|(targets, _)| targets),
+            calibration,
+          )
+          .await;
         }
+        window = active;
+      }
+    }
+    // Lights stay off and nothing is captured until the window ends.
+    if window.as_ref().is_some_and(|window| !window.enabled) {
+      tokio::select! {
+        _ = tokio::time::sleep(SCHEDULE_CHECK_INTERVAL) => continue,
+        _ = &mut shutdown => break,
+      }
+    }
+    let presses = profile_presses.load(Ordering::SeqCst);
+    if presses != profile_switches {
+      let steps = presses.wrapping_sub(profile_switches);
+      profile_switches = presses;
+      let next = next_profile(&config, active_profile.as_deref(), steps);
+      info!("Switching to profile {}", next);
+      // Connections handed back meanwhile go to the lights they belong to before the indices
+      // change.
+      while let Ok((index, connected)) = reconnected.try_recv() {
+        lights[index].reconnected(connected, &config);
+      }
+      switch_profile(
+        &mut lights,
+        config.profile_lights(Some(&next)),
+        central.as_ref(),
+        &config,
+        pool.as_ref(),
+        dry_run,
+        &reconnected_sender,
+      )
+      .await;
+      set_panic_lights(&panic_lights, &lights, &config);
+      active_profile = Some(next);
+    }
+    let profile = active_profile
+      .as_ref()
+      .and_then(|name| config.profiles.get(name));
 
-        let avg_color = sampled_color / samples as f32;
-        Vec3::new(avg_color.x.sqrt(), avg_color.y.sqrt(), avg_color.z.sqrt()) / 255.0
+    let pause = paused.load(Ordering::SeqCst);
+    if pause != was_paused {
+      was_paused = pause;
+      if pause {
+        info!("Paused");
+        pause_lights(
+          &mut lights,
+          config.pause_action,
+          paced.as_ref().map(|(targets, _)| targets),
+          calibration,
+        )
+        .await;
+      } else {
+        info!("Resumed");
+        // Follow the screen from its current frame right away instead of fading from the pause.
+        previous_frame = None;
+        snap_next = true;
+        backoff.frame(true);
       }
-      ColorSamplingAlgorithm::MostDominant { quality, sorted } => {
-        let pixels = buffer
-          .iter()
-          .flat_map(|pixel| [pixel.r, pixel.g, pixel.b])
-          .collect::<Vec<_>>();
-        let mut dominant = get_palette(&pixels, color_thief::ColorFormat::Rgb, quality, 2)?;
-        if sorted {
-          dominant.sort_unstable_by_key(|color| {
-            let color = Vec3::new(color.r as f32, color.g as f32, color.b as f32);
-            let min = color.min_element() as u8;
-            let max = color.max_element() as u8;
-            ((max + min) * (max - min)) / max.max(1)
-          });
-        }
-        let dominant = dominant[0];
-        let color = Vec3::new(dominant.r as f32, dominant.g as f32, dominant.b as f32);
-        color / 255.0
-      }
-      ColorSamplingAlgorithm::Vibrancy => {
-        let pixels = buffer
+    }
+    if pause {
+      tokio::select! {
+        _ = tokio::time::sleep(PAUSE_CHECK_INTERVAL) => continue,
+        _ = &mut shutdown => break,
+      }
+    }
+    // A still screen is captured less and less often, the idle effect keeps animating.
+    let delay = backoff.delay();
+    if !delay.is_zero() && !idle {
+      tokio::select! {
+        _ = tokio::time::sleep(delay) => {}
+        _ = &mut shutdown => break,
+      }
+    }
+    if let Some(pacer) = &mut pacer {
+      pacer.tick().await;
+    }
+    let frame_time = frame_start.elapsed();
+    frame_start = Instant::now();
+
+    let motion = match &audio {
+      Some(audio) => {
+        let color = config
+          .audio_mapping
+          .color(audio.take(), config.audio_sensitivity);
+        for light in &mut lights {
+          light.color.sampled = color;
+        }
+        // Following the sound, a still screen never counts as idle.
+        last_change = Instant::now();
+        1.0
+      }
+      None => {
+        let stage_start = Instant::now();
+        let mut new_frame = false;
+        let mut failure = None;
+        for monitor in &mut monitors {
+          match monitor.capture.capture_frame() {
+            Ok(frame) => {
+              monitor.frame = Some(frame);
+              new_frame = true;
+            }
+            // Nothing changed on this screen, its last frame still holds.
+            Err(CaptureError::Timeout) => {}
+            Err(e) => failure = Some(e),
+          }
+        }
+        if let Some(e) = failure {
+          capture_failures += 1;
+          if capture_failures > config.capture_max_failures {
+            return Err(AmbilightError::CaptureFailed(e).into());
+          }
+          warn!(
+            "Capture failed ({} in a row), retrying: {}",
+            capture_failures, e
+          );
+          continue;
+        }
+        capture_failures = 0;
+        // Nothing changed on screen since the last frame, right after resuming its last frame is
+        // sampled anyway.
+        if !new_frame && !snap_next {
+          backoff.frame(false);
+          continue;
+        }
+        METRICS.record(Stage::Capture, stage_start.elapsed());
+
+        // The samples of all screens together, so a change on any of them counts.
+        let frame: Vec<[u8; 3]> = monitors
           .iter()
-          .flat_map(|pixel| [pixel.r, pixel.g, pixel.b])
-          .collect::<Vec<_>>();
-        let image = DynamicImage::ImageRgb8(
-          ImageBuffer::from_raw(width as u32, height as u32, pixels).unwrap(),
-        )
-        .resize(
-          (width as f32 * 0.05) as u32,
-          (height as f32 * 0.05) as u32,
-          FilterType::Nearest,
+          .filter_map(|monitor| monitor.frame.as_ref())
+          .flat_map(|(buffer, (width, height))| config.frame_diff.sample(buffer, *width, *height))
+          .collect();
+        // Protected content and a briefly unavailable duplication both come through as pure black.
+        if frame.iter().all(|sample| *sample == [0, 0, 0]) {
+          black_frames += 1;
+        } else {
+          black_frames = 0;
+        }
+        let hold = black_frames > 0 && black_frames <= config.black_frame_hold;
+        let (changed, motion) = match &previous_frame {
+          Some(_) if hold => (false, 0.0),
+          Some(previous) => (
+            config.frame_diff.changed(previous, &frame),
+            config.frame_diff.motion(previous, &frame),
+          ),
+          None => (true, 1.0),
+        };
+        if changed {
+          previous_frame = Some(frame);
+          last_change = Instant::now();
+        }
+        backoff.frame(changed);
+
+        let stage_start = Instant::now();
+        let mut downsample_time = Duration::ZERO;
+        if changed {
+          let contents: Vec<_> = monitors
+            .iter()
+            .map(|monitor| {
+              let (buffer, (width, height)) = monitor.frame.as_ref()?;
+              Some(config.letterbox.crop(buffer, *width, *height))
+            })
+            .collect();
+          // One dual tone per monitor and frame, so the warm and cool lights split one palette
+          let tone_lights = lights.iter().any(|light| light.tone_role.is_some());
+          let mut tones = Vec::new();
+          for (sampler, content) in tone_samplers.iter_mut().zip(&contents) {
+            let tone = match content {
+              Some((content, width, height)) if tone_lights => {
+                let tone = sampler.dual_tone(content, *width, *height);
+                downsample_time += sampler.downsample_time();
+                tone
+              }
+              _ => None,
+            };
+            tones.push(tone);
+          }
+          for light in &mut lights {
+            let mut color = Vec3::ZERO;
+            let mut luma = 0.0;
+            let mut total_weight = 0.0;
+            let role = light.tone_role;
+            for (((monitor, content), tone), sampler) in monitors
+              .iter()
+              .zip(&contents)
+              .zip(&tones)
+              .zip(&mut light.samplers)
+            {
+              let Some((content, width, height)) = content else {
+                continue;
+              };
+              if light
+                .config
+                .monitor
+                .as_ref()
+                .is_some_and(|target| *target != monitor.target)
+              {
+                continue;
+              }
+              let (zone, zone_width, zone_height) = light.zone.crop(content, *width, *height);
+              color += match (role, tone) {
+                (Some(role), Some(tone)) => sampling::tone_color(tone, role),
+                (Some(_), None) => light.color.sampled,
+                (None, _) => {
+                  let color = sampler.sample(&zone, zone_width, zone_height)?;
+                  downsample_time += sampler.downsample_time();
+                  color
+                }
+              } * monitor.weight;
+              if config.auto_brightness {
+                luma += sampling::mean_luma(&zone, zone_width, zone_height) * monitor.weight;
+              }
+              total_weight += monitor.weight;
+            }
+            if total_weight > 0.0 {
+              light.color.sampled = color / total_weight;
+              if config.auto_brightness {
+                light.scene_luma = luma / total_weight;
+              }
+            }
+          }
+        } else {
+          metrics::inc(&METRICS.frames_skipped);
+        }
+        METRICS.record(Stage::Downsample, downsample_time);
+        METRICS.record(
+          Stage::Sample,
+          stage_start.elapsed().saturating_sub(downsample_time),
+        );
+        motion
+      }
+    };
+
+    let stage_start = Instant::now();
+    let fade = color_config.fade.keep(motion, frame_time);
+    let window_cap = window.as_ref().and_then(|window| window.brightness);
+    let profile_cap = profile.and_then(|profile| profile.brightness);
+    let brightness_cap = window_cap.into_iter().chain(profile_cap).min();
+    for light in &mut lights {
+      let snap = light.color.step(&color_config, fade, snap_next);
+      light.rgb = correction::to_bytes(light.color.shown, config.color_rounding);
+      let target_brightness = target_brightness(&config, light.scene_luma, brightness_cap);
+      light.brightness = if snap {
+        target_brightness
+      } else {
+        light.brightness * fade + target_brightness * (1.0 - fade)
+      };
+    }
+    snap_next = false;
+    // Once the screen stood still long enough the effect takes over, until it changes again.
+    let idle_for = idle_timeout.and_then(|timeout| last_change.elapsed().checked_sub(timeout));
+    if idle_for.is_some() != idle {
+      idle = idle_for.is_some();
+      info!(
+        "{} the idle effect",
+        if idle { "Starting" } else { "Leaving" }
+      );
+    }
+    if let Some(idle_for) = idle_for {
+      let color = config.idle_effect.color(idle_for);
+      for light in &mut lights {
+        light.rgb = color;
+        // Fade back from the effect once the screen changes
+        light.color.shown = Vec3::new(color[0] as f32, color[1] as f32, color[2] as f32) / 255.0;
+      }
+    }
+    if let Some(color) = profile.and_then(|profile| profile.static_color) {
+      for light in &mut lights {
+        light.rgb = color;
+        // Fade back to the screen once another profile follows it
+        light.color.shown = Vec3::new(color[0] as f32, color[1] as f32, color[2] as f32) / 255.0;
+      }
+    }
+    METRICS.record(Stage::Correct, stage_start.elapsed());
+    debug!(
+      "Color grabbed {}",
+      lights
+        .iter()
+        .map(|light| {
+          let [r, g, b] = light.rgb;
+          format!("({},{},{}) ~ {}", r, g, b, nearest_named_color(light.rgb))
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+    );
+    if let Some(trace) = &trace {
+      for (index, light) in lights.iter().enumerate() {
+        let sent = calibration.apply(light.rgb);
+        trace.record(index, light.color.sampled, light.color.corrected, sent);
+      }
+    }
+
+    let mut disconnected = vec![false; lights.len()];
+    while let Some(Some(event)) = events
+      .as_mut()
+      .and_then(|events| events.next().now_or_never())
+    {
+      if let CentralEvent::DeviceDisconnected(id) = event {
+        for (light, disconnected) in lights.iter_mut().zip(&mut disconnected) {
+          let ours = light.connected.as_ref();
+          if ours.is_some_and(|connected| connected.light.id() == id) {
+            warn!("Light {} disconnected", light.config.mac);
+            light.health.mark_dropped();
+            *disconnected = true;
+          }
+        }
+      }
+    }
+
+    if let Some((targets, _)) = &paced {
+      // The output task does the writing, it only needs the new targets and reports back how
+      // each write went.
+      let _ = targets.send(output_targets(&lights, calibration));
+      while let Ok((index, wrote)) = write_outcomes.try_recv() {
+        let light = &mut lights[index];
+        if wrote {
+          light.health.record_success();
+        } else {
+          light.health.record_failure();
+          // Only BLE lights reconnect, WLED is simply tried again once the retry is due.
+          disconnected[index] |= light.connected.is_some() && light.health.is_dropped();
+        }
+      }
+    } else {
+      let stage_start = Instant::now();
+      let writes = lights
+        .iter_mut()
+        .zip(&disconnected)
+        .enumerate()
+        .filter(|(_, (light, disconnected))| !**disconnected && !light.reconnecting())
+        .filter(|(_, (light, _))| {
+          let write = light.needs_write(&config);
+          if !write {
+            metrics::inc(&METRICS.writes_skipped);
+            skip_window.1 += 1;
+          }
+          skip_window.2 += 1;
+          write
+        })
+        .map(|(index, (light, _))| {
+          let write = health::Write {
+            rgb: calibration.apply(light.rgb),
+            brightness: light.brightness_byte(),
+            driver: light.driver.as_ref(),
+            health: &mut light.health,
+          };
+          (index, write)
+        });
+      let results = health::write_active(writes).await;
+      // Encode is only recorded by the writes, so frames without any keep the last timings.
+      let wrote = !results.is_empty();
+      for (index, result) in results {
+        match result {
+          Ok(()) => {
+            metrics::inc(&METRICS.writes_sent);
+            lights[index].last_sent = Some((lights[index].rgb, lights[index].brightness_byte()));
+            lights[index].last_sent_at = Some(Instant::now());
+          }
+          Err(e) => {
+            metrics::inc(&METRICS.write_failures);
+            warn!("Light {}: {}", lights[index].config, e);
+            // Only BLE lights reconnect, WLED is simply tried again once the retry is due.
+            disconnected[index] =
+              lights[index].connected.is_some() && lights[index].health.is_dropped();
+          }
+        }
+      }
+      if wrote {
+        let encode_latency = METRICS.latency(Stage::Encode);
+        METRICS.record(
+          Stage::Write,
+          stage_start.elapsed().saturating_sub(encode_latency),
+        );
+      }
+    }
+
+    if let Some(status) = &status {
+      *status.lock().unwrap() = lights
+        .iter()
+        .zip(&disconnected)
+        .map(|(light, disconnected)| status::LightStatus {
+          name: light.config.to_string(),
+          rgb: light.rgb,
+          connected: match light.config.output {
+            LightOutput::Ble => light.connected.is_some() && !disconnected,
+            LightOutput::Wled { .. } | LightOutput::OpenRgb => !dry_run,
+          },
+          health: light.health.state(),
+          failures: light.health.failures(),
+          battery_level: light.connected.as_ref().and_then(|c| c.battery_level),
+          device_info: light
+            .connected
+            .as_ref()
+            .map(|c| c.device_info.clone())
+            .unwrap_or_default(),
+        })
+        .collect();
+    }
+
+    // Only connected lights can fail, so there is always an adapter here. Each light reconnects
+    // on a task of its own, the others keep being captured and written meanwhile.
+    if let (Some(central), true) = (&central, disconnected.contains(&true)) {
+      for (index, light) in lights.iter_mut().enumerate() {
+        if disconnected[index] && !light.reconnecting() {
+          light.reconnect(index, central, &config, &reconnected_sender);
+        }
+      }
+      set_panic_lights(&panic_lights, &lights, &config);
+    }
+    while let Ok((index, connected)) = reconnected.try_recv() {
+      lights[index].reconnected(connected, &config);
+      set_panic_lights(&panic_lights, &lights, &config);
+    }
+    #[cfg(feature = "mqtt")]
+    if let Some(mqtt) = &mut mqtt {
+      mqtt.set_color(lights[0].rgb);
+    }
+    metrics::inc(&METRICS.frames_processed);
+
+    if skip_window.0.elapsed() >= WRITE_SKIP_LOG_INTERVAL {
+      info!(
+        "Skipped {} of {} light writes in the last minute",
+        skip_window.1, skip_window.2
+      );
+      skip_window = (Instant::now(), 0, 0);
+    }
+
+    if battery_polled.elapsed() >= BATTERY_POLL_INTERVAL {
+      for connected in lights
+        .iter_mut()
+        .filter_map(|light| light.connected.as_mut())
+      {
+        if let Some(battery_char) = &connected.battery_char {
+          connected.battery_level = read_battery_level(&connected.light, battery_char).await;
+        }
+      }
+      battery_polled = Instant::now();
+    }
+
+    fps_window.1 += 1;
+    let elapsed = fps_window.0.elapsed();
+    if elapsed.as_secs() >= 1 {
+      let fps = fps_window.1 as f64 / elapsed.as_secs_f64();
+      debug!("Running at {:.1} FPS", fps);
+      METRICS.set_fps(fps);
+      fps_window = (Instant::now(), 0);
+    }
+
+    if let Some(benchmark) = &mut benchmark {
+      if benchmark.record_frame() {
+        benchmark.print();
+        break;
+      }
+    }
+  }
+
+  if let Some(trace) = trace {
+    trace.finish().await;
+  }
+  // Stop the paced writes first so they can't overwrite the exit color.
+  if let Some((_, task)) = paced.take() {
+    task.abort();
+  }
+  for task in lights.iter().filter_map(|light| light.reconnect.as_ref()) {
+    task.abort();
+  }
+  let exits = lights
+    .iter()
+    .filter(|light| !light.reconnecting())
+    .map(|light| {
+      let last_color = calibration.apply(correction::to_bytes(
+        light.color.shown,
+        config.color_rounding,
+      ));
+      exit_light(
+        light.driver.as_ref(),
+        config.on_exit,
+        last_color,
+        light.brightness_byte(),
+      )
+    });
+  for result in futures::future::join_all(exits).await {
+    if let Err(e) = result {
+      warn!("Resetting the light on exit failed: {}", e);
+    }
+  }
+  for connected in lights.iter().filter_map(|light| light.connected.as_ref()) {
+    let _ = connected.light.disconnect().await;
+  }
+  Ok(())
+}
+
+/// A captured output with its latest frame, which stays in use while the output doesn't change
+struct Monitor {
+  target: CaptureTarget,
+  weight: f32,
+  capture: Box<dyn capture::CaptureSource>,
+  frame: Option<(Vec<capture::Bgra8>, (usize, usize))>,
+}
+
+/// A light with the screen zone it follows and its color state
+struct ZoneLight {
+  config: light::LightConfig,
+  zone: sampling::Zone,
+  /// Not connected in a dry run, nor while reconnecting
+  connected: Option<ConnectedLight>,
+  /// Task connecting to the light again after it dropped
+  reconnect: Option<JoinHandle<()>>,
+  driver: Arc<dyn LightDriver + Send + Sync>,
+  /// One per captured monitor, so their sampling state stays apart
+  samplers: Vec<sampling::Sampler>,
+  /// Set for dual tone lights, which show the tone of the whole frame shared by all of them
+  /// instead of sampling their zone
+  tone_role: Option<vibrant::ToneRole>,
+  color: LightColor,
+  rgb: [u8; 3],
+  /// Mean luma of the zone, only tracked with `auto_brightness`
+  scene_luma: f32,
+  /// Faded brightness in percent
+  brightness: f32,
+  /// Last color and brightness written successfully, unknown after (re)connecting
+  last_sent: Option<([u8; 3], u8)>,
+  last_sent_at: Option<Instant>,
+  health: LightHealth,
+}
+
+impl ZoneLight {
+  fn new(
+    light_config: light::LightConfig,
+    connected: Option<ConnectedLight>,
+    config: &config::Config,
+    pool: Option<&Arc<rayon::ThreadPool>>,
+    dry_run: bool,
+  ) -> Result<ZoneLight, light::LightError> {
+    let outputs = (&connected, &light_config.output, &config.openrgb);
+    let driver: Arc<dyn LightDriver + Send + Sync> = match outputs {
+      (Some(connected), _, _) => ble_driver(connected, &light_config, config),
+      (None, LightOutput::Wled { addr, start, leds }, _) if !dry_run => {
+        let end = *start as u32 + *leds as u32;
+        info!("Sending light {} to LEDs {}..{}", addr, start, end);
+        Arc::new(WledLightDriver::new(addr, *start, *leds)?)
+      }
+      #[cfg(feature = "openrgb")]
+      (None, LightOutput::OpenRgb, Some(openrgb)) if !dry_run => {
+        info!("Sending light to OpenRGB server at {}", openrgb.addr);
+        Arc::new(RateLimitedDriver::new(
+          Arc::new(openrgb::OpenRgbLightDriver::new(openrgb)),
+          Duration::from_millis(openrgb.min_interval_ms),
+        ))
+      }
+      (None, _, _) => Arc::new(NullLightDriver(config.protocol(&light_config).clone())),
+    };
+    let algorithm = light_config.algorithm;
+    let tone_role = match algorithm.unwrap_or(config.sampling().algorithm) {
+      sampling::ColorSamplingAlgorithm::DualTone { role } => {
+        info!(
+          "Light {} shows the {} tone of the frame",
+          light_config, role
         );
-        let vibrancy = vibrant::Vibrancy::new(&image);
-        let color = vibrancy
-          .primary
-          .or(vibrancy.light)
-          .or(vibrancy.light_muted)
-          .or(vibrancy.muted)
-          .or(vibrancy.dark_muted)
-          .or(vibrancy.dark)
-          .unwrap_or_else(|| image::Rgb([0, 0, 0]));
-        Vec3::new(color.0[0] as f32, color.0[1] as f32, color.0[2] as f32) / 255.0
+        Some(role)
+      }
+      _ => None,
+    };
+    Ok(ZoneLight {
+      health: LightHealth::new(config.retry_policy(&light_config)),
+      tone_role,
+      zone: light_config.zone,
+      config: light_config,
+      driver,
+      connected,
+      samplers: config
+        .monitors()
+        .iter()
+        .map(|_| {
+          let mut sampling = config.sampling();
+          if let Some(algorithm) = algorithm {
+            sampling.algorithm = algorithm;
+          }
+          let sampler = sampling::Sampler::new(sampling);
+          match pool {
+            Some(pool) => sampler.with_pool(pool.clone()),
+            None => sampler,
+          }
+        })
+        .collect(),
+      color: LightColor::new(config.smoothing_frames),
+      rgb: [0, 0, 0],
+      scene_luma: 1.0,
+      brightness: config.brightness as f32,
+      last_sent: None,
+      last_sent_at: None,
+      reconnect: None,
+    })
+  }
+
+  fn reconnecting(&self) -> bool {
+    self.reconnect.is_some()
+  }
+
+  /// Drop the connection and reconnect on a task of its own, which sends the new connection
+  /// with the light's `index` once it is back
+  fn reconnect(
+    &mut self,
+    index: usize,
+    central: &Adapter,
+    config: &Arc<config::Config>,
+    reconnected: &mpsc::UnboundedSender<(usize, ConnectedLight)>,
+  ) {
+    let previous = self.connected.take();
+    let central = central.clone();
+    let light_config = self.config.clone();
+    let config = config.clone();
+    let reconnected = reconnected.clone();
+    self.reconnect = Some(tokio::spawn(async move {
+      if let Some(previous) = previous {
+        let _ = previous.light.disconnect().await;
+      }
+      let connected = reconnect_light(&central, &light_config, &config).await;
+      let _ = reconnected.send((index, connected));
+    }));
+  }
+
+  fn reconnected(&mut self, connected: ConnectedLight, config: &config::Config) {
+    self.driver = ble_driver(&connected, &self.config, config);
+    self.connected = Some(connected);
+    self.reconnect = None;
+    self.last_sent = None;
+    self.health.record_success();
+  }
+
+  /// Whether the current color or brightness moved out of the deadzone around the last one sent,
+  /// or the light is due for a keep-alive
+  fn needs_write(&self, config: &config::Config) -> bool {
+    match self.last_sent {
+      Some((last_rgb, last_brightness)) => {
+        config.skip_metric.distance(self.rgb, last_rgb) > config.skip_threshold()
+          || self.brightness_byte().abs_diff(last_brightness) > config.write_deadzone
+          || config.keep_alive().is_some_and(|interval| {
+            self
+              .last_sent_at
+              .is_some_and(|sent_at| sent_at.elapsed() >= interval)
+          })
+      }
+      None => true,
+    }
+  }
+
+  fn brightness_byte(&self) -> u8 {
+    self.brightness.round().clamp(0.0, 100.0) as u8
+  }
+}
+
+/// Driver writing to a connected BLE light, no faster than `min_write_interval_ms`
+fn ble_driver(
+  connected: &ConnectedLight,
+  light_config: &light::LightConfig,
+  config: &config::Config,
+) -> Arc<dyn LightDriver + Send + Sync> {
+  let driver = Arc::new(BleLightDriver::new(
+    connected.light.clone(),
+    connected.cmd_char.clone(),
+    config.protocol(light_config).clone(),
+    config.write_options(),
+  ));
+  if config.min_write_interval_ms == 0 {
+    driver
+  } else {
+    Arc::new(RateLimitedDriver::new(driver, config.min_write_interval()))
+  }
+}
+
+/// Hand the connected lights to the panic hook, each with the off command of its protocol
+fn set_panic_lights(
+  panic_lights: &panic_guard::SharedLights,
+  lights: &[ZoneLight],
+  config: &config::Config,
+) {
+  *panic_lights.lock().unwrap() = lights
+    .iter()
+    .filter_map(|light| {
+      let connected = light.connected.as_ref()?;
+      let off_command = config
+        .protocol(&light.config)
+        .command(config.panic_off_color, FULL_BRIGHTNESS);
+      Some((
+        connected.light.clone(),
+        connected.cmd_char.clone(),
+        off_command,
+      ))
+    })
+    .collect();
+}
+
+/// The profile `steps` after `current` in name order, counting from before the first one without
+/// a current profile
+fn next_profile(config: &config::Config, current: Option<&str>, steps: usize) -> String {
+  let names: Vec<&String> = config.profiles.keys().collect();
+  let index = match current.and_then(|current| names.iter().position(|name| *name == current)) {
+    Some(position) => (position + steps) % names.len(),
+    None => (steps - 1) % names.len(),
+  };
+  names[index].clone()
+}
+
+/// Drive the lights of another profile. Lights on a device the profile keeps hold on to their
+/// connection and color, new BLE lights connect in the background and the ones left out are
+/// switched off and disconnected.
+async fn switch_profile(
+  lights: &mut Vec<ZoneLight>,
+  next: Vec<light::LightConfig>,
+  central: Option<&Adapter>,
+  config: &Arc<config::Config>,
+  pool: Option<&Arc<rayon::ThreadPool>>,
+  dry_run: bool,
+  reconnected: &mpsc::UnboundedSender<(usize, ConnectedLight)>,
+) {
+  let active: Vec<_> = lights.iter().map(|light| light.config.clone()).collect();
+  let plan = profile::plan_switch(&active, &next);
+  // The indices change, so lights still reconnecting start over once they are placed.
+  let mut previous: Vec<_> = lights.drain(..).map(Some).collect();
+  for task in previous
+    .iter()
+    .flatten()
+    .filter_map(|light| light.reconnect.as_ref())
+  {
+    task.abort();
+  }
+  for index in plan.dropped {
+    let Some(light) = previous[index].take() else {
+      continue;
+    };
+    if !light.reconnecting() {
+      let off = exit_light(light.driver.as_ref(), ExitAction::Off, [0, 0, 0], 0);
+      if let Err(e) = off.await {
+        warn!("Switching light {} off failed: {}", light.config, e);
+      }
+    }
+    if let Some(connected) = &light.connected {
+      let _ = connected.light.disconnect().await;
+    }
+  }
+  for (light_config, reused) in next.into_iter().zip(plan.reused) {
+    let mut kept = reused.and_then(|index| previous[index].take());
+    let connected = kept.as_mut().and_then(|kept| kept.connected.take());
+    let mut light = match ZoneLight::new(light_config, connected, config, pool, dry_run) {
+      Ok(light) => light,
+      Err(e) => {
+        warn!("Light left out of the profile: {}", e);
+        continue;
+      }
+    };
+    if let Some(kept) = kept {
+      light.color = kept.color;
+      light.rgb = kept.rgb;
+      light.scene_luma = kept.scene_luma;
+      light.brightness = kept.brightness;
+      light.last_sent = kept.last_sent;
+      light.last_sent_at = kept.last_sent_at;
+    }
+    let connect = light.config.output == LightOutput::Ble && light.connected.is_none();
+    lights.push(light);
+    if let (Some(central), true) = (central, connect) {
+      let index = lights.len() - 1;
+      lights[index].reconnect(index, central, config, reconnected);
+    }
+  }
+}
+
+/// Leave the light as configured by `on_exit` before the program ends
+async fn exit_light(
+  driver: &(dyn LightDriver + Send + Sync),
+  action: ExitAction,
+  last_color: [u8; 3],
+  brightness: u8,
+) -> Result<(), light::LightError> {
+  match action {
+    ExitAction::Leave => Ok(()),
+    ExitAction::Off => {
+      info!("Turning light off");
+      driver.set_color([0, 0, 0], 0).await?;
+      driver.flush().await
+    }
+    ExitAction::Black => {
+      info!("Fading light to black");
+      for step in (0..EXIT_FADE_STEPS).rev() {
+        let color = last_color.map(|c| (c as u32 * step / EXIT_FADE_STEPS) as u8);
+        driver.set_color(color, brightness).await?;
+        tokio::time::sleep(EXIT_FADE_DURATION / EXIT_FADE_STEPS).await;
+      }
+      driver.flush().await
+    }
+  }
+}
+
+/// Connected light with the characteristics the loop uses
+struct ConnectedLight {
+  light: Peripheral,
+  cmd_char: Characteristic,
+  battery_char: Option<Characteristic>,
+  /// Last battery level read, in percent
+  battery_level: Option<u8>,
+  device_info: DeviceInfo,
+}
+
+/// Pick the bluetooth adapter at `index` in the order `--list-adapters` prints them.
+async fn select_adapter(manager: &Manager, index: usize) -> Result<Adapter, AmbilightError> {
+  let mut adapters = manager.adapters().await?;
+  if adapters.is_empty() {
+    return Err(AmbilightError::NoAdapter);
+  }
+  if index >= adapters.len() {
+    return Err(AmbilightError::AdapterOutOfRange {
+      index,
+      count: adapters.len(),
+    });
+  }
+  let central = adapters.swap_remove(index);
+  info!("Using bluetooth adapter {}", central.adapter_info().await?);
+  Ok(central)
+}
+
+/// Scan for the light, connect and resolve its characteristics.
+async fn connect_light(
+  central: &Adapter,
+  light_config: &light::LightConfig,
+  config: &config::Config,
+) -> Result<ConnectedLight, AmbilightError> {
+  let timeout = Duration::from_secs(config.scan_timeout_secs);
+  let light = scan_for_light(central, light_config, timeout).await?;
+  light.connect().await?;
+  let chars = discover_characteristics(&light).await?;
+  debug!("Found characteristics in light: {:#?}", chars);
+  let listed: Vec<_> = chars.iter().collect();
+  let infos: Vec<_> = listed.iter().map(|c| characteristic_info(c)).collect();
+  let cmd_char = match gatt::find_control(&infos, config.light_control_uuid) {
+    Ok(ControlCharacteristic::Configured(index)) => listed[index].clone(),
+    Ok(ControlCharacteristic::Guessed(index)) => {
+      warn!(
+        "Light has no characteristic {}, writing to {} instead, pin it with light_control_uuid",
+        config.light_control_uuid, listed[index].uuid
+      );
+      listed[index].clone()
+    }
+    Err(e) => {
+      if let ControlLookupError::CharacteristicMissing(_) = e {
+        warn!("Light has no writable characteristic at all:");
+        for c in &chars {
+          warn!("  {} {:?}", c.uuid, c.properties);
+        }
+      }
+      return Err(e.into());
+    }
+  };
+  let (battery_char, device_info) = if config.read_device_info {
+    let battery_char = chars
+      .iter()
+      .find(|c| c.uuid == BATTERY_LEVEL_UUID && c.properties.contains(CharPropFlags::READ))
+      .cloned();
+    (battery_char, read_device_info(&light, &chars).await)
+  } else {
+    (None, DeviceInfo::default())
+  };
+  Ok(ConnectedLight {
+    light,
+    cmd_char,
+    battery_char,
+    battery_level: None,
+    device_info,
+  })
+}
+
+/// What [`gatt::find_control`] looks at of a characteristic
+fn characteristic_info(c: &Characteristic) -> CharacteristicInfo {
+  CharacteristicInfo {
+    uuid: c.uuid,
+    write: c.properties.contains(CharPropFlags::WRITE),
+    write_without_response: c.properties.contains(CharPropFlags::WRITE_WITHOUT_RESPONSE),
+  }
+}
+
+/// Connect to the light again, backing off between failed attempts as its retry policy says.
+async fn reconnect_light(
+  central: &Adapter,
+  light_config: &light::LightConfig,
+  config: &config::Config,
+) -> ConnectedLight {
+  let policy = config.retry_policy(light_config);
+  let mut attempt = 1;
+  loop {
+    info!(
+      "Reconnecting to light {} (attempt {})",
+      light_config.mac, attempt
+    );
+    let delay = policy.delay(attempt - 1);
+    match connect_light(central, light_config, config).await {
+      Ok(connected) => {
+        info!("Reconnected to light at {}", connected.light.address());
+        metrics::inc(&METRICS.reconnects);
+        return connected;
       }
+      Err(e) => warn!("Reconnecting failed: {}, retrying in {:?}", e, delay),
+    }
+    tokio::time::sleep(delay).await;
+    attempt += 1;
+  }
+}
+
+/// Scan until the configured light shows up. A device with the configured address is taken right
+/// away, one whose advertised name matches `match_name` only once no address match appeared
+/// within `NAME_MATCH_GRACE`. Gives up after `timeout`, listing the devices seen instead.
+async fn scan_for_light(
+  central: &Adapter,
+  config: &light::LightConfig,
+  timeout: Duration,
+) -> Result<Peripheral, AmbilightError> {
+  // With several lights the earlier scans may already have reported this one.
+  for peripheral in central.peripherals().await? {
+    if peripheral.address() == config.mac {
+      info!("Found light at {}", config.mac);
+      return Ok(peripheral);
+    }
+  }
+
+  let mut events = central.events().await?;
+  central.start_scan(ScanFilter::default()).await?;
+
+  info!("Waiting for bluetooth light to be discovered...");
+  let scan_deadline = tokio::time::Instant::now() + timeout;
+  let mut name_match = None;
+  let mut deadline = scan_deadline;
+  let mut seen = Vec::new();
+  let light = loop {
+    let id = match tokio::time::timeout_at(deadline, events.next()).await {
+      Ok(Some(CentralEvent::DeviceDiscovered(id))) => id,
+      Ok(Some(_)) => continue,
+      Ok(None) | Err(_) => break name_match,
     };
 
-    let color = color.powf(1.0 / COLOR_GAMMA);
-    let mut hsl: Hsl = Rgb::new(color.x, color.y, color.z).into_color();
-    hsl.lightness = mix(hsl.lightness, 0.5, COLOR_CORRECT_LIGHT);
-    hsl.saturation = mix(hsl.saturation, 1.0, COLOR_CORRECT_SATURATION);
-    let rgb: Rgb = hsl.into_color();
-    let color = Vec3::new(rgb.red, rgb.green, rgb.blue);
-    let color = previous_pixel * COLOR_FADE + color * (1.0 - COLOR_FADE);
-    previous_pixel = color;
-    let color = (color * 255.0).min(Vec3::splat(255.0));
-    println!("Color grabbed {}", color);
-    let color_cmd = vec![0x01, color.x as u8, color.y as u8, color.z as u8, 0x64];
-    light
-      .write(cmd_char, &color_cmd, WriteType::WithoutResponse)
-      .await?;
-  }
-}
-
-fn mix(x: f32, y: f32, weight: f32) -> f32 {
-  (x * x * (1.0 - weight) + y * y * weight).sqrt()
-}
-enum ColorSamplingAlgorithm {
-  SquaredAverage { sample_rate: f32 },
-  MostDominant { quality: u8, sorted: bool },
-  Vibrancy,
+    let peripheral = central.peripheral(&id).await?;
+    if peripheral.address() == config.mac {
+      info!("Found light at {:?}", id);
+      break Some(peripheral);
+    }
+    let local_name = peripheral.properties().await?.and_then(|p| p.local_name);
+    seen.push(match &local_name {
+      Some(local_name) => format!("{} ({})", peripheral.address(), local_name),
+      None => peripheral.address().to_string(),
+    });
+    if let (Some(name), None) = (&config.match_name, &name_match) {
+      if let Some(local_name) = local_name {
+        if local_name.to_lowercase() == name.to_lowercase() {
+          info!(
+            "Found light named {} at {}, waiting briefly for {}",
+            local_name,
+            peripheral.address(),
+            config.mac
+          );
+          deadline = scan_deadline.min(tokio::time::Instant::now() + NAME_MATCH_GRACE);
+          name_match = Some(peripheral);
+          continue;
+        }
+      }
+    }
+
+    debug!("Found unknown device at {:?}", id);
+  };
+  central.stop_scan().await?;
+
+  match light {
+    Some(light) => {
+      if light.address() != config.mac {
+        info!("Using the light matched by name at {}", light.address());
+      }
+      Ok(light)
+    }
+    None if tokio::time::Instant::now() >= scan_deadline => Err(AmbilightError::ScanTimeout {
+      mac: config.mac,
+      secs: timeout.as_secs(),
+      seen: if seen.is_empty() {
+        "none".to_string()
+      } else {
+        seen.join(", ")
+      },
+    }),
+    None => Err(AmbilightError::LightNotFound(config.mac)),
+  }
+}
+
+/// Discover the services of the light, retrying while it reports no characteristics at all.
+/// Still empty after the last attempt, the control lookup reports the broken connection.
+async fn discover_characteristics(
+  light: &Peripheral,
+) -> Result<BTreeSet<Characteristic>, AmbilightError> {
+  let mut chars = BTreeSet::new();
+  for attempt in 1..=DISCOVERY_ATTEMPTS {
+    light.discover_services().await?;
+    chars = light.characteristics();
+    if !chars.is_empty() {
+      break;
+    }
+    warn!(
+      "Light reported no characteristics (attempt {}/{})",
+      attempt, DISCOVERY_ATTEMPTS
+    );
+    tokio::time::sleep(DISCOVERY_RETRY_DELAY).await;
+  }
+  Ok(chars)
+}
+
+/// Read and log the battery level in percent, `None` when the read fails
+async fn read_battery_level(light: &Peripheral, battery_char: &Characteristic) -> Option<u8> {
+  match light.read(battery_char).await {
+    Ok(value) if !value.is_empty() => {
+      info!("Light battery level: {}%", value[0]);
+      Some(value[0])
+    }
+    Ok(_) => {
+      warn!("Light returned an empty battery level");
+      None
+    }
+    Err(e) => {
+      warn!("Reading light battery level failed: {}", e);
+      None
+    }
+  }
+}
+
+/// Read the device information strings the light has, skipping the missing ones
+async fn read_device_info(light: &Peripheral, chars: &BTreeSet<Characteristic>) -> DeviceInfo {
+  let read = |uuid: Uuid| async move {
+    let characteristic = chars
+      .iter()
+      .find(|c| c.uuid == uuid && c.properties.contains(CharPropFlags::READ))?;
+    match light.read(characteristic).await {
+      Ok(value) => Some(
+        String::from_utf8_lossy(&value)
+          .trim_matches('\0')
+          .trim()
+          .to_string(),
+      )
+      .filter(|value| !value.is_empty()),
+      Err(e) => {
+        warn!("Reading device information {} failed: {}", uuid, e);
+        None
+      }
+    }
+  };
+  DeviceInfo {
+    manufacturer: read(MANUFACTURER_NAME_UUID).await,
+    model: read(MODEL_NUMBER_UUID).await,
+    firmware: read(FIRMWARE_REVISION_UUID).await,
+  }
+}
+
+/// Flash every light in turn, logging which one is lit, to confirm all of them respond.
+async fn identify_lights(
+  lights: &[ZoneLight],
+  flash_color: [u8; 3],
+  flash_duration: Duration,
+) -> Result<(), light::LightError> {
+  for (index, light) in lights.iter().enumerate() {
+    info!("Flashing light #{} at {}", index, light.config);
+    light.driver.set_color(flash_color, FULL_BRIGHTNESS).await?;
+    tokio::time::sleep(flash_duration).await;
+    light.driver.set_color([0, 0, 0], FULL_BRIGHTNESS).await?;
+  }
+  Ok(())
+}
+
+/// Play the startup animation on all lights at once.
+async fn play_startup_effect(
+  lights: &[ZoneLight],
+  effect: StartupEffect,
+  duration: Duration,
+) -> Result<(), light::LightError> {
+  info!("Playing the startup effect");
+  let start = Instant::now();
+  loop {
+    let progress = start.elapsed().as_secs_f32() / duration.as_secs_f32().max(f32::EPSILON);
+    let color = effect.color(progress);
+    let writes = lights
+      .iter()
+      .map(|light| light.driver.set_color(color, light.brightness_byte()));
+    for result in futures::future::join_all(writes).await {
+      result?;
+    }
+    if progress >= 1.0 {
+      return Ok(());
+    }
+    tokio::time::sleep(STARTUP_EFFECT_STEP).await;
+  }
+}
+
+/// Brightness in percent the light should settle at for a zone with the given mean luma.
+fn target_brightness(config: &config::Config, scene_luma: f32, cap: Option<u8>) -> f32 {
+  let brightness = config.brightness.min(cap.unwrap_or(100)) as f32;
+  if !config.auto_brightness {
+    return brightness;
+  }
+  let min = config.auto_brightness_min as f32 / 100.0;
+  brightness * (min + (1.0 - min) * scene_luma.clamp(0.0, 1.0))
+}
+
+/// The calibration with the color temperature of the schedule window, if it sets one
+fn scheduled_calibration(
+  config: &config::Config,
+  window: Option<&schedule::ScheduleWindow>,
+) -> correction::Calibration {
+  match window.and_then(|window| window.color_temperature) {
+    Some(kelvin) => config.calibration().with_temperature(kelvin),
+    None => config.calibration(),
+  }
+}
+
+fn output_targets(
+  lights: &[ZoneLight],
+  calibration: correction::Calibration,
+) -> Vec<pacing::OutputTarget> {
+  lights
+    .iter()
+    .map(|light| pacing::OutputTarget {
+      connected: !light.reconnecting() && light.health.is_active(),
+      driver: light.driver.clone(),
+      rgb: light.rgb,
+      brightness: light.brightness_byte(),
+      calibration,
+    })
+    .collect()
+}
+
+/// Show `action` on the lights when pausing, fading them out first for `black`
+async fn pause_lights(
+  lights: &mut [ZoneLight],
+  action: ExitAction,
+  paced: Option<&tokio::sync::watch::Sender<Vec<pacing::OutputTarget>>>,
+  calibration: correction::Calibration,
+) {
+  // The paced output task fades to the black targets on its own.
+  if action == ExitAction::Black && paced.is_none() {
+    let fades = lights
+      .iter()
+      .filter(|light| !light.reconnecting())
+      .map(|light| {
+        exit_light(
+          light.driver.as_ref(),
+          action,
+          calibration.apply(light.rgb),
+          light.brightness_byte(),
+        )
+      });
+    for result in futures::future::join_all(fades).await {
+      if let Err(e) = result {
+        warn!("Fading the light out for the pause failed: {}", e);
+      }
+    }
+  }
+  if action != ExitAction::Leave {
+    switch_off(lights, paced, calibration).await;
+  }
+}
+
+/// Switch every light off for a disabled schedule window. The colors restart from black once the
+/// window ends.
+async fn switch_off(
+  lights: &mut [ZoneLight],
+  paced: Option<&tokio::sync::watch::Sender<Vec<pacing::OutputTarget>>>,
+  calibration: correction::Calibration,
+) {
+  for light in lights.iter_mut() {
+    light.rgb = [0, 0, 0];
+    light.color.shown = Vec3::ZERO;
+    light.brightness = 0.0;
+  }
+  if let Some(targets) = paced {
+    let _ = targets.send(output_targets(lights, calibration));
+    return;
+  }
+  for light in lights.iter_mut().filter(|light| !light.reconnecting()) {
+    match light.driver.set_color([0, 0, 0], 0).await {
+      Ok(()) => {
+        light.last_sent = Some(([0, 0, 0], 0));
+        light.last_sent_at = Some(Instant::now());
+      }
+      Err(e) => warn!("Switching light {} off failed: {}", light.config, e),
+    }
+  }
 }
@@ -0,0 +1,189 @@
+#![cfg_attr(not(feature = "metrics"), allow(dead_code))]
+
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Process wide counters, updated by the capture loop with relaxed atomics so recording stays
+/// cheap. They are only formatted when scraped.
+pub static METRICS: Metrics = Metrics::new();
+
+pub fn inc(counter: &AtomicU64) {
+  counter.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Pipeline stages whose latency is tracked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+  Capture,
+  /// Shrinking the frame for the palette algorithms, summed over all lights and monitors
+  Downsample,
+  /// Color sampling, excluding the time spent downsampling
+  Sample,
+  Correct,
+  /// Building the command of the last light written
+  Encode,
+  /// Writing the lights, excluding the last encode
+  Write,
+}
+
+impl Stage {
+  pub const ALL: [Stage; 6] = [
+    Stage::Capture,
+    Stage::Downsample,
+    Stage::Sample,
+    Stage::Correct,
+    Stage::Encode,
+    Stage::Write,
+  ];
+
+  pub fn name(self) -> &'static str {
+    match self {
+      Stage::Capture => "capture",
+      Stage::Downsample => "downsample",
+      Stage::Sample => "sample",
+      Stage::Correct => "correct",
+      Stage::Encode => "encode",
+      Stage::Write => "write",
+    }
+  }
+}
+
+pub struct Metrics {
+  pub frames_processed: AtomicU64,
+  pub frames_skipped: AtomicU64,
+  pub writes_sent: AtomicU64,
+  pub writes_skipped: AtomicU64,
+  pub write_failures: AtomicU64,
+  pub reconnects: AtomicU64,
+  /// Frames per second stored as `f64` bits
+  fps: AtomicU64,
+  /// Latest latency per stage in nanoseconds
+  stage_latency: [AtomicU64; Stage::ALL.len()],
+}
+
+impl Metrics {
+  const fn new() -> Metrics {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicU64 = AtomicU64::new(0);
+    Metrics {
+      frames_processed: AtomicU64::new(0),
+      frames_skipped: AtomicU64::new(0),
+      writes_sent: AtomicU64::new(0),
+      writes_skipped: AtomicU64::new(0),
+      write_failures: AtomicU64::new(0),
+      reconnects: AtomicU64::new(0),
+      fps: AtomicU64::new(0),
+      stage_latency: [ZERO; Stage::ALL.len()],
+    }
+  }
+
+  pub fn set_fps(&self, fps: f64) {
+    self.fps.store(fps.to_bits(), Ordering::Relaxed);
+  }
+
+  pub fn fps(&self) -> f64 {
+    f64::from_bits(self.fps.load(Ordering::Relaxed))
+  }
+
+  pub fn record(&self, stage: Stage, latency: Duration) {
+    self.stage_latency[stage as usize].store(latency.as_nanos() as u64, Ordering::Relaxed);
+  }
+
+  pub fn latency(&self, stage: Stage) -> Duration {
+    Duration::from_nanos(self.stage_latency[stage as usize].load(Ordering::Relaxed))
+  }
+
+  /// Render all metrics in the Prometheus text exposition format
+  pub fn render(&self) -> String {
+    let mut out = String::new();
+    let counters = [
+      (
+        "frames_processed_total",
+        "Frames captured and processed",
+        &self.frames_processed,
+      ),
+      (
+        "frames_skipped_total",
+        "Frames skipped without updating the light",
+        &self.frames_skipped,
+      ),
+      (
+        "ble_writes_total",
+        "Color commands written to the light",
+        &self.writes_sent,
+      ),
+      (
+        "ble_writes_skipped_total",
+        "Color commands not sent because the color stayed within the deadzone",
+        &self.writes_skipped,
+      ),
+      (
+        "ble_write_failures_total",
+        "Failed color command writes",
+        &self.write_failures,
+      ),
+      (
+        "reconnects_total",
+        "Reconnections to the light",
+        &self.reconnects,
+      ),
+    ];
+    for (name, help, value) in counters {
+      let _ = writeln!(out, "# HELP ambilight_{} {}", name, help);
+      let _ = writeln!(out, "# TYPE ambilight_{} counter", name);
+      let _ = writeln!(out, "ambilight_{} {}", name, value.load(Ordering::Relaxed));
+    }
+
+    let _ = writeln!(out, "# HELP ambilight_fps Frames processed per second");
+    let _ = writeln!(out, "# TYPE ambilight_fps gauge");
+    let _ = writeln!(out, "ambilight_fps {}", self.fps());
+
+    let _ = writeln!(
+      out,
+      "# HELP ambilight_stage_latency_seconds Latency of the last frame per stage"
+    );
+    let _ = writeln!(out, "# TYPE ambilight_stage_latency_seconds gauge");
+    for stage in Stage::ALL {
+      let _ = writeln!(
+        out,
+        "ambilight_stage_latency_seconds{{stage=\"{}\"}} {}",
+        stage.name(),
+        self.latency(stage).as_secs_f64()
+      );
+    }
+
+    out
+  }
+}
+
+/// Serve `GET /metrics` on the bound `listener` until the process exits or accepting fails. Binding
+/// is left to the caller, so an unusable address is reported at startup.
+#[cfg(feature = "metrics")]
+pub async fn serve(listener: tokio::net::TcpListener) -> std::io::Result<()> {
+  use log::info;
+  use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+  info!(
+    "Serving metrics on http://{}/metrics",
+    listener.local_addr()?
+  );
+  loop {
+    let (mut socket, _) = listener.accept().await?;
+    tokio::spawn(async move {
+      let mut request = [0u8; 1024];
+      let len = socket.read(&mut request).await.unwrap_or(0);
+      let response = if request[..len].starts_with(b"GET /metrics ") {
+        let body = METRICS.render();
+        format!(
+          "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+          body.len(),
+          body
+        )
+      } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+      };
+      let _ = socket.write_all(response.as_bytes()).await;
+    });
+  }
+}
@@ -0,0 +1,77 @@
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
+
+use crate::config::MqttConfig;
+
+/// Requests buffered by the client before `try_publish` starts dropping colors
+const QUEUE_CAPACITY: usize = 10;
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Output sink publishing the color as `{"r":..,"g":..,"b":..}` to an MQTT topic, e.g. for Home
+/// Assistant.
+///
+/// Publishes are throttled to `min_interval_ms`. The connection is driven by a background task
+/// that reconnects on its own, so a missing broker only costs dropped colors.
+pub struct MqttOutput {
+  client: AsyncClient,
+  topic: String,
+  min_interval: Duration,
+  last_publish: Option<Instant>,
+  last_color: Option<[u8; 3]>,
+}
+
+impl MqttOutput {
+  pub fn new(config: &MqttConfig) -> MqttOutput {
+    let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+    if let Some(username) = &config.username {
+      options.set_credentials(username.as_str(), config.password.as_deref().unwrap_or(""));
+    }
+    let (client, mut eventloop) = AsyncClient::new(options, QUEUE_CAPACITY);
+
+    let broker = format!("{}:{}", config.host, config.port);
+    tokio::spawn(async move {
+      loop {
+        match eventloop.poll().await {
+          Ok(Event::Incoming(Packet::ConnAck(_))) => {
+            info!("Connected to MQTT broker at {}", broker)
+          }
+          Ok(_) => {}
+          Err(e) => {
+            warn!("MQTT broker at {} unavailable: {}", broker, e);
+            tokio::time::sleep(RECONNECT_DELAY).await;
+          }
+        }
+      }
+    });
+
+    MqttOutput {
+      client,
+      topic: config.topic.clone(),
+      min_interval: Duration::from_millis(config.min_interval_ms),
+      last_publish: None,
+      last_color: None,
+    }
+  }
+
+  /// Publish the color unless it is unchanged or the last publish was too recent
+  pub fn set_color(&mut self, rgb: [u8; 3]) {
+    let now = Instant::now();
+    if self.last_color == Some(rgb)
+      || matches!(self.last_publish, Some(last) if now - last < self.min_interval)
+    {
+      return;
+    }
+    // Failures count as a publish too, so a full queue is retried and logged at the same rate.
+    self.last_publish = Some(now);
+    let payload = format!(r#"{{"r":{},"g":{},"b":{}}}"#, rgb[0], rgb[1], rgb[2]);
+    match self
+      .client
+      .try_publish(&self.topic, QoS::AtMostOnce, false, payload)
+    {
+      Ok(()) => self.last_color = Some(rgb),
+      Err(e) => warn!("Publishing the color over MQTT failed: {}", e),
+    }
+  }
+}
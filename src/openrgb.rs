@@ -0,0 +1,137 @@
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use log::info;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tokio::time::timeout;
+
+use mini_ambilight_bluetooth::light::{LightDriver, LightError, FULL_BRIGHTNESS};
+use mini_ambilight_bluetooth::metrics::{Stage, METRICS};
+
+use crate::config::{OpenRgbConfig, OpenRgbDevice};
+
+const PACKET_SET_CLIENT_NAME: u32 = 50;
+const PACKET_UPDATE_LEDS: u32 = 1050;
+const PACKET_SET_CUSTOM_MODE: u32 = 1100;
+
+const CLIENT_NAME: &[u8] = b"mini-ambilight-bluetooth\0";
+const IO_TIMEOUT: Duration = Duration::from_secs(1);
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Controllers on an OpenRGB server, written through the SDK protocol over TCP.
+///
+/// When the server is not reachable the color is dropped and the connection retried a few
+/// seconds later. Writes are not throttled here, the caller wraps the driver in a
+/// `RateLimitedDriver` for `min_interval_ms`.
+pub struct OpenRgbLightDriver {
+  addr: String,
+  devices: Vec<OpenRgbDevice>,
+  connection: Mutex<Connection>,
+}
+
+struct Connection {
+  stream: Option<TcpStream>,
+  retry_at: Instant,
+}
+
+impl OpenRgbLightDriver {
+  pub fn new(config: &OpenRgbConfig) -> OpenRgbLightDriver {
+    OpenRgbLightDriver {
+      addr: config.addr.clone(),
+      devices: config.devices.clone(),
+      connection: Mutex::new(Connection {
+        stream: None,
+        retry_at: Instant::now(),
+      }),
+    }
+  }
+
+  async fn connect(&self) -> std::io::Result<TcpStream> {
+    let mut stream = timeout(IO_TIMEOUT, TcpStream::connect(&self.addr)).await??;
+    stream
+      .write_all(&packet(0, PACKET_SET_CLIENT_NAME, CLIENT_NAME))
+      .await?;
+    for device in &self.devices {
+      stream
+        .write_all(&packet(device.index, PACKET_SET_CUSTOM_MODE, &[]))
+        .await?;
+    }
+    Ok(stream)
+  }
+
+  /// One `UPDATE_LEDS` packet per device, all its LEDs set to `rgb`
+  fn packets(&self, rgb: [u8; 3]) -> Vec<Vec<u8>> {
+    self
+      .devices
+      .iter()
+      .map(|device| {
+        let data_size = 4 + 2 + 4 * device.leds as u32;
+        let mut data = Vec::with_capacity(data_size as usize);
+        data.extend_from_slice(&data_size.to_le_bytes());
+        data.extend_from_slice(&device.leds.to_le_bytes());
+        for _ in 0..device.leds {
+          data.extend_from_slice(&[rgb[0], rgb[1], rgb[2], 0]);
+        }
+        packet(device.index, PACKET_UPDATE_LEDS, &data)
+      })
+      .collect()
+  }
+}
+
+#[async_trait]
+impl LightDriver for OpenRgbLightDriver {
+  async fn set_color(&self, rgb: [u8; 3], brightness: u8) -> Result<(), LightError> {
+    let start = Instant::now();
+    // The protocol has no brightness, so it is applied to the color itself.
+    let scale = brightness.min(FULL_BRIGHTNESS) as u16;
+    let rgb = rgb.map(|c| (c as u16 * scale / FULL_BRIGHTNESS as u16) as u8);
+    let packets = self.packets(rgb);
+    METRICS.record(Stage::Encode, start.elapsed());
+
+    let mut connection = self.connection.lock().await;
+    if connection.stream.is_none() {
+      if Instant::now() < connection.retry_at {
+        return Ok(());
+      }
+      match self.connect().await {
+        Ok(stream) => {
+          info!("Connected to OpenRGB server at {}", self.addr);
+          connection.stream = Some(stream);
+        }
+        Err(e) => {
+          connection.retry_at = Instant::now() + RETRY_INTERVAL;
+          return Err(LightError::OpenRgb(e));
+        }
+      }
+    }
+    let Some(stream) = &mut connection.stream else {
+      return Ok(());
+    };
+    if let Err(e) = write_packets(stream, &packets).await {
+      connection.stream = None;
+      connection.retry_at = Instant::now() + RETRY_INTERVAL;
+      return Err(LightError::OpenRgb(e));
+    }
+    Ok(())
+  }
+}
+
+async fn write_packets(stream: &mut TcpStream, packets: &[Vec<u8>]) -> std::io::Result<()> {
+  for packet in packets {
+    timeout(IO_TIMEOUT, stream.write_all(packet)).await??;
+  }
+  Ok(())
+}
+
+/// Frame a packet with the `ORGB` header
+fn packet(device: u32, id: u32, data: &[u8]) -> Vec<u8> {
+  let mut packet = Vec::with_capacity(16 + data.len());
+  packet.extend_from_slice(b"ORGB");
+  packet.extend_from_slice(&device.to_le_bytes());
+  packet.extend_from_slice(&id.to_le_bytes());
+  packet.extend_from_slice(&(data.len() as u32).to_le_bytes());
+  packet.extend_from_slice(data);
+  packet
+}
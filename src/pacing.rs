@@ -0,0 +1,151 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use glam::Vec3;
+use log::warn;
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+
+use mini_ambilight_bluetooth::correction::Calibration;
+use mini_ambilight_bluetooth::light::LightDriver;
+use mini_ambilight_bluetooth::metrics::{self, Stage, METRICS};
+
+/// Longest cross-fade, so a stalled capture doesn't stretch the fade to the next color
+const MAX_FADE: Duration = Duration::from_millis(500);
+
+/// Latest color the capture loop computed for one light, with the driver to send it through
+#[derive(Clone)]
+pub struct OutputTarget {
+  /// Nothing is sent while the light reconnects or is dropped
+  pub connected: bool,
+  pub driver: Arc<dyn LightDriver + Send + Sync>,
+  pub rgb: [u8; 3],
+  pub brightness: u8,
+  pub calibration: Calibration,
+}
+
+/// Cross-fade of one light from the color shown when its target last changed
+struct Fade {
+  from: Vec3,
+  to: Vec3,
+  /// Last value written, `None` until the first write succeeds and again after a reconnect
+  sent: Option<([u8; 3], u8)>,
+  sent_at: Option<Instant>,
+  driver: Option<Arc<dyn LightDriver + Send + Sync>>,
+}
+
+/// Send the targets to the lights `rate_hz` times a second from a task of its own, decoupled
+/// from the capture timing.
+///
+/// Each new target starts a linear fade from the color currently shown, lasting as long as the
+/// capture took to produce it, so the light keeps moving evenly between bursty frames. An
+/// unchanged value is only resent once `keep_alive` passed since the last write. The index of
+/// every target written goes to `outcomes` with whether the write went through, for the capture
+/// loop to track the health of the light. The task ends once the sender is dropped.
+pub fn spawn(
+  rate_hz: f32,
+  keep_alive: Option<Duration>,
+  mut targets: watch::Receiver<Vec<OutputTarget>>,
+  outcomes: mpsc::UnboundedSender<(usize, bool)>,
+) -> JoinHandle<()> {
+  tokio::spawn(async move {
+    let mut ticks = tokio::time::interval(Duration::from_secs_f32(1.0 / rate_hz));
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut fades: Vec<Fade> = Vec::new();
+    let mut fade_start = Instant::now();
+    let mut fade_duration = MAX_FADE;
+
+    loop {
+      tokio::select! {
+        changed = targets.changed() => {
+          if changed.is_err() {
+            return;
+          }
+          let now = Instant::now();
+          let progress = fade_progress(fade_start, fade_duration);
+          let current = targets.borrow();
+          fades.resize_with(current.len(), || Fade {
+            from: Vec3::ZERO,
+            to: Vec3::ZERO,
+            sent: None,
+            sent_at: None,
+            driver: None,
+          });
+          for (fade, target) in fades.iter_mut().zip(current.iter()) {
+            fade.from = fade.from.lerp(fade.to, progress);
+            fade.to = Vec3::from(target.rgb.map(|c| c as f32));
+            if !fade.driver.as_ref().is_some_and(|driver| same_driver(driver, &target.driver)) {
+              fade.driver = Some(target.driver.clone());
+              fade.sent = None;
+            }
+          }
+          fade_duration = (now - fade_start).min(MAX_FADE);
+          fade_start = now;
+        }
+        _ = ticks.tick() => {
+          let progress = fade_progress(fade_start, fade_duration);
+          let pending: Vec<_> = targets
+            .borrow()
+            .iter()
+            .zip(&fades)
+            .enumerate()
+            .filter(|(_, (target, _))| target.connected)
+            .filter_map(|(index, (target, fade))| {
+              let color = fade.from.lerp(fade.to, progress).round();
+              let value = ([color.x as u8, color.y as u8, color.z as u8], target.brightness);
+              let keep_alive_due = keep_alive.is_some_and(|interval| {
+                fade.sent_at.is_some_and(|sent_at| sent_at.elapsed() >= interval)
+              });
+              if fade.sent == Some(value) && !keep_alive_due {
+                metrics::inc(&METRICS.writes_skipped);
+                return None;
+              }
+              Some((index, value, target.driver.clone(), target.calibration))
+            })
+            .collect();
+          if pending.is_empty() {
+            continue;
+          }
+          let write_start = Instant::now();
+          let writes = pending.into_iter().map(|(index, value, driver, calibration)| async move {
+            (index, value, driver.set_color(calibration.apply(value.0), value.1).await)
+          });
+          for (index, value, result) in futures::future::join_all(writes).await {
+            match result {
+              Ok(()) => {
+                metrics::inc(&METRICS.writes_sent);
+                fades[index].sent = Some(value);
+                fades[index].sent_at = Some(Instant::now());
+                let _ = outcomes.send((index, true));
+              }
+              Err(e) => {
+                metrics::inc(&METRICS.write_failures);
+                warn!("Paced write failed: {}", e);
+                let _ = outcomes.send((index, false));
+              }
+            }
+          }
+          let encode_latency = METRICS.latency(Stage::Encode);
+          METRICS.record(Stage::Write, write_start.elapsed().saturating_sub(encode_latency));
+        }
+      }
+    }
+  })
+}
+
+fn same_driver(
+  a: &Arc<dyn LightDriver + Send + Sync>,
+  b: &Arc<dyn LightDriver + Send + Sync>,
+) -> bool {
+  Arc::as_ptr(a).cast::<()>() == Arc::as_ptr(b).cast::<()>()
+}
+
+/// How far the current fade is, 0...1
+fn fade_progress(start: Instant, duration: Duration) -> f32 {
+  if duration.is_zero() {
+    1.0
+  } else {
+    (start.elapsed().as_secs_f32() / duration.as_secs_f32()).min(1.0)
+  }
+}
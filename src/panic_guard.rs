@@ -0,0 +1,31 @@
+use std::time::Duration;
+
+use btleplug::api::{Characteristic, Peripheral as _, WriteType};
+use btleplug::platform::Peripheral;
+use mini_ambilight_bluetooth::panic_hook::{self, SharedTargets};
+
+const WRITE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Lights the panic hook switches off, set once they are connected, each with its off command.
+pub type SharedLights = SharedTargets<(Peripheral, Characteristic, Vec<u8>)>;
+
+/// Install a panic hook that writes their off command to the shared lights and disconnects them,
+/// see [`panic_hook::install`].
+pub fn install(lights: SharedLights) {
+  panic_hook::install(lights, |targets| {
+    let runtime = match tokio::runtime::Builder::new_current_thread()
+      .enable_all()
+      .build()
+    {
+      Ok(runtime) => runtime,
+      Err(_) => return,
+    };
+    runtime.block_on(async {
+      for (light, cmd_char, off_command) in targets {
+        let write = light.write(cmd_char, off_command, WriteType::WithoutResponse);
+        let _ = tokio::time::timeout(WRITE_TIMEOUT, write).await;
+        let _ = tokio::time::timeout(WRITE_TIMEOUT, light.disconnect()).await;
+      }
+    });
+  });
+}
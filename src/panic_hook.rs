@@ -0,0 +1,42 @@
+use std::sync::{Arc, Mutex, TryLockError};
+
+use log::error;
+
+/// Lights the panic hook switches off, set once they are connected
+pub type SharedTargets<T> = Arc<Mutex<Vec<T>>>;
+
+/// Install a panic hook that makes a best-effort attempt to switch off the shared lights, so a
+/// crash doesn't leave them stuck on the last color.
+///
+/// `switch_off` is called with the lights on a thread of its own, after the default hook reported
+/// the panic. Each light carries its own off command, as lights may differ in their protocol. It
+/// isn't called without any lights, nor when the panic happened while they were locked.
+pub fn install<T, F>(targets: SharedTargets<T>, switch_off: F)
+where
+  T: Clone + Send + 'static,
+  F: Fn(&[T]) + Send + Sync + 'static,
+{
+  let switch_off = Arc::new(switch_off);
+  let default_hook = std::panic::take_hook();
+  std::panic::set_hook(Box::new(move |info| {
+    default_hook(info);
+
+    // The panic may have happened while the lock was held, never wait for it here.
+    let targets = match targets.try_lock() {
+      Ok(guard) => guard.clone(),
+      Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner().clone(),
+      Err(TryLockError::WouldBlock) => Vec::new(),
+    };
+    if targets.is_empty() {
+      return;
+    }
+
+    // The hook can run on a runtime thread which must not block, so use a fresh one.
+    let switch_off = switch_off.clone();
+    let _ = std::thread::spawn(move || {
+      error!("Turning lights off after panic");
+      switch_off(&targets);
+    })
+    .join();
+  }));
+}
@@ -0,0 +1,44 @@
+use serde::Deserialize;
+
+use crate::light::LightConfig;
+
+/// A named setup of lights, `[profiles.reading]` in the config, switched between at runtime
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+  /// The lights driven while the profile is active, each with the zone it follows
+  pub lights: Vec<LightConfig>,
+  /// Upper limit of the brightness in percent, like a schedule window sets
+  #[serde(default)]
+  pub brightness: Option<u8>,
+  /// Color all lights show instead of following the screen, e.g. a warm white for reading
+  #[serde(default)]
+  pub static_color: Option<[u8; 3]>,
+}
+
+/// What switching from one set of lights to another takes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LightSwitch {
+  /// For every light of the new set, the index of the old light on the same device, whose
+  /// connection it takes over, or `None` when it has to be connected
+  pub reused: Vec<Option<usize>>,
+  /// Indices of the old lights the new set leaves out, to switch off and disconnect
+  pub dropped: Vec<usize>,
+}
+
+/// Pair the lights of `next` with the `active` ones on the same device, so a light in both keeps
+/// its connection whatever zone it follows now
+pub fn plan_switch(active: &[LightConfig], next: &[LightConfig]) -> LightSwitch {
+  let mut taken = vec![false; active.len()];
+  let reused = next
+    .iter()
+    .map(|light| {
+      let index =
+        (0..active.len()).find(|&index| !taken[index] && active[index].same_device(light))?;
+      taken[index] = true;
+      Some(index)
+    })
+    .collect();
+  let dropped = (0..active.len()).filter(|&index| !taken[index]).collect();
+  LightSwitch { reused, dropped }
+}
@@ -0,0 +1,685 @@
+use std::borrow::Cow;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use color_quant::NeuQuant;
+use color_thief::get_palette;
+use glam::Vec3;
+use image::{
+  imageops::{self, FilterType},
+  DynamicImage, ImageBuffer, Rgb32FImage, RgbImage,
+};
+use log::warn;
+use palette::{IntoColor, Lab, Srgb};
+use rayon::prelude::*;
+use serde::Deserialize;
+
+use crate::capture::Bgra8;
+use crate::{dominant, kmeans, srgb, vibrant};
+
+/// Pixel stride of the edge sampling, the border regions are large enough that every pixel is
+/// not needed for a stable average.
+const EDGE_SAMPLE_STEP: usize = 4;
+/// Grid density of `mean_luma`, brightness only needs a rough average.
+const LUMA_SAMPLE_RATE: f32 = 0.05;
+/// Grid density of the hue histogram.
+const HUE_SAMPLE_RATE: f32 = 0.05;
+/// Mean saturation times value below which a frame counts as gray for the hue histogram.
+const HUE_MIN_WEIGHT: f32 = 0.02;
+
+/// In the config a table tagged with the algorithm, e.g.
+/// `{ type = "most_dominant", quality = 2, sorted = true, sort_window = 1 }`.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ColorSamplingAlgorithm {
+  SquaredAverage {
+    sample_rate: f32,
+  },
+  /// Mean of the grid samples in linear light, what `SquaredAverage` approximates.
+  LinearAverage {
+    sample_rate: f32,
+  },
+  /// `sort_window` averages the sort keys over that many frames before picking.
+  MostDominant {
+    quality: u8,
+    sorted: bool,
+    sort_window: usize,
+  },
+  /// Population weighted mean of the `swatches` most common palette colors, less jumpy than
+  /// picking a single one on scenes with several hues.
+  WeightedPalette {
+    swatches: usize,
+  },
+  /// Average of the pixels within `border_fraction` of each screen edge, skipping the center,
+  /// for a light mounted behind the monitor. Fractions of 0.5 and above cover the whole frame.
+  EdgeAverage {
+    border_fraction: f32,
+  },
+  /// Centroid of the largest k-means cluster of the grid samples, clustered in Lab.
+  KMeans {
+    k: usize,
+    iterations: usize,
+    sample_rate: f32,
+  },
+  Vibrancy,
+  /// Warmest or coolest prominent color of the frame, pick one role per light.
+  DualTone {
+    role: vibrant::ToneRole,
+  },
+  /// Mean color of the fullest of `bins` hue bins, each pixel counting by its saturation times
+  /// value. Locks onto the hue defining the scene, like a blue sky. Gray frames give their
+  /// average gray.
+  HueHistogram {
+    bins: usize,
+  },
+}
+
+/// Region of the screen a light follows, as fractions of the frame size.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Zone {
+  pub x: f32,
+  pub y: f32,
+  pub w: f32,
+  pub h: f32,
+}
+
+impl Default for Zone {
+  fn default() -> Zone {
+    Zone {
+      x: 0.0,
+      y: 0.0,
+      w: 1.0,
+      h: 1.0,
+    }
+  }
+}
+
+impl Zone {
+  /// Cut the zone out of the frame, returns the pixels with their width and height. The whole
+  /// frame is borrowed without copying.
+  pub fn crop<'a>(
+    &self,
+    buffer: &'a [Bgra8],
+    width: usize,
+    height: usize,
+  ) -> (Cow<'a, [Bgra8]>, usize, usize) {
+    let span = |start: f32, size: f32, total: usize| {
+      let from = ((start * total as f32).round() as usize).min(total.saturating_sub(1));
+      let to = (((start + size) * total as f32).round() as usize).clamp(from + 1, total.max(1));
+      (from, to)
+    };
+    let (x0, x1) = span(self.x, self.w, width);
+    let (y0, y1) = span(self.y, self.h, height);
+    if x0 == 0 && y0 == 0 && x1 == width && y1 == height {
+      return (Cow::Borrowed(buffer), width, height);
+    }
+
+    let mut pixels = Vec::with_capacity((x1 - x0) * (y1 - y0));
+    for y in y0..y1 {
+      if let Some(row) = buffer.get(y * width + x0..y * width + x1) {
+        pixels.extend_from_slice(row);
+      }
+    }
+    (Cow::Owned(pixels), x1 - x0, y1 - y0)
+  }
+}
+
+/// Settings of the sampling step, see the matching fields of `config::Config`.
+#[derive(Debug, Clone, Copy)]
+pub struct SamplingConfig {
+  pub algorithm: ColorSamplingAlgorithm,
+  pub vibrancy: vibrant::VibrancyConfig,
+  pub palette: vibrant::PaletteOptions,
+  pub filter: FilterType,
+  /// Scale of the downsampled frame, in (0, 1]
+  pub resize_factor: f32,
+  pub linear_resize: bool,
+  pub palette_ema_factor: f64,
+  pub palette_ema_threshold: f64,
+  pub memory_decay: f64,
+  pub memory_bias: f64,
+  /// Train a new palette quantizer every this many frames, frames in between are mapped against
+  /// the last one. 1 trains one every frame.
+  pub requantize_frames: usize,
+  /// Mean change per channel, in 0...255 units, of the downsampled frame since the last training
+  /// that trains a new quantizer early.
+  pub requantize_change: f32,
+}
+
+impl Default for SamplingConfig {
+  fn default() -> SamplingConfig {
+    SamplingConfig {
+      algorithm: ColorSamplingAlgorithm::Vibrancy,
+      vibrancy: vibrant::VibrancyConfig::default(),
+      palette: vibrant::PaletteOptions::default(),
+      filter: FilterType::Nearest,
+      resize_factor: 0.05,
+      linear_resize: true,
+      palette_ema_factor: 1.0,
+      palette_ema_threshold: 24.0,
+      memory_decay: 0.95,
+      memory_bias: 0.0,
+      requantize_frames: 1,
+      requantize_change: 16.0,
+    }
+  }
+}
+
+impl SamplingConfig {
+  /// Default settings with the given algorithm
+  pub fn with_algorithm(algorithm: ColorSamplingAlgorithm) -> SamplingConfig {
+    SamplingConfig {
+      algorithm,
+      ..SamplingConfig::default()
+    }
+  }
+}
+
+/// Sample a single image the way a captured frame would be, with default settings and no state
+/// from earlier frames. Returns RGB in 0...1.
+pub fn dominant_color_from_image(
+  image: &DynamicImage,
+  algorithm: &ColorSamplingAlgorithm,
+) -> Result<Vec3, Box<dyn std::error::Error>> {
+  let (buffer, width, height) = frame_from_image(image);
+  dominant_color_from_buffer(&buffer, width, height, algorithm)
+}
+
+/// Sample a single BGRA frame with default settings and no state from earlier frames, the path
+/// every captured frame takes. Returns RGB in 0...1.
+pub fn dominant_color_from_buffer(
+  buffer: &[Bgra8],
+  width: usize,
+  height: usize,
+  algorithm: &ColorSamplingAlgorithm,
+) -> Result<Vec3, Box<dyn std::error::Error>> {
+  Sampler::new(SamplingConfig::with_algorithm(*algorithm)).sample(buffer, width, height)
+}
+
+/// Convert an image to the BGRA frame layout the capture produces
+pub fn frame_from_image(image: &DynamicImage) -> (Vec<Bgra8>, usize, usize) {
+  let image = image.to_rgb8();
+  let buffer = image
+    .pixels()
+    .map(|pixel| Bgra8 {
+      b: pixel[2],
+      g: pixel[1],
+      r: pixel[0],
+      a: 255,
+    })
+    .collect();
+  (buffer, image.width() as usize, image.height() as usize)
+}
+
+/// Mean Rec. 709 luma of a coarse grid over the frame, 0...1
+pub fn mean_luma(buffer: &[Bgra8], width: usize, height: usize) -> f32 {
+  let (sum, samples) = sample_grid(buffer, width, height, LUMA_SAMPLE_RATE).fold(
+    (0.0, 0u32),
+    |(sum, samples), bgra| {
+      let luma = 0.2126 * bgra.r as f32 + 0.7152 * bgra.g as f32 + 0.0722 * bgra.b as f32;
+      (sum + luma / 255.0, samples + 1)
+    },
+  );
+  if samples == 0 {
+    0.0
+  } else {
+    sum / samples as f32
+  }
+}
+
+/// Turns a captured frame into a single color, keeping the state the algorithms carry between
+/// frames.
+pub struct Sampler {
+  config: SamplingConfig,
+  sort_keys: dominant::SortKeyAverager,
+  color_memory: vibrant::ColorMemory,
+  palette_smoother: vibrant::PaletteSmoother,
+  /// Pool shared by the samplers when `threads` is set, the global rayon pool otherwise
+  pool: Option<Arc<rayon::ThreadPool>>,
+  /// RGB copy of the frame, kept between frames so the allocation is reused
+  pixels: Vec<u8>,
+  /// Palette quantizer with the downsampled frame it was trained on and the frames since
+  quantizer: Option<(NeuQuant, DynamicImage, usize)>,
+  /// Color of the last frame, repeated for frames that can't be sampled
+  last_color: Vec3,
+  /// Part of the last `sample` spent downsampling
+  downsample_time: Duration,
+}
+
+impl Sampler {
+  pub fn new(config: SamplingConfig) -> Sampler {
+    Sampler {
+      config,
+      sort_keys: dominant::SortKeyAverager::default(),
+      color_memory: vibrant::ColorMemory::new(config.memory_decay, config.memory_bias),
+      palette_smoother: vibrant::PaletteSmoother::new(
+        config.palette_ema_factor,
+        config.palette_ema_threshold,
+      ),
+      pool: None,
+      pixels: Vec::new(),
+      quantizer: None,
+      last_color: Vec3::ZERO,
+      downsample_time: Duration::ZERO,
+    }
+  }
+
+  /// Run the parallel work on `pool` instead of rayon's global pool, one pool can serve all
+  /// samplers
+  pub fn with_pool(mut self, pool: Arc<rayon::ThreadPool>) -> Sampler {
+    self.pool = Some(pool);
+    self
+  }
+
+  /// Time the last [`Sampler::sample`] spent downsampling the frame, zero for the algorithms
+  /// working on the full frame
+  pub fn downsample_time(&self) -> Duration {
+    self.downsample_time
+  }
+
+  /// Sample the frame, returns RGB in 0...1
+  pub fn sample(
+    &mut self,
+    buffer: &[Bgra8],
+    width: usize,
+    height: usize,
+  ) -> Result<Vec3, Box<dyn std::error::Error>> {
+    self.downsample_time = Duration::ZERO;
+    let color = match self.config.algorithm {
+      ColorSamplingAlgorithm::SquaredAverage { sample_rate } => {
+        let sum = || squared_sum(buffer, width, height, sample_rate);
+        let (sums, samples) = match &self.pool {
+          Some(pool) => pool.install(sum),
+          None => sum(),
+        };
+        let avg_color =
+          Vec3::new(sums[0] as f32, sums[1] as f32, sums[2] as f32) / samples.max(1) as f32;
+        Vec3::new(avg_color.x.sqrt(), avg_color.y.sqrt(), avg_color.z.sqrt()) / 255.0
+      }
+      ColorSamplingAlgorithm::LinearAverage { sample_rate } => {
+        linear_mean(sample_grid(buffer, width, height, sample_rate))
+      }
+      ColorSamplingAlgorithm::MostDominant {
+        quality,
+        sorted,
+        sort_window,
+      } => {
+        fill_rgb(&mut self.pixels, buffer);
+        let dominant = get_palette(&self.pixels, color_thief::ColorFormat::Rgb, quality, 2)?;
+        let dominant = if sorted {
+          self.sort_keys.select(&dominant, sort_window).unwrap()
+        } else {
+          dominant[0]
+        };
+        let color = Vec3::new(dominant.r as f32, dominant.g as f32, dominant.b as f32);
+        color / 255.0
+      }
+      ColorSamplingAlgorithm::WeightedPalette { swatches } => {
+        let image = match self.downsample(buffer, width, height) {
+          Some(image) => image,
+          None => return Ok(self.last_color),
+        };
+        let palette = self.palette(&image);
+        weighted_swatches(&palette, swatches)
+      }
+      ColorSamplingAlgorithm::EdgeAverage { border_fraction } => {
+        linear_mean(edge_pixels(buffer, width, height, border_fraction))
+      }
+      ColorSamplingAlgorithm::KMeans {
+        k,
+        iterations,
+        sample_rate,
+      } => {
+        let points = sample_grid(buffer, width, height, sample_rate)
+          .map(|bgra| {
+            let lab: Lab = Srgb::new(bgra.r, bgra.g, bgra.b)
+              .into_format::<f32>()
+              .into_color();
+            Vec3::new(lab.l, lab.a, lab.b)
+          })
+          .collect::<Vec<_>>();
+        match kmeans::largest_cluster(&points, k, iterations) {
+          Some(centroid) => {
+            let rgb: Srgb = Lab::new(centroid.x, centroid.y, centroid.z).into_color();
+            Vec3::new(rgb.red, rgb.green, rgb.blue).clamp(Vec3::ZERO, Vec3::ONE)
+          }
+          None => Vec3::ZERO,
+        }
+      }
+      ColorSamplingAlgorithm::Vibrancy => {
+        let image = match self.downsample(buffer, width, height) {
+          Some(image) => image,
+          None => return Ok(self.last_color),
+        };
+        let palette = self.palette(&image);
+        let palette = self.palette_smoother.smooth(&palette);
+        let vibrancy = vibrant::Vibrancy::from_palette(
+          &palette,
+          &self.config.vibrancy,
+          Some(&self.color_memory),
+        );
+        let selected = vibrancy
+          .primary
+          .or(vibrancy.light)
+          .or(vibrancy.light_muted)
+          .or(vibrancy.muted)
+          .or(vibrancy.dark_muted)
+          .or(vibrancy.dark);
+        if let Some(selected) = selected {
+          self.color_memory.remember(selected);
+        }
+        let color = selected.unwrap_or(image::Rgb([0, 0, 0]));
+        Vec3::new(color.0[0] as f32, color.0[1] as f32, color.0[2] as f32) / 255.0
+      }
+      ColorSamplingAlgorithm::DualTone { role } => match self.dual_tone(buffer, width, height) {
+        Some(tone) => tone_color(&tone, role),
+        None => return Ok(self.last_color),
+      },
+      ColorSamplingAlgorithm::HueHistogram { bins } => {
+        let pixels = sample_grid(buffer, width, height, HUE_SAMPLE_RATE).collect::<Vec<_>>();
+        hue_peak(&pixels, bins).unwrap_or(self.last_color)
+      }
+    };
+    self.last_color = color;
+    Ok(color)
+  }
+
+  /// Warm and cool tone of the frame from one palette, so the lights of both roles can share it.
+  /// `None` for frames that can't be sampled.
+  pub fn dual_tone(
+    &mut self,
+    buffer: &[Bgra8],
+    width: usize,
+    height: usize,
+  ) -> Option<vibrant::DualTone> {
+    self.downsample_time = Duration::ZERO;
+    let image = self.downsample(buffer, width, height)?;
+    let palette = self.palette(&image);
+    Some(vibrant::DualTone::from_palette(&palette))
+  }
+
+  /// Palette of the downsampled frame, reusing the last quantizer while the frames stay similar
+  fn palette(&mut self, image: &DynamicImage) -> vibrant::Palette {
+    let reuse = match &mut self.quantizer {
+      Some((_, trained_on, frames)) => {
+        *frames += 1;
+        *frames < self.config.requantize_frames
+          && mean_change(trained_on, image) <= self.config.requantize_change
+      }
+      None => false,
+    };
+    if !reuse {
+      let quant = vibrant::quantize(image, 256, 10, &self.config.palette);
+      self.quantizer = Some((quant, image.clone(), 0));
+    }
+    let (quant, _, _) = self.quantizer.as_ref().unwrap();
+    vibrant::Palette::with_quantizer(image, quant)
+  }
+
+  /// Shrink the frame for the palette algorithms. `None` when the buffer doesn't hold
+  /// `width * height` pixels, e.g. while the display mode changes.
+  fn downsample(&mut self, buffer: &[Bgra8], width: usize, height: usize) -> Option<DynamicImage> {
+    if buffer.len() != width * height {
+      warn!(
+        "Skipping a frame of {} pixels that doesn't match its size of {}x{}",
+        buffer.len(),
+        width,
+        height
+      );
+      return None;
+    }
+    let start = Instant::now();
+    let mut pixels = std::mem::take(&mut self.pixels);
+    fill_rgb(&mut pixels, buffer);
+    let image: RgbImage = match ImageBuffer::from_raw(width as u32, height as u32, pixels) {
+      Some(image) => image,
+      None => {
+        warn!(
+          "Skipping a frame of {}x{} that doesn't fit in an image",
+          width, height
+        );
+        return None;
+      }
+    };
+    let factor = self.config.resize_factor;
+    let (new_width, new_height) = (
+      ((width as f32 * factor) as u32).max(1),
+      ((height as f32 * factor) as u32).max(1),
+    );
+    let (resized, image) = if self.config.linear_resize && self.config.filter != FilterType::Nearest
+    {
+      let resized = resize_linear(&image, new_width, new_height, self.config.filter);
+      (DynamicImage::ImageRgb8(resized), image)
+    } else {
+      let image = DynamicImage::ImageRgb8(image);
+      let resized = image.resize(new_width, new_height, self.config.filter);
+      (resized, image.into_rgb8())
+    };
+    // Hand the full size buffer back for the next frame
+    self.pixels = image.into_raw();
+    self.downsample_time = start.elapsed();
+    Some(resized)
+  }
+}
+
+/// Color of the tone with the given role in 0...1, black when the frame has none
+pub fn tone_color(tone: &vibrant::DualTone, role: vibrant::ToneRole) -> Vec3 {
+  let color = tone.get(role).unwrap_or(image::Rgb([0, 0, 0]));
+  Vec3::new(color.0[0] as f32, color.0[1] as f32, color.0[2] as f32) / 255.0
+}
+
+/// Replace the contents of `pixels` with the RGB bytes of the frame, keeping its allocation
+pub fn fill_rgb(pixels: &mut Vec<u8>, buffer: &[Bgra8]) {
+  pixels.clear();
+  pixels.extend(buffer.iter().flat_map(|pixel| [pixel.r, pixel.g, pixel.b]));
+}
+
+/// Mean of the pixels in linear light, encoded back to sRGB in 0...1
+fn linear_mean(pixels: impl Iterator<Item = Bgra8>) -> Vec3 {
+  let mut sampled_color = Vec3::ZERO;
+  let mut samples = 0;
+  for bgra in pixels {
+    sampled_color += Vec3::new(
+      srgb::to_linear(bgra.r),
+      srgb::to_linear(bgra.g),
+      srgb::to_linear(bgra.b),
+    );
+    samples += 1;
+  }
+
+  let avg_color = sampled_color / samples.max(1) as f32;
+  Vec3::new(
+    srgb::encode(avg_color.x),
+    srgb::encode(avg_color.y),
+    srgb::encode(avg_color.z),
+  )
+}
+
+/// Mean absolute difference per channel of two images, in 0...255 units. Images of different sizes
+/// count as entirely different.
+fn mean_change(a: &DynamicImage, b: &DynamicImage) -> f32 {
+  let (a, b) = (a.as_bytes(), b.as_bytes());
+  if a.len() != b.len() {
+    return f32::MAX;
+  }
+  let sum: u64 = a.iter().zip(b).map(|(a, b)| a.abs_diff(*b) as u64).sum();
+  sum as f32 / a.len().max(1) as f32
+}
+
+/// Mean of the `count` most populated swatches weighted by their population, in linear light.
+/// Palettes with fewer swatches use all of them.
+fn weighted_swatches(palette: &vibrant::Palette, count: usize) -> Vec3 {
+  let (sum, population) = palette.dominant_colors(count).into_iter().fold(
+    (Vec3::ZERO, 0),
+    |(sum, total), (rgb, population)| {
+      let linear = Vec3::new(
+        srgb::to_linear(rgb[0]),
+        srgb::to_linear(rgb[1]),
+        srgb::to_linear(rgb[2]),
+      );
+      (sum + linear * population as f32, total + population)
+    },
+  );
+  let mean = sum / population.max(1) as f32;
+  Vec3::new(
+    srgb::encode(mean.x),
+    srgb::encode(mean.y),
+    srgb::encode(mean.z),
+  )
+}
+
+/// Mean of the pixels in the fullest of `bins` hue bins, in linear light and weighted like the
+/// histogram by saturation times value. The average gray of the pixels when they are mostly
+/// unsaturated. `None` without any pixels to average.
+fn hue_peak(pixels: &[Bgra8], bins: usize) -> Option<Vec3> {
+  if pixels.is_empty() {
+    return None;
+  }
+  let bins = bins.max(1);
+  // Hue in 0...1 and saturation times value of a pixel
+  let hue_weight = |bgra: &Bgra8| {
+    let rgb = Vec3::new(bgra.r as f32, bgra.g as f32, bgra.b as f32) / 255.0;
+    let (max, min) = (rgb.max_element(), rgb.min_element());
+    if max <= min {
+      return (0.0, 0.0);
+    }
+    let chroma = max - min;
+    let hue = if max == rgb.x {
+      (rgb.y - rgb.z) / chroma
+    } else if max == rgb.y {
+      (rgb.z - rgb.x) / chroma + 2.0
+    } else {
+      (rgb.x - rgb.y) / chroma + 4.0
+    };
+    // Saturation is chroma / max, times the value max
+    ((hue / 6.0).rem_euclid(1.0), chroma)
+  };
+  let bin = |hue: f32| ((hue * bins as f32) as usize).min(bins - 1);
+
+  let mut histogram = vec![0.0; bins];
+  for pixel in pixels {
+    let (hue, weight) = hue_weight(pixel);
+    histogram[bin(hue)] += weight;
+  }
+  let total = histogram.iter().sum::<f32>();
+  if total < HUE_MIN_WEIGHT * pixels.len() as f32 {
+    let luminance = pixels.iter().fold(0.0, |sum, bgra| {
+      sum
+        + 0.2126 * srgb::to_linear(bgra.r)
+        + 0.7152 * srgb::to_linear(bgra.g)
+        + 0.0722 * srgb::to_linear(bgra.b)
+    });
+    return Some(Vec3::splat(srgb::encode(luminance / pixels.len() as f32)));
+  }
+  let peak = (0..bins)
+    .max_by(|a, b| histogram[*a].total_cmp(&histogram[*b]))
+    .unwrap_or(0);
+
+  let (sum, weight) = pixels
+    .iter()
+    .map(|pixel| (pixel, hue_weight(pixel)))
+    .filter(|(_, (hue, weight))| *weight > 0.0 && bin(*hue) == peak)
+    .fold((Vec3::ZERO, 0.0), |(sum, total), (bgra, (_, weight))| {
+      let linear = Vec3::new(
+        srgb::to_linear(bgra.r),
+        srgb::to_linear(bgra.g),
+        srgb::to_linear(bgra.b),
+      );
+      (sum + linear * weight, total + weight)
+    });
+  if weight == 0.0 {
+    return None;
+  }
+  let mean = sum / weight;
+  Some(Vec3::new(
+    srgb::encode(mean.x),
+    srgb::encode(mean.y),
+    srgb::encode(mean.z),
+  ))
+}
+
+/// Every `EDGE_SAMPLE_STEP`th pixel within `border_fraction` of any edge
+fn edge_pixels(
+  buffer: &[Bgra8],
+  width: usize,
+  height: usize,
+  border_fraction: f32,
+) -> impl Iterator<Item = Bgra8> + '_ {
+  // Past half the frame the borders of opposite edges would overlap, so clamp them to meet.
+  let border_fraction = border_fraction.clamp(0.0, 0.5);
+  let border_x = ((width as f32 * border_fraction).ceil() as usize).max(1);
+  let border_y = ((height as f32 * border_fraction).ceil() as usize).max(1);
+  (0..height).step_by(EDGE_SAMPLE_STEP).flat_map(move |y| {
+    let edge_row = y < border_y || y + border_y >= height;
+    (0..width)
+      .step_by(EDGE_SAMPLE_STEP)
+      .filter(move |&x| edge_row || x < border_x || x + border_x >= width)
+      .filter_map(move |x| buffer.get(x + y * width).copied())
+  })
+}
+
+/// Pixels on an evenly spaced grid covering `sample_rate` of the frame in each direction
+fn sample_grid(
+  buffer: &[Bgra8],
+  width: usize,
+  height: usize,
+  sample_rate: f32,
+) -> impl Iterator<Item = Bgra8> + '_ {
+  let (sample_width, step_x, sample_height, step_y) = grid_steps(width, height, sample_rate);
+  (0..sample_width).flat_map(move |x| {
+    (0..sample_height).filter_map(move |y| buffer.get(x * step_x + width * (y * step_y)).copied())
+  })
+}
+
+/// Column count and stride, row count and stride of the sample grid
+fn grid_steps(width: usize, height: usize, sample_rate: f32) -> (usize, usize, usize, usize) {
+  // Never sample more points than there are pixels, so both steps stay at least 1.
+  let sample_width = ((width as f32 * sample_rate) as usize).min(width).max(1);
+  let step_x = (width / sample_width).max(1);
+  let sample_height = ((height as f32 * sample_rate) as usize).min(height).max(1);
+  let step_y = (height / sample_height).max(1);
+  (sample_width, step_x, sample_height, step_y)
+}
+
+/// Per channel sums of the squared samples of `sample_grid` and their count, with the grid columns
+/// split across the current rayon pool. The sums are integers, so the result is exact and the same
+/// for any number of threads.
+fn squared_sum(buffer: &[Bgra8], width: usize, height: usize, sample_rate: f32) -> ([u64; 3], u64) {
+  let (sample_width, step_x, sample_height, step_y) = grid_steps(width, height, sample_rate);
+  (0..sample_width)
+    .into_par_iter()
+    .map(|x| {
+      let mut sums = [0u64; 3];
+      let mut samples = 0;
+      for y in 0..sample_height {
+        if let Some(bgra) = buffer.get(x * step_x + width * (y * step_y)) {
+          sums[0] += bgra.r as u64 * bgra.r as u64;
+          sums[1] += bgra.g as u64 * bgra.g as u64;
+          sums[2] += bgra.b as u64 * bgra.b as u64;
+          samples += 1;
+        }
+      }
+      (sums, samples)
+    })
+    .reduce(
+      || ([0; 3], 0),
+      |a, b| {
+        (
+          [a.0[0] + b.0[0], a.0[1] + b.0[1], a.0[2] + b.0[2]],
+          a.1 + b.1,
+        )
+      },
+    )
+}
+
+/// Resize in linear light so smoothing filters average physical intensities, not sRGB values.
+pub fn resize_linear(image: &RgbImage, width: u32, height: u32, filter: FilterType) -> RgbImage {
+  let linear: Rgb32FImage = ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
+    image::Rgb(image.get_pixel(x, y).0.map(srgb::to_linear))
+  });
+  let resized = imageops::resize(&linear, width, height, filter);
+  ImageBuffer::from_fn(width, height, |x, y| {
+    image::Rgb(resized.get_pixel(x, y).0.map(srgb::from_linear))
+  })
+}
@@ -0,0 +1,64 @@
+use std::error::Error;
+use std::time::Duration;
+
+use btleplug::api::{bleuuid::uuid_from_u16, Central, Peripheral as _, ScanFilter};
+use btleplug::platform::Adapter;
+use uuid::Uuid;
+
+/// Print every bluetooth adapter with the index `adapter_index` selects it by.
+pub async fn list_adapters(adapters: &[Adapter]) -> Result<(), Box<dyn Error>> {
+  if adapters.is_empty() {
+    println!("No bluetooth adapter found, is bluetooth enabled?");
+  }
+  for (index, adapter) in adapters.iter().enumerate() {
+    println!("{:>3}  {}", index, adapter.adapter_info().await?);
+  }
+  Ok(())
+}
+
+/// Scan for `duration` and print every discovered peripheral without connecting to any of them.
+///
+/// Devices advertising a service in the `0xFFF0` family, which the light's control
+/// characteristic belongs to, are marked with `*`.
+pub async fn list_devices(central: &Adapter, duration: Duration) -> Result<(), Box<dyn Error>> {
+  println!("Scanning for {} seconds...", duration.as_secs_f32());
+  central.start_scan(ScanFilter::default()).await?;
+  tokio::time::sleep(duration).await;
+  central.stop_scan().await?;
+
+  let mut devices = Vec::new();
+  for peripheral in central.peripherals().await? {
+    if let Some(properties) = peripheral.properties().await? {
+      devices.push(properties);
+    }
+  }
+  // Strongest signal first, the light next to the screen is usually at the top.
+  devices.sort_by_key(|properties| std::cmp::Reverse(properties.rssi.unwrap_or(i16::MIN)));
+
+  println!("  {:<20}{:<24}{:>6}  services", "address", "name", "rssi");
+  for properties in &devices {
+    let control = properties.services.iter().any(is_control_family);
+    let services = properties
+      .services
+      .iter()
+      .map(|uuid| uuid.to_string())
+      .collect::<Vec<_>>()
+      .join(", ");
+    println!(
+      "{} {:<20}{:<24}{:>6}  {}",
+      if control { '*' } else { ' ' },
+      properties.address.to_string(),
+      properties.local_name.as_deref().unwrap_or("-"),
+      properties
+        .rssi
+        .map_or_else(|| "-".to_string(), |rssi| rssi.to_string()),
+      services
+    );
+  }
+  println!("Found {} devices", devices.len());
+  Ok(())
+}
+
+fn is_control_family(uuid: &Uuid) -> bool {
+  (0xFFF0..=0xFFFF).any(|short| uuid_from_u16(short) == *uuid)
+}
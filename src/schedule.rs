@@ -0,0 +1,48 @@
+use chrono::NaiveTime;
+use serde::{Deserialize, Deserializer};
+
+/// One window of the schedule, e.g.
+/// `{ from = "22:00", to = "07:00", brightness = 30, color_temperature = 2700.0 }`. Windows
+/// ending before they start run past midnight.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleWindow {
+  #[serde(deserialize_with = "clock_time")]
+  pub from: NaiveTime,
+  #[serde(deserialize_with = "clock_time")]
+  pub to: NaiveTime,
+  /// Upper bound of the brightness in percent
+  #[serde(default)]
+  pub brightness: Option<u8>,
+  /// Replaces `color_temperature` during the window
+  #[serde(default)]
+  pub color_temperature: Option<f32>,
+  /// `false` switches the lights off and stops capturing for the whole window
+  #[serde(default = "ScheduleWindow::default_enabled")]
+  pub enabled: bool,
+}
+
+impl ScheduleWindow {
+  fn default_enabled() -> bool {
+    true
+  }
+
+  pub fn contains(&self, time: NaiveTime) -> bool {
+    if self.from <= self.to {
+      self.from <= time && time < self.to
+    } else {
+      time >= self.from || time < self.to
+    }
+  }
+}
+
+/// The window active at `time`, the first one listed when several overlap
+pub fn active(windows: &[ScheduleWindow], time: NaiveTime) -> Option<&ScheduleWindow> {
+  windows.iter().find(|window| window.contains(time))
+}
+
+/// A local time of day as `HH:MM`
+fn clock_time<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveTime, D::Error> {
+  let value = String::deserialize(deserializer)?;
+  NaiveTime::parse_from_str(&value, "%H:%M").map_err(serde::de::Error::custom)
+}
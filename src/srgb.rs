@@ -0,0 +1,24 @@
+/// Decode an 8 bit sRGB channel into linear light in 0...1
+pub fn to_linear(value: u8) -> f32 {
+  let v = value as f32 / 255.0;
+  if v <= 0.04045 {
+    v / 12.92
+  } else {
+    ((v + 0.055) / 1.055).powf(2.4)
+  }
+}
+
+/// Encode linear light in 0...1 as an 8 bit sRGB channel
+pub fn from_linear(value: f32) -> u8 {
+  (encode(value) * 255.0).round() as u8
+}
+
+/// Encode linear light in 0...1 as sRGB in 0...1
+pub fn encode(value: f32) -> f32 {
+  let v = value.clamp(0.0, 1.0);
+  if v <= 0.0031308 {
+    v * 12.92
+  } else {
+    1.055 * v.powf(1.0 / 2.4) - 0.055
+  }
+}
@@ -0,0 +1,38 @@
+use palette::{Hsv, IntoColor, Srgb};
+use serde::Deserialize;
+
+/// Short animation played once the lights are connected, confirming they respond to the
+/// configured characteristic and command layout before capture takes over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StartupEffect {
+  /// Skip the animation
+  Off,
+  /// Fade through red, green and blue
+  RgbFade,
+  /// Sweep once through every hue
+  HueSweep,
+}
+
+impl StartupEffect {
+  /// Color at `progress` through the animation, 0...1
+  pub fn color(&self, progress: f32) -> [u8; 3] {
+    let progress = progress.clamp(0.0, 1.0);
+    match self {
+      StartupEffect::Off => [0, 0, 0],
+      StartupEffect::RgbFade => {
+        const STOPS: [[f32; 3]; 3] = [[255.0, 0.0, 0.0], [0.0, 255.0, 0.0], [0.0, 0.0, 255.0]];
+        let position = progress * (STOPS.len() - 1) as f32;
+        let index = (position as usize).min(STOPS.len() - 2);
+        let fraction = position - index as f32;
+        let (from, to) = (STOPS[index], STOPS[index + 1]);
+        [0, 1, 2].map(|c| (from[c] + (to[c] - from[c]) * fraction).round() as u8)
+      }
+      StartupEffect::HueSweep => {
+        let rgb: Srgb = Hsv::new(progress * 360.0, 1.0, 1.0).into_color();
+        let rgb = rgb.into_format::<u8>();
+        [rgb.red, rgb.green, rgb.blue]
+      }
+    }
+  }
+}
@@ -0,0 +1,112 @@
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+
+use log::info;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+use crate::gatt::DeviceInfo;
+use crate::health::HealthState;
+use crate::metrics::METRICS;
+
+/// Light state reported by the main loop once per frame
+pub type SharedStatus = Arc<Mutex<Vec<LightStatus>>>;
+
+#[derive(Debug, Clone)]
+pub struct LightStatus {
+  /// MAC or WLED address, as in the logs
+  pub name: String,
+  pub rgb: [u8; 3],
+  pub connected: bool,
+  pub health: HealthState,
+  /// Failed writes in a row
+  pub failures: u32,
+  /// Battery level in percent, for BLE lights reporting one
+  pub battery_level: Option<u8>,
+  pub device_info: DeviceInfo,
+}
+
+/// Serve `GET /color`, the color of the first light as `{"r":..,"g":..,"b":..}`, and `GET /stats`
+/// with the frame rate, write counters and every light's color, connection state, health, battery
+/// level and device information, `null` where unknown. Runs on the bound `listener` until
+/// accepting fails.
+pub async fn serve(listener: TcpListener, status: SharedStatus) -> std::io::Result<()> {
+  info!(
+    "Serving status on http://{}/color and /stats",
+    listener.local_addr()?
+  );
+  loop {
+    let (mut socket, _) = listener.accept().await?;
+    let status = status.clone();
+    tokio::spawn(async move {
+      let mut request = [0u8; 1024];
+      let len = socket.read(&mut request).await.unwrap_or(0);
+      let body = if request[..len].starts_with(b"GET /color ") {
+        Some(color_json(&status.lock().unwrap()))
+      } else if request[..len].starts_with(b"GET /stats ") {
+        Some(stats_json(&status.lock().unwrap()))
+      } else {
+        None
+      };
+      let response = match body {
+        Some(body) => format!(
+          "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+          body.len(),
+          body
+        ),
+        None => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+      };
+      let _ = socket.write_all(response.as_bytes()).await;
+    });
+  }
+}
+
+fn rgb_json(rgb: [u8; 3]) -> String {
+  format!(r#"{{"r":{},"g":{},"b":{}}}"#, rgb[0], rgb[1], rgb[2])
+}
+
+fn color_json(lights: &[LightStatus]) -> String {
+  match lights.first() {
+    Some(light) => rgb_json(light.rgb),
+    None => "null".to_string(),
+  }
+}
+
+fn string_json(value: &str) -> String {
+  format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn optional_json<T>(value: Option<T>, to_json: impl FnOnce(T) -> String) -> String {
+  value.map_or_else(|| "null".to_string(), to_json)
+}
+
+fn stats_json(lights: &[LightStatus]) -> String {
+  let lights: Vec<String> = lights
+    .iter()
+    .map(|light| {
+      let info = &light.device_info;
+      format!(
+        r#"{{"name":{},"color":{},"connected":{},"health":"{}","failures":{},"battery_level":{},"manufacturer":{},"model":{},"firmware":{}}}"#,
+        string_json(&light.name),
+        rgb_json(light.rgb),
+        light.connected,
+        light.health,
+        light.failures,
+        optional_json(light.battery_level, |level| level.to_string()),
+        optional_json(info.manufacturer.as_deref(), string_json),
+        optional_json(info.model.as_deref(), string_json),
+        optional_json(info.firmware.as_deref(), string_json)
+      )
+    })
+    .collect();
+  format!(
+    r#"{{"fps":{:.1},"frames_processed":{},"writes_sent":{},"writes_skipped":{},"write_failures":{},"reconnects":{},"lights":[{}]}}"#,
+    METRICS.fps(),
+    METRICS.frames_processed.load(Ordering::Relaxed),
+    METRICS.writes_sent.load(Ordering::Relaxed),
+    METRICS.writes_skipped.load(Ordering::Relaxed),
+    METRICS.write_failures.load(Ordering::Relaxed),
+    METRICS.reconnects.load(Ordering::Relaxed),
+    lights.join(",")
+  )
+}
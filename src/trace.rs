@@ -0,0 +1,168 @@
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use glam::Vec3;
+use log::{info, warn};
+use serde::Deserialize;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// How often the buffered rows are written out to the file
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TraceFormat {
+  /// One JSON object per line
+  #[default]
+  Jsonl,
+  /// A header line, then one comma separated row per light and frame
+  Csv,
+}
+
+/// Points of the color path a trace can record
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TraceStage {
+  /// Color of the sampling algorithm, RGB in 0...1
+  Sampled,
+  /// After the color correction, before smoothing and fading, RGB in 0...1
+  Corrected,
+  /// Bytes sent to the light after fading and calibration
+  Sent,
+}
+
+impl TraceStage {
+  fn name(&self) -> &'static str {
+    match self {
+      TraceStage::Sampled => "sampled",
+      TraceStage::Corrected => "corrected",
+      TraceStage::Sent => "sent",
+    }
+  }
+}
+
+/// `trace = { path = "colors.jsonl", format = "jsonl", stages = ["sampled", "corrected", "sent"] }`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TraceConfig {
+  pub path: PathBuf,
+  #[serde(default)]
+  pub format: TraceFormat,
+  #[serde(default = "TraceConfig::default_stages")]
+  pub stages: Vec<TraceStage>,
+}
+
+impl TraceConfig {
+  fn default_stages() -> Vec<TraceStage> {
+    vec![TraceStage::Sampled, TraceStage::Corrected, TraceStage::Sent]
+  }
+}
+
+/// Appends the colors of every frame to a file for analysis, e.g. graphing the fade over a film.
+///
+/// Rows go through a channel to a background task that buffers the writes and flushes them every
+/// `FLUSH_INTERVAL`, so the capture loop never waits on the disk.
+pub struct TraceRecorder {
+  rows: mpsc::UnboundedSender<String>,
+  task: JoinHandle<()>,
+  format: TraceFormat,
+  stages: Vec<TraceStage>,
+  started: Instant,
+}
+
+impl TraceRecorder {
+  /// Open the file for appending, a new CSV file starts with the header
+  pub async fn new(config: &TraceConfig) -> std::io::Result<TraceRecorder> {
+    let file = tokio::fs::OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(&config.path)
+      .await?;
+    let empty = file.metadata().await?.len() == 0;
+    let mut writer = BufWriter::new(file);
+    if config.format == TraceFormat::Csv && empty {
+      let mut header = "time_ms,light".to_string();
+      for stage in &config.stages {
+        for channel in ["r", "g", "b"] {
+          header += &format!(",{}_{}", stage.name(), channel);
+        }
+      }
+      writer.write_all(header.as_bytes()).await?;
+      writer.write_all(b"\n").await?;
+    }
+    info!("Recording the colors to {}", config.path.display());
+
+    let (rows, mut receiver) = mpsc::unbounded_channel::<String>();
+    let path = config.path.clone();
+    let task = tokio::spawn(async move {
+      let mut flush = tokio::time::interval(FLUSH_INTERVAL);
+      loop {
+        let result = tokio::select! {
+          row = receiver.recv() => match row {
+            Some(row) => writer.write_all(row.as_bytes()).await,
+            None => break,
+          },
+          _ = flush.tick() => writer.flush().await,
+        };
+        if let Err(e) = result {
+          warn!(
+            "Writing the trace {} failed, stopping: {}",
+            path.display(),
+            e
+          );
+          return;
+        }
+      }
+      if let Err(e) = writer.flush().await {
+        warn!("Writing the trace {} failed: {}", path.display(), e);
+      }
+    });
+
+    Ok(TraceRecorder {
+      rows,
+      task,
+      format: config.format,
+      stages: config.stages.clone(),
+      started: Instant::now(),
+    })
+  }
+
+  /// Queue the colors of one light for the current frame
+  pub fn record(&self, light: usize, sampled: Vec3, corrected: Vec3, sent: [u8; 3]) {
+    let time_ms = self.started.elapsed().as_millis();
+    let colors = self.stages.iter().map(|stage| {
+      let values = match stage {
+        TraceStage::Sampled => sampled.to_array().map(|c| format!("{:.4}", c)),
+        TraceStage::Corrected => corrected.to_array().map(|c| format!("{:.4}", c)),
+        TraceStage::Sent => sent.map(|c| c.to_string()),
+      };
+      (stage.name(), values)
+    });
+    let row = match self.format {
+      TraceFormat::Jsonl => {
+        let mut row = format!(r#"{{"time_ms":{},"light":{}"#, time_ms, light);
+        for (name, [r, g, b]) in colors {
+          row += &format!(r#","{}":[{},{},{}]"#, name, r, g, b);
+        }
+        row + "}\n"
+      }
+      TraceFormat::Csv => {
+        let mut row = format!("{},{}", time_ms, light);
+        for (_, [r, g, b]) in colors {
+          row += &format!(",{},{},{}", r, g, b);
+        }
+        row + "\n"
+      }
+    };
+    // The task only stops after a write error, which it already logged.
+    let _ = self.rows.send(row);
+  }
+
+  /// Write out the remaining rows
+  pub async fn finish(self) {
+    drop(self.rows);
+    let _ = self.task.await;
+  }
+}
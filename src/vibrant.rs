@@ -4,20 +4,47 @@ use std::fmt;
 use image::{GenericImage, Pixel, Rgb};
 
 use hsl::HSL;
+use palette::{IntoColor, Oklab, Srgb};
+use serde::Deserialize;
 
 /// Vibrancy
 ///
 /// 6 vibrant colors: primary, dark, light, dark muted and light muted.
 #[derive(Debug, Hash, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, Deserialize))]
 pub struct Vibrancy {
+  #[cfg_attr(feature = "serde", serde(default, with = "swatch_serde::option"))]
   pub primary: Option<Rgb<u8>>,
+  #[cfg_attr(feature = "serde", serde(default, with = "swatch_serde::option"))]
   pub dark: Option<Rgb<u8>>,
+  #[cfg_attr(feature = "serde", serde(default, with = "swatch_serde::option"))]
   pub light: Option<Rgb<u8>>,
+  #[cfg_attr(feature = "serde", serde(default, with = "swatch_serde::option"))]
   pub muted: Option<Rgb<u8>>,
+  #[cfg_attr(feature = "serde", serde(default, with = "swatch_serde::option"))]
   pub dark_muted: Option<Rgb<u8>>,
+  #[cfg_attr(feature = "serde", serde(default, with = "swatch_serde::option"))]
   pub light_muted: Option<Rgb<u8>>,
 }
 
+/// Color space in which swatches are sorted into the luma and saturation bands
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BandSpace {
+  /// HSL lightness and saturation
+  #[default]
+  Hsl,
+  /// Oklab perceptual lightness and chroma, normalized to the sRGB gamut
+  Oklab,
+}
+
+/// Vibrancy tuning
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VibrancyConfig {
+  pub band_space: BandSpace,
+}
+
 impl Vibrancy {
   /// Create new vibrancy map from an image
   pub fn new<P, G>(image: &G) -> Vibrancy
@@ -25,7 +52,22 @@ impl Vibrancy {
     P: Sized + Pixel<Subpixel = u8>,
     G: Sized + GenericImage<Pixel = P>,
   {
-    generate_varation_colors(&Palette::new(image, 256, 10))
+    generate_varation_colors(
+      &Palette::new(image, 256, 10),
+      &VariationRanges::default(),
+      &VibrancyConfig::default(),
+      None,
+    )
+  }
+
+  /// Create new vibrancy map from an already quantized palette, optionally preferring recently
+  /// seen colors when swatches score nearly the same.
+  pub fn from_palette(
+    palette: &Palette,
+    config: &VibrancyConfig,
+    memory: Option<&ColorMemory>,
+  ) -> Vibrancy {
+    generate_varation_colors(palette, &VariationRanges::default(), config, memory)
   }
 
   fn color_already_set(&self, color: &Rgb<u8>) -> bool {
@@ -44,14 +86,15 @@ impl Vibrancy {
     pixel_counts: &BTreeMap<usize, usize>,
     luma: &MTM<f64>,
     saturation: &MTM<f64>,
+    space: BandSpace,
+    memory: Option<&ColorMemory>,
   ) -> Option<Rgb<u8>> {
-    let mut max = None;
-    let mut max_value = 0_f64;
+    let mut candidates = Vec::new();
 
     let complete_population = pixel_counts.values().fold(0, |acc, c| acc + c);
 
     for (index, swatch) in palette.iter().enumerate() {
-      let HSL { h: _, s, l } = HSL::from_rgb(swatch.channels());
+      let (s, l) = band_values(swatch, space);
 
       if s >= saturation.min
         && s <= saturation.max
@@ -71,123 +114,315 @@ impl Vibrancy {
           population,
           complete_population as f64,
         );
-        if max.is_none() || value > max_value {
-          max = Some(swatch.clone());
-          max_value = value;
+        candidates.push((*swatch, value));
+      }
+    }
+
+    // Only candidates nearly tied with the best one profit from the memory
+    let best = candidates
+      .iter()
+      .map(|(_, value)| *value)
+      .fold(f64::NEG_INFINITY, f64::max);
+    let mut max = None;
+    let mut max_value = 0_f64;
+    for (swatch, value) in candidates {
+      let value = match memory {
+        Some(memory) if best - value <= settings::MEMORY_TIE_MARGIN => {
+          value + memory.bonus(&swatch)
         }
+        _ => value,
+      };
+      if max.is_none() || value > max_value {
+        max = Some(swatch);
+        max_value = value;
       }
     }
 
     max
   }
 
-  // fn fill_empty_swatches(self) {
-  //     if self.primary.is_none() {
-  //         // If we do not have a vibrant color...
-  //         if let Some(dark) = self.dark {
-  //             // ...but we do have a dark vibrant, generate the value by modifying the luma
-  //             let hsl = HSL::from_pixel(&dark).clone()
-  //             hsl.l = settings::TARGET_NORMAL_LUMA;
-  //         }
-  //     }
-  // }
+  /// Derive a missing primary from the dark vibrant swatch and a missing dark vibrant from the
+  /// primary by moving the HSL lightness to the target of the missing variation.
+  fn fill_empty_swatches(&mut self) {
+    if self.primary.is_none() {
+      // If we do not have a vibrant color...
+      if let Some(dark) = self.dark {
+        // ...but we do have a dark vibrant, generate the value by modifying the luma
+        self.primary = Some(with_luma(&dark, settings::TARGET_NORMAL_LUMA));
+      }
+    }
+    if self.dark.is_none() {
+      if let Some(primary) = self.primary {
+        self.dark = Some(with_luma(&primary, settings::TARGET_DARK_LUMA));
+      }
+    }
+  }
 }
 
-fn generate_varation_colors(p: &Palette) -> Vibrancy {
-  let mut vibrancy = Vibrancy::default();
-  vibrancy.primary = vibrancy.find_color_variation(
-    &p.palette,
-    &p.pixel_counts,
-    &MTM {
+/// The swatch with its HSL lightness replaced
+fn with_luma(swatch: &Rgb<u8>, luma: f64) -> Rgb<u8> {
+  let mut hsl = HSL::from_rgb(swatch.channels());
+  hsl.l = luma;
+  let (r, g, b) = hsl.to_rgb();
+  Rgb([r, g, b])
+}
+
+/// Saturation and luma of a swatch in the given band space
+fn band_values(swatch: &Rgb<u8>, space: BandSpace) -> (f64, f64) {
+  match space {
+    BandSpace::Hsl => {
+      let HSL { h: _, s, l } = HSL::from_rgb(swatch.channels());
+      (s, l)
+    }
+    BandSpace::Oklab => {
+      let lab: Oklab<f64> = Srgb::new(swatch[0], swatch[1], swatch[2])
+        .into_format::<f64>()
+        .into_color();
+      let chroma = (lab.a * lab.a + lab.b * lab.b).sqrt();
+      ((chroma / settings::OKLAB_MAX_CHROMA).min(1_f64), lab.l)
+    }
+  }
+}
+
+/// Luma and saturation range of one vibrancy variation
+#[derive(Debug, Clone, Copy)]
+pub struct VariationRange {
+  pub luma: MTM<f64>,
+  pub saturation: MTM<f64>,
+}
+
+/// Ranges the six variations are picked from, `Default` gives the tuned values
+#[derive(Debug, Clone, Copy)]
+pub struct VariationRanges {
+  pub primary: VariationRange,
+  pub light: VariationRange,
+  pub dark: VariationRange,
+  pub muted: VariationRange,
+  pub light_muted: VariationRange,
+  pub dark_muted: VariationRange,
+}
+
+impl Default for VariationRanges {
+  fn default() -> VariationRanges {
+    let normal_luma = MTM {
       min: settings::MIN_NORMAL_LUMA,
       target: settings::TARGET_NORMAL_LUMA,
       max: settings::MAX_NORMAL_LUMA,
-    },
-    &MTM {
-      min: settings::MIN_VIBRANT_SATURATION,
-      target: settings::TARGET_VIBRANT_SATURATION,
-      max: 1_f64,
-    },
-  );
-
-  vibrancy.light = vibrancy.find_color_variation(
-    &p.palette,
-    &p.pixel_counts,
-    &MTM {
+    };
+    let light_luma = MTM {
       min: settings::MIN_LIGHT_LUMA,
       target: settings::TARGET_LIGHT_LUMA,
       max: 1_f64,
-    },
-    &MTM {
-      min: settings::MIN_VIBRANT_SATURATION,
-      target: settings::TARGET_VIBRANT_SATURATION,
-      max: 1_f64,
-    },
-  );
-
-  vibrancy.dark = vibrancy.find_color_variation(
-    &p.palette,
-    &p.pixel_counts,
-    &MTM {
+    };
+    let dark_luma = MTM {
       min: 0_f64,
       target: settings::TARGET_DARK_LUMA,
       max: settings::MAX_DARK_LUMA,
-    },
-    &MTM {
+    };
+    let vibrant = MTM {
       min: settings::MIN_VIBRANT_SATURATION,
       target: settings::TARGET_VIBRANT_SATURATION,
       max: 1_f64,
-    },
-  );
-
-  vibrancy.muted = vibrancy.find_color_variation(
-    &p.palette,
-    &p.pixel_counts,
-    &MTM {
-      min: settings::MIN_NORMAL_LUMA,
-      target: settings::TARGET_NORMAL_LUMA,
-      max: settings::MAX_NORMAL_LUMA,
-    },
-    &MTM {
-      min: 0_f64,
-      target: settings::TARGET_MUTED_SATURATION,
-      max: settings::MAX_MUTED_SATURATION,
-    },
-  );
-
-  vibrancy.light_muted = vibrancy.find_color_variation(
-    &p.palette,
-    &p.pixel_counts,
-    &MTM {
-      min: settings::MIN_LIGHT_LUMA,
-      target: settings::TARGET_LIGHT_LUMA,
-      max: 1_f64,
-    },
-    &MTM {
+    };
+    let muted = MTM {
       min: 0_f64,
       target: settings::TARGET_MUTED_SATURATION,
       max: settings::MAX_MUTED_SATURATION,
-    },
-  );
+    };
+    let range = |luma, saturation| VariationRange { luma, saturation };
+    VariationRanges {
+      primary: range(normal_luma, vibrant),
+      light: range(light_luma, vibrant),
+      dark: range(dark_luma, vibrant),
+      muted: range(normal_luma, muted),
+      light_muted: range(light_luma, muted),
+      dark_muted: range(dark_luma, muted),
+    }
+  }
+}
 
-  vibrancy.dark_muted = vibrancy.find_color_variation(
-    &p.palette,
-    &p.pixel_counts,
-    &MTM {
-      min: 0_f64,
-      target: settings::TARGET_DARK_LUMA,
-      max: settings::MAX_DARK_LUMA,
-    },
-    &MTM {
-      min: 0_f64,
-      target: settings::TARGET_MUTED_SATURATION,
-      max: settings::MAX_MUTED_SATURATION,
-    },
-  );
+/// Pick the six variations from the palette, each the best scoring swatch within its range
+pub fn generate_varation_colors(
+  p: &Palette,
+  ranges: &VariationRanges,
+  config: &VibrancyConfig,
+  memory: Option<&ColorMemory>,
+) -> Vibrancy {
+  let mut vibrancy = Vibrancy::default();
+  let find = |vibrancy: &Vibrancy, range: &VariationRange| {
+    vibrancy.find_color_variation(
+      &p.palette,
+      &p.pixel_counts,
+      &range.luma,
+      &range.saturation,
+      config.band_space,
+      memory,
+    )
+  };
+  // Order matters, a swatch already taken by an earlier variation is skipped.
+  vibrancy.primary = find(&vibrancy, &ranges.primary);
+  vibrancy.light = find(&vibrancy, &ranges.light);
+  vibrancy.dark = find(&vibrancy, &ranges.dark);
+  vibrancy.muted = find(&vibrancy, &ranges.muted);
+  vibrancy.light_muted = find(&vibrancy, &ranges.light_muted);
+  vibrancy.dark_muted = find(&vibrancy, &ranges.dark_muted);
+  vibrancy.fill_empty_swatches();
 
   vibrancy
 }
 
+/// Color memory
+///
+/// Decaying record of recently selected colors. Swatches near a remembered color get a bonus of up
+/// to `bias` on their comparison value, but only when they score within `MEMORY_TIE_MARGIN` of the
+/// best swatch. The memory thus only decides between nearly equal candidates, so a scene returning
+/// to an earlier color picks that color again instead of an unrelated one, while a clearly better
+/// swatch always wins.
+#[derive(Debug, Default)]
+pub struct ColorMemory {
+  decay: f64,
+  bias: f64,
+  colors: Vec<(Rgb<u8>, f64)>,
+}
+
+impl ColorMemory {
+  pub fn new(decay: f64, bias: f64) -> ColorMemory {
+    ColorMemory {
+      decay,
+      bias,
+      colors: Vec::new(),
+    }
+  }
+
+  /// Fade all remembered colors and reinforce the selected one
+  pub fn remember(&mut self, color: Rgb<u8>) {
+    for (_, weight) in &mut self.colors {
+      *weight *= self.decay;
+    }
+    self
+      .colors
+      .retain(|(_, weight)| *weight >= settings::MIN_MEMORY_WEIGHT);
+    match self.colors.iter_mut().find(|(c, _)| *c == color) {
+      Some((_, weight)) => *weight += 1_f64,
+      None => self.colors.push((color, 1_f64)),
+    }
+  }
+
+  fn bonus(&self, swatch: &Rgb<u8>) -> f64 {
+    let strongest = self.colors.iter().map(|(_, w)| *w).fold(0_f64, f64::max);
+    if strongest == 0_f64 {
+      return 0_f64;
+    }
+    let swatch = [swatch[0] as f64, swatch[1] as f64, swatch[2] as f64];
+    self
+      .colors
+      .iter()
+      .map(|(color, weight)| {
+        let color = [color[0] as f64, color[1] as f64, color[2] as f64];
+        let closeness = 1_f64 - color_distance(&color, &swatch) / settings::MEMORY_RADIUS;
+        closeness.max(0_f64) * weight / strongest
+      })
+      .fold(0_f64, f64::max)
+      * self.bias
+  }
+}
+
+/// Dual tone
+///
+/// The most prominent warm and cool colors of an image, for splitting a scene across two lights.
+#[derive(Debug, Hash, PartialEq, Eq, Default)]
+pub struct DualTone {
+  pub warm: Option<Rgb<u8>>,
+  pub cool: Option<Rgb<u8>>,
+}
+
+/// Which of the two dual tone colors a light displays.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToneRole {
+  Warm,
+  Cool,
+}
+
+impl fmt::Display for ToneRole {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ToneRole::Warm => write!(f, "warm"),
+      ToneRole::Cool => write!(f, "cool"),
+    }
+  }
+}
+
+impl DualTone {
+  /// Create new dual tone from an image
+  pub fn new<P, G>(image: &G) -> DualTone
+  where
+    P: Sized + Pixel<Subpixel = u8>,
+    G: Sized + GenericImage<Pixel = P>,
+  {
+    DualTone::from_palette(&Palette::new(image, 256, 10))
+  }
+
+  /// Classify the palette by hue and pick the warm and cool swatch with the highest population
+  /// weighted by saturation.
+  ///
+  /// A monochromatic scene only has one temperature, in which case both tones fall back to the
+  /// same swatch. Without any saturated swatch both use the most populous one.
+  ///
+  /// Populations are looked up by color, so a color the palette holds more than once, e.g. after
+  /// smoothing, counts the pixels of all its entries.
+  pub fn from_palette(p: &Palette) -> DualTone {
+    let mut warm = None;
+    let mut warm_value = 0_f64;
+    let mut cool = None;
+    let mut cool_value = 0_f64;
+
+    for swatch in p.palette.iter().unique() {
+      let HSL { h, s, l: _ } = HSL::from_rgb(swatch.channels());
+      let population = p.frequency_of(swatch) as f64;
+      if population == 0_f64 || s < settings::MIN_TONE_SATURATION {
+        continue;
+      }
+
+      let value = population * s;
+      if is_warm_hue(h) {
+        if value > warm_value {
+          warm = Some(*swatch);
+          warm_value = value;
+        }
+      } else if value > cool_value {
+        cool = Some(*swatch);
+        cool_value = value;
+      }
+    }
+
+    let fallback = warm.or(cool).or_else(|| {
+      p.palette
+        .iter()
+        .filter(|swatch| p.frequency_of(swatch) > 0)
+        .max_by_key(|swatch| p.frequency_of(swatch))
+        .copied()
+    });
+
+    DualTone {
+      warm: warm.or(fallback),
+      cool: cool.or(fallback),
+    }
+  }
+
+  /// Color for a light with the given role
+  pub fn get(&self, role: ToneRole) -> Option<Rgb<u8>> {
+    match role {
+      ToneRole::Warm => self.warm,
+      ToneRole::Cool => self.cool,
+    }
+  }
+}
+
+fn is_warm_hue(hue: f64) -> bool {
+  !(settings::WARM_HUE_END..settings::WARM_HUE_START).contains(&hue)
+}
+
 fn invert_diff(val: f64, target_val: f64) -> f64 {
   1_f64 - (val - target_val).abs()
 }
@@ -218,11 +453,11 @@ fn create_comparison_value(
 }
 
 /// Minimum, Maximum, Target
-#[derive(Debug, Hash)]
-struct MTM<T> {
-  min: T,
-  target: T,
-  max: T,
+#[derive(Debug, Clone, Copy, Hash)]
+pub struct MTM<T> {
+  pub min: T,
+  pub target: T,
+  pub max: T,
 }
 
 mod settings {
@@ -246,6 +481,20 @@ mod settings {
   pub const WEIGHT_SATURATION: f64 = 3.0;
   pub const WEIGHT_LUMA: f64 = 6.0;
   pub const WEIGHT_POPULATION: f64 = 1.0;
+
+  /// Largest Oklab chroma of an sRGB color, used to map chroma onto the saturation bands.
+  pub const OKLAB_MAX_CHROMA: f64 = 0.3225;
+
+  /// RGB distance within which a swatch profits from a remembered color
+  pub const MEMORY_RADIUS: f64 = 64.0;
+  pub const MIN_MEMORY_WEIGHT: f64 = 0.01;
+  /// How far below the best comparison value a swatch may score and still get the memory bonus
+  pub const MEMORY_TIE_MARGIN: f64 = 0.02;
+
+  /// Hue range in degrees (wrapping through red) counted as warm, everything else is cool.
+  pub const WARM_HUE_START: f64 = 300.0;
+  pub const WARM_HUE_END: f64 = 90.0;
+  pub const MIN_TONE_SATURATION: f64 = 0.15;
 }
 
 use color_quant::NeuQuant;
@@ -254,11 +503,14 @@ use itertools::Itertools;
 
 /// Palette of colors.
 #[derive(Debug, Hash, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, Deserialize))]
 pub struct Palette {
   /// Palette of Colors represented in RGB
+  #[cfg_attr(feature = "serde", serde(with = "swatch_serde::vec"))]
   pub palette: Vec<Rgb<u8>>,
   /// A map of indices in the palette to a count of pixels in approximately that color in the
   /// original image.
+  #[cfg_attr(feature = "serde", serde(with = "swatch_serde::counts"))]
   pub pixel_counts: BTreeMap<usize, usize>,
 }
 
@@ -270,6 +522,30 @@ impl Palette {
   ///
   /// [color_quant]: https://github.com/PistonDevelopers/color_quant
   pub fn new<P, G>(image: &G, color_count: usize, quality: i32) -> Palette
+  where
+    P: Sized + Pixel<Subpixel = u8>,
+    G: Sized + GenericImage<Pixel = P>,
+  {
+    Palette::with_options(image, color_count, quality, &PaletteOptions::default())
+  }
+
+  /// Create a new palette, with `options` deciding which pixels are left out of the quantization
+  pub fn with_options<P, G>(
+    image: &G,
+    color_count: usize,
+    quality: i32,
+    options: &PaletteOptions,
+  ) -> Palette
+  where
+    P: Sized + Pixel<Subpixel = u8>,
+    G: Sized + GenericImage<Pixel = P>,
+  {
+    Palette::with_quantizer(image, &quantize(image, color_count, quality, options))
+  }
+
+  /// Create a palette of the image from the color map of a quantizer trained on an earlier, similar
+  /// image. Much cheaper than training a new one.
+  pub fn with_quantizer<P, G>(image: &G, quant: &NeuQuant) -> Palette
   where
     P: Sized + Pixel<Subpixel = u8>,
     G: Sized + GenericImage<Pixel = P>,
@@ -279,81 +555,197 @@ impl Palette {
       .map(|(_, _, pixel)| pixel.to_rgba())
       .collect();
 
-    let mut flat_pixels: Vec<u8> = Vec::with_capacity(pixels.len());
-    for rgba in &pixels {
-      if is_boring_pixel(&rgba) {
-        continue;
-      }
-
-      for subpixel in rgba.channels() {
-        flat_pixels.push(*subpixel);
-      }
+    // The color map can hold the same color several times. Duplicates are merged, so the quantizer
+    // indices are remapped to keep `pixel_counts` pointing at the right swatch.
+    let mut palette: Vec<Rgb<u8>> = Vec::new();
+    let mut palette_index = Vec::new();
+    for rgba in quant.color_map_rgba().chunks(4) {
+      let rgb = Rgba::from_slice(rgba).to_rgb();
+      let index = match palette.iter().position(|swatch| *swatch == rgb) {
+        Some(index) => index,
+        None => {
+          palette.push(rgb);
+          palette.len() - 1
+        }
+      };
+      palette_index.push(index);
     }
 
-    let quant = NeuQuant::new(quality, color_count, &flat_pixels);
-
     let pixel_counts = pixels
       .iter()
-      .map(|rgba| quant.index_of(&rgba.channels()))
+      .map(|rgba| palette_index[quant.index_of(rgba.channels())])
       .fold(BTreeMap::new(), |mut acc, pixel| {
         *acc.entry(pixel).or_insert(0) += 1;
         acc
       });
 
-    let palette: Vec<Rgb<u8>> = quant
-      .color_map_rgba()
-      .iter()
-      .chunks_lazy(4)
-      .into_iter()
-      .map(|rgba_iter| {
-        let rgba_slice: Vec<u8> = rgba_iter.cloned().collect();
-        Rgba::from_slice(&rgba_slice).clone().to_rgb()
-      })
-      .unique()
-      .collect();
-
     Palette {
       palette: palette,
       pixel_counts: pixel_counts,
     }
   }
 
+  /// Pixels of all palette entries with this color
   fn frequency_of(&self, color: &Rgb<u8>) -> usize {
-    let index = self
+    self
       .palette
       .iter()
-      .position(|x| x.channels() == color.channels());
-    if let Some(index) = index {
-      *self.pixel_counts.get(&index).unwrap_or(&0)
-    } else {
-      0
-    }
+      .enumerate()
+      .filter(|(_, swatch)| *swatch == color)
+      .map(|(index, _)| *self.pixel_counts.get(&index).unwrap_or(&0))
+      .sum()
   }
 
-  /// Change ordering of colors in palette to be of frequency using the pixel count.
+  /// Up to `n` swatches with their pixel counts, the most frequent first
+  pub fn dominant_colors(&self, n: usize) -> Vec<(Rgb<u8>, usize)> {
+    let mut swatches: Vec<(Rgb<u8>, usize)> = self
+      .pixel_counts
+      .iter()
+      .filter_map(|(&index, &count)| Some((*self.palette.get(index)?, count)))
+      .collect();
+    swatches.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+    swatches.truncate(n);
+    swatches
+  }
+
+  /// Change ordering of colors in palette to be of frequency using the pixel count, the most
+  /// frequent color first. `pixel_counts` follows the new indices. Repeated colors are merged.
   pub fn sort_by_frequency(&self) -> Self {
-    let mut colors = self.palette.clone();
-    colors.sort_by(|a, b| self.frequency_of(&a).cmp(&self.frequency_of(&b)));
+    let mut colors: Vec<Rgb<u8>> = self.palette.iter().unique().copied().collect();
+    colors.sort_by_key(|color| std::cmp::Reverse(self.frequency_of(color)));
 
+    let pixel_counts = colors
+      .iter()
+      .enumerate()
+      .map(|(index, color)| (index, self.frequency_of(color)))
+      .filter(|&(_, count)| count > 0)
+      .collect();
     Palette {
       palette: colors,
-      pixel_counts: self.pixel_counts.clone(),
+      pixel_counts,
+    }
+  }
+}
+
+/// Train a quantizer on the image, for `Palette::with_quantizer`. Parameters as for `Palette::new`.
+pub fn quantize<P, G>(
+  image: &G,
+  color_count: usize,
+  quality: i32,
+  options: &PaletteOptions,
+) -> NeuQuant
+where
+  P: Sized + Pixel<Subpixel = u8>,
+  G: Sized + GenericImage<Pixel = P>,
+{
+  let mut flat_pixels: Vec<u8> = Vec::new();
+  for (_, _, pixel) in image.pixels() {
+    let rgba = pixel.to_rgba();
+    if options.skip_boring && is_boring_pixel(&rgba, options) {
+      continue;
+    }
+
+    for subpixel in rgba.channels() {
+      flat_pixels.push(*subpixel);
+    }
+  }
+
+  NeuQuant::new(quality, color_count, &flat_pixels)
+}
+
+/// Which pixels `Palette` leaves out of the quantization
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PaletteOptions {
+  /// Pixels less opaque than this are skipped. Captured frames are always opaque.
+  pub min_alpha: u8,
+  /// Pixels with every channel above this count as white and are skipped
+  pub max_color: u8,
+  /// Whether to skip transparent and white pixels at all
+  pub skip_boring: bool,
+}
+
+impl Default for PaletteOptions {
+  fn default() -> PaletteOptions {
+    PaletteOptions {
+      min_alpha: 125,
+      max_color: 250,
+      skip_boring: true,
     }
   }
 }
 
-fn is_boring_pixel(pixel: &Rgba<u8>) -> bool {
+fn is_boring_pixel(pixel: &Rgba<u8>, options: &PaletteOptions) -> bool {
   let (r, g, b, a) = (pixel[0], pixel[1], pixel[2], pixel[3]);
+  let max_color = options.max_color;
 
   // If pixel is mostly opaque and not white
-  const MIN_ALPHA: u8 = 125;
-  const MAX_COLOR: u8 = 250;
-
-  let interesting = (a >= MIN_ALPHA) && !(r > MAX_COLOR && g > MAX_COLOR && b > MAX_COLOR);
+  let interesting = (a >= options.min_alpha) && !(r > max_color && g > max_color && b > max_color);
 
   !interesting
 }
 
+/// Exponential moving average of palettes across frames.
+///
+/// Every swatch of a new palette is matched to the nearest swatch of the previous smoothed
+/// palette. Matches closer than `threshold` (RGB distance) move towards the new color by `factor`,
+/// unmatched swatches are taken as they are. With a factor of 1 the palette passes unchanged.
+#[derive(Debug, Default)]
+pub struct PaletteSmoother {
+  factor: f64,
+  threshold: f64,
+  smoothed: Vec<[f64; 3]>,
+}
+
+impl PaletteSmoother {
+  pub fn new(factor: f64, threshold: f64) -> PaletteSmoother {
+    PaletteSmoother {
+      factor,
+      threshold,
+      smoothed: Vec::new(),
+    }
+  }
+
+  /// Blend the palette into the running average and return the smoothed palette
+  pub fn smooth(&mut self, palette: &Palette) -> Palette {
+    let smoothed: Vec<[f64; 3]> = palette
+      .palette
+      .iter()
+      .map(|swatch| {
+        let color = [swatch[0] as f64, swatch[1] as f64, swatch[2] as f64];
+        let nearest = self
+          .smoothed
+          .iter()
+          .map(|previous| (previous, color_distance(previous, &color)))
+          .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        match nearest {
+          Some((previous, distance)) if distance <= self.threshold => [
+            previous[0] + (color[0] - previous[0]) * self.factor,
+            previous[1] + (color[1] - previous[1]) * self.factor,
+            previous[2] + (color[2] - previous[2]) * self.factor,
+          ],
+          _ => color,
+        }
+      })
+      .collect();
+
+    let colors = smoothed
+      .iter()
+      .map(|c| Rgb([c[0].round() as u8, c[1].round() as u8, c[2].round() as u8]))
+      .collect();
+    self.smoothed = smoothed;
+
+    Palette {
+      palette: colors,
+      pixel_counts: palette.pixel_counts.clone(),
+    }
+  }
+}
+
+fn color_distance(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+  ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
 impl fmt::Display for Palette {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
     let color_list = self
@@ -365,3 +757,65 @@ impl fmt::Display for Palette {
     write!(f, "Color Palette {{ {} }}", color_list)
   }
 }
+
+/// Swatches as `[r, g, b]` arrays, `image::Rgb` has no serde support of its own. Unset swatches
+/// may be left out entirely, as TOML does.
+#[cfg(feature = "serde")]
+mod swatch_serde {
+  use std::collections::BTreeMap;
+
+  use image::Rgb;
+  use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+  pub mod option {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(swatch: &Option<Rgb<u8>>, s: S) -> Result<S::Ok, S::Error> {
+      swatch.map(|swatch| swatch.0).serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Rgb<u8>>, D::Error> {
+      Ok(Option::<[u8; 3]>::deserialize(d)?.map(Rgb))
+    }
+  }
+
+  pub mod vec {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(swatches: &[Rgb<u8>], s: S) -> Result<S::Ok, S::Error> {
+      s.collect_seq(swatches.iter().map(|swatch| swatch.0))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<Rgb<u8>>, D::Error> {
+      Ok(
+        Vec::<[u8; 3]>::deserialize(d)?
+          .into_iter()
+          .map(Rgb)
+          .collect(),
+      )
+    }
+  }
+
+  /// Pixel counts as `[index, count]` pairs, formats like TOML only take string map keys
+  pub mod counts {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+      counts: &BTreeMap<usize, usize>,
+      s: S,
+    ) -> Result<S::Ok, S::Error> {
+      s.collect_seq(counts.iter().map(|(index, count)| [*index, *count]))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+      d: D,
+    ) -> Result<BTreeMap<usize, usize>, D::Error> {
+      Ok(
+        Vec::<[usize; 2]>::deserialize(d)?
+          .into_iter()
+          .map(|[index, count]| (index, count))
+          .collect(),
+      )
+    }
+  }
+}
@@ -0,0 +1,205 @@
+use image::{DynamicImage, Rgb, RgbImage};
+use mini_ambilight_bluetooth::capture::Bgra8;
+use mini_ambilight_bluetooth::sampling::{dominant_color_from_buffer, dominant_color_from_image};
+use mini_ambilight_bluetooth::vibrant::ToneRole;
+use mini_ambilight_bluetooth::ColorSamplingAlgorithm;
+
+/// Largest difference per channel, in 0...255 units, between the sampled and the expected color
+const TOLERANCE: f32 = 3.0;
+
+fn algorithms() -> Vec<(&'static str, ColorSamplingAlgorithm)> {
+  vec![
+    (
+      "squared_average",
+      ColorSamplingAlgorithm::SquaredAverage { sample_rate: 0.05 },
+    ),
+    (
+      "linear_average",
+      ColorSamplingAlgorithm::LinearAverage { sample_rate: 0.05 },
+    ),
+    (
+      "most_dominant",
+      ColorSamplingAlgorithm::MostDominant {
+        quality: 2,
+        sorted: false,
+        sort_window: 1,
+      },
+    ),
+    (
+      "weighted_palette",
+      ColorSamplingAlgorithm::WeightedPalette { swatches: 4 },
+    ),
+    (
+      "edge_average",
+      ColorSamplingAlgorithm::EdgeAverage {
+        border_fraction: 0.1,
+      },
+    ),
+    (
+      "k_means",
+      ColorSamplingAlgorithm::KMeans {
+        k: 4,
+        iterations: 10,
+        sample_rate: 0.05,
+      },
+    ),
+    ("vibrancy", ColorSamplingAlgorithm::Vibrancy),
+    (
+      "dual_tone",
+      ColorSamplingAlgorithm::DualTone {
+        role: ToneRole::Warm,
+      },
+    ),
+    (
+      "hue_histogram",
+      ColorSamplingAlgorithm::HueHistogram { bins: 36 },
+    ),
+  ]
+}
+
+/// Sample the fixture with every algorithm and compare against the expected color of each
+fn check(fixture: &str, expected: &[(&str, [u8; 3])]) {
+  let path = format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), fixture);
+  let image = image::open(&path).unwrap_or_else(|e| panic!("Loading {} failed: {}", path, e));
+  let mut failures = Vec::new();
+  for (name, algorithm) in algorithms() {
+    let expected = expected
+      .iter()
+      .find(|(algorithm, _)| *algorithm == name)
+      .unwrap_or_else(|| panic!("No expected color for {} on {}", name, fixture))
+      .1;
+    let color = dominant_color_from_image(&image, &algorithm).unwrap() * 255.0;
+    let delta = [color.x, color.y, color.z]
+      .iter()
+      .zip(expected)
+      .map(|(sampled, expected)| (sampled - expected as f32).abs())
+      .fold(0.0, f32::max);
+    if delta > TOLERANCE {
+      failures.push(format!(
+        "{}: expected {:?}, sampled [{:.1}, {:.1}, {:.1}]",
+        name, expected, color.x, color.y, color.z
+      ));
+    }
+  }
+  assert!(failures.is_empty(), "{}:\n{}", fixture, failures.join("\n"));
+}
+
+#[test]
+fn solid_red() {
+  check(
+    "solid_red.png",
+    &[
+      ("squared_average", [255, 0, 0]),
+      ("linear_average", [255, 0, 0]),
+      ("most_dominant", [252, 4, 4]),
+      ("weighted_palette", [255, 0, 0]),
+      ("edge_average", [255, 0, 0]),
+      ("k_means", [255, 0, 0]),
+      ("vibrancy", [255, 0, 0]),
+      ("dual_tone", [255, 0, 0]),
+      ("hue_histogram", [255, 0, 0]),
+    ],
+  );
+}
+
+#[test]
+fn gradient() {
+  check(
+    "gradient.png",
+    &[
+      ("squared_average", [141, 141, 141]),
+      ("linear_average", [145, 145, 145]),
+      ("most_dominant", [88, 88, 88]),
+      ("weighted_palette", [37, 37, 37]),
+      ("edge_average", [163, 163, 163]),
+      ("k_means", [208, 208, 208]),
+      ("vibrancy", [184, 184, 184]),
+      ("dual_tone", [248, 248, 248]),
+      ("hue_histogram", [145, 145, 145]),
+    ],
+  );
+}
+
+#[test]
+fn movie_still() {
+  check(
+    "movie_still.png",
+    &[
+      ("squared_average", [120, 72, 72]),
+      ("linear_average", [125, 74, 73]),
+      ("most_dominant", [58, 40, 58]),
+      ("weighted_palette", [67, 45, 63]),
+      ("edge_average", [93, 55, 74]),
+      ("k_means", [75, 54, 99]),
+      ("vibrancy", [195, 108, 67]),
+      ("dual_tone", [195, 108, 67]),
+      ("hue_histogram", [224, 114, 54]),
+    ],
+  );
+}
+
+/// A sky of several blues over a smaller mix of other colors samples as blue, where the average
+/// would be muddied by the rest
+#[test]
+fn hue_histogram_locks_onto_the_scene_hue() {
+  let image = RgbImage::from_fn(200, 100, |x, y| match (x, y) {
+    (_, 0..=59) => Rgb([40, 90 + (x % 40) as u8, 220 + (y % 30) as u8]),
+    (0..=99, _) => Rgb([200, 40, 30]),
+    _ => Rgb([60, 160, 50]),
+  });
+  let algorithm = ColorSamplingAlgorithm::HueHistogram { bins: 36 };
+  let color = dominant_color_from_image(&DynamicImage::ImageRgb8(image), &algorithm).unwrap();
+  assert!(color.z > 0.8 && color.x < 0.25, "{:?}", color);
+}
+
+/// The grid averages stay within the frame on sizes that don't divide evenly by the sample count,
+/// where rounding the grid step up would index past the last row, and on frames shorter than
+/// their size, which skip the missing samples.
+#[test]
+fn grid_averages_stay_in_bounds() {
+  let pixel = Bgra8 {
+    b: 30,
+    g: 90,
+    r: 200,
+    a: 255,
+  };
+  let sizes = [
+    (1366, 768, 0.05),
+    (1921, 1081, 0.05),
+    (7, 5, 0.9),
+    (3, 1, 1.0),
+    (1, 1, 0.01),
+  ];
+  for (width, height, sample_rate) in sizes {
+    for algorithm in [
+      ColorSamplingAlgorithm::SquaredAverage { sample_rate },
+      ColorSamplingAlgorithm::LinearAverage { sample_rate },
+    ] {
+      for len in [width * height, width * height - width / 2] {
+        let buffer = vec![pixel; len];
+        let color = dominant_color_from_buffer(&buffer, width, height, &algorithm).unwrap() * 255.0;
+        let delta = (color - glam::Vec3::new(200.0, 90.0, 30.0))
+          .abs()
+          .max_element();
+        assert!(
+          delta < 1.0,
+          "{:?} on {}x{} with {} pixels: {:?}",
+          algorithm,
+          width,
+          height,
+          len,
+          color
+        );
+      }
+    }
+  }
+}
+
+/// An empty frame has no hue to lock onto, the histogram keeps the previous color instead of
+/// dividing by a zero weight.
+#[test]
+fn hue_histogram_of_an_empty_frame_is_finite() {
+  let algorithm = ColorSamplingAlgorithm::HueHistogram { bins: 36 };
+  let color = dominant_color_from_buffer(&[], 0, 0, &algorithm).unwrap();
+  assert!(color.is_finite(), "{:?}", color);
+}
@@ -0,0 +1,50 @@
+use std::f32::consts::PI;
+
+use glam::Vec3;
+use mini_ambilight_bluetooth::audio::{AudioMapping, BandSplitter, Bands};
+
+const SAMPLE_RATE: u32 = 48000;
+
+/// One second of a full scale sine at `frequency` Hz, split into bands
+fn sine_bands(frequency: f32) -> Bands {
+  let samples: Vec<f32> = (0..SAMPLE_RATE)
+    .map(|i| (2.0 * PI * frequency * i as f32 / SAMPLE_RATE as f32).sin())
+    .collect();
+  let mut splitter = BandSplitter::new(SAMPLE_RATE);
+  splitter.push(&samples);
+  splitter.take()
+}
+
+#[test]
+fn tones_land_in_their_band() {
+  let bass = sine_bands(50.0);
+  assert!(bass.bass > 2.0 * bass.mid.max(bass.treble), "{:?}", bass);
+  let mid = sine_bands(1000.0);
+  assert!(mid.mid > 2.0 * mid.bass.max(mid.treble), "{:?}", mid);
+  let treble = sine_bands(12000.0);
+  assert!(
+    treble.treble > 2.0 * treble.bass.max(treble.mid),
+    "{:?}",
+    treble
+  );
+}
+
+#[test]
+fn take_resets_the_levels() {
+  let mut splitter = BandSplitter::new(SAMPLE_RATE);
+  splitter.push(&[1.0; 480]);
+  assert!(splitter.take().level() > 0.0);
+  assert_eq!(splitter.take(), Bands::default());
+}
+
+#[test]
+fn silence_is_black() {
+  let mappings = [
+    AudioMapping::Level { color: [255, 0, 0] },
+    AudioMapping::Hue,
+    AudioMapping::default(),
+  ];
+  for mapping in mappings {
+    assert_eq!(mapping.color(Bands::default(), 3.0), Vec3::ZERO);
+  }
+}
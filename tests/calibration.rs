@@ -0,0 +1,76 @@
+use glam::Vec3;
+use mini_ambilight_bluetooth::correction::{gamut_map, Calibration, NEUTRAL_KELVIN};
+use palette::{convert::IntoColorUnclamped, Oklch, Srgb};
+
+#[test]
+fn identity_leaves_colors_unchanged() {
+  let calibration = Calibration::default();
+  for rgb in [[0, 0, 0], [255, 255, 255], [12, 200, 99], [255, 1, 128]] {
+    assert_eq!(calibration.apply(rgb), rgb);
+  }
+}
+
+#[test]
+fn matrix_rows_mix_the_output_channels() {
+  // Halve green and move all of the red into blue
+  let calibration =
+    Calibration::from_rows([[0.0, 0.0, 0.0], [0.0, 0.5, 0.0], [1.0, 0.0, 1.0]], None);
+  assert_eq!(calibration.apply([100, 200, 50]), [0, 100, 150]);
+  // Sums past full scale are clamped
+  assert_eq!(calibration.apply([200, 0, 200]), [0, 0, 255]);
+}
+
+#[test]
+fn gamma_applies_per_channel() {
+  let identity = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+  let calibration = Calibration::from_rows(identity, Some([1.0, 2.0, 1.0]));
+  assert_eq!(calibration.apply([255, 128, 64]), [255, 64, 64]);
+}
+
+#[test]
+fn neutral_temperature_leaves_colors_unchanged() {
+  let calibration = Calibration::default().with_temperature(NEUTRAL_KELVIN);
+  for rgb in [[255, 255, 255], [12, 200, 99]] {
+    assert_eq!(calibration.apply(rgb), rgb);
+  }
+}
+
+#[test]
+fn temperature_tints_white() {
+  let warm = Calibration::default()
+    .with_temperature(3000.0)
+    .apply([255, 255, 255]);
+  assert!(
+    warm[0] == 255 && warm[1] < 255 && warm[2] < warm[1],
+    "{:?}",
+    warm
+  );
+  let cool = Calibration::default()
+    .with_temperature(10000.0)
+    .apply([255, 255, 255]);
+  assert!(cool[2] == 255 && cool[0] < 255, "{:?}", cool);
+}
+
+fn hue(color: Vec3) -> f32 {
+  let oklch: Oklch = Srgb::new(color.x, color.y, color.z).into_color_unclamped();
+  oklch.hue.to_positive_degrees()
+}
+
+#[test]
+fn gamut_map_keeps_the_hue() {
+  let color = Vec3::new(1.3, 0.4, -0.2);
+  let mapped = gamut_map(color);
+  assert!(
+    mapped.cmpge(Vec3::ZERO).all() && mapped.cmple(Vec3::ONE).all(),
+    "{:?}",
+    mapped
+  );
+  assert!((hue(mapped) - hue(color)).abs() < 0.5);
+  // Clamping each channel drifts towards yellow
+  assert!((hue(color.clamp(Vec3::ZERO, Vec3::ONE)) - hue(color)).abs() > 2.0);
+  // Colors in range stay as they are
+  assert_eq!(
+    gamut_map(Vec3::new(0.2, 0.5, 0.9)),
+    Vec3::new(0.2, 0.5, 0.9)
+  );
+}
@@ -0,0 +1,55 @@
+use mini_ambilight_bluetooth::capture::{CaptureError, CaptureTarget, OutputInfo};
+
+fn outputs() -> Vec<OutputInfo> {
+  let output = |index, name: &str, size| OutputInfo {
+    index,
+    name: name.to_string(),
+    device: Some(format!("\\\\.\\DISPLAY{}", index + 1)),
+    size,
+  };
+  vec![
+    output(0, "DELL U2720Q", (2560, 1440)),
+    output(1, "Generic PnP Monitor", (1920, 1080)),
+    output(2, "Generic PnP Monitor", (1920, 1080)),
+  ]
+}
+
+fn name(name: &str) -> CaptureTarget {
+  CaptureTarget::Name(name.to_string())
+}
+
+#[test]
+fn indices_are_taken_as_they_are() {
+  assert_eq!(CaptureTarget::Index(5).resolve(&[]).unwrap(), 5);
+}
+
+#[test]
+fn names_and_resolutions_select_their_output() {
+  let outputs = outputs();
+  assert_eq!(name("dell u2720q").resolve(&outputs).unwrap(), 0);
+  assert_eq!(name("\\\\.\\DISPLAY3").resolve(&outputs).unwrap(), 2);
+  assert_eq!(name("2560x1440").resolve(&outputs).unwrap(), 0);
+}
+
+#[test]
+fn no_or_several_matches_are_errors() {
+  let outputs = outputs();
+  match name("LG 27GL850").resolve(&outputs) {
+    Err(CaptureError::UnknownOutput { available, .. }) => {
+      assert!(
+        available.contains("0: DELL U2720Q 2560x1440"),
+        "{}",
+        available
+      );
+    }
+    other => panic!("expected an unknown output, got {:?}", other),
+  }
+  assert!(matches!(
+    name("1920x1080").resolve(&outputs),
+    Err(CaptureError::AmbiguousOutput { .. })
+  ));
+  assert!(matches!(
+    name("Generic PnP Monitor").resolve(&outputs),
+    Err(CaptureError::AmbiguousOutput { .. })
+  ));
+}
@@ -0,0 +1,35 @@
+use std::collections::BTreeMap;
+
+use image::Rgb;
+use mini_ambilight_bluetooth::vibrant::{ColorMemory, VibrancyConfig};
+use mini_ambilight_bluetooth::{Palette, Vibrancy};
+
+const RED: Rgb<u8> = Rgb([255, 0, 0]);
+const GREEN: Rgb<u8> = Rgb([0, 255, 0]);
+
+fn primary(red_pixels: usize, green_pixels: usize, memory: Option<&ColorMemory>) -> Rgb<u8> {
+  let palette = Palette {
+    palette: vec![RED, GREEN],
+    pixel_counts: BTreeMap::from([(0, red_pixels), (1, green_pixels)]),
+  };
+  Vibrancy::from_palette(&palette, &VibrancyConfig::default(), memory)
+    .primary
+    .unwrap()
+}
+
+/// Two equally vibrant and frequent swatches tie, the remembered one wins the tie
+#[test]
+fn memory_breaks_a_tie() {
+  assert_eq!(primary(50, 50, None), RED);
+  let mut memory = ColorMemory::new(0.95, 0.01);
+  memory.remember(GREEN);
+  assert_eq!(primary(50, 50, Some(&memory)), GREEN);
+}
+
+/// A clearly more frequent swatch wins however large the bias, the memory only decides ties
+#[test]
+fn memory_does_not_override_a_clear_winner() {
+  let mut memory = ColorMemory::new(0.95, 1.0);
+  memory.remember(GREEN);
+  assert_eq!(primary(90, 10, Some(&memory)), RED);
+}
@@ -0,0 +1,175 @@
+use std::sync::Arc;
+
+use glam::Vec3;
+use mini_ambilight_bluetooth::capture::Bgra8;
+use mini_ambilight_bluetooth::correction::{
+  ColorPreset, CorrectionSpace, FadeConfig, FadeSpace, Pipeline,
+};
+use mini_ambilight_bluetooth::sampling::frame_from_image;
+use mini_ambilight_bluetooth::{
+  compute_color, ColorConfig, ColorSamplingAlgorithm, LightColor, Sampler, SamplingConfig,
+};
+
+/// Share of the previous color kept every frame
+const KEEP: f32 = 0.5;
+
+fn config(fade: FadeConfig) -> ColorConfig {
+  ColorConfig {
+    pipeline: Pipeline::parse(&ColorPreset::Natural.transforms(), CorrectionSpace::Hsl).unwrap(),
+    fade,
+  }
+}
+
+fn fade() -> FadeConfig {
+  FadeConfig {
+    half_life_ms: 100.0,
+    motion_half_life_ms: None,
+    motion_full: 0.5,
+    space: FadeSpace::Rgb,
+    max_delta: None,
+    snap_threshold: None,
+  }
+}
+
+fn movie_still_frame() -> (Vec<Bgra8>, usize, usize) {
+  let path = format!(
+    "{}/tests/fixtures/movie_still.png",
+    env!("CARGO_MANIFEST_DIR")
+  );
+  frame_from_image(&image::open(path).unwrap())
+}
+
+/// The color of the movie still fading from gray, sampled with the defaults
+fn movie_still(previous: Vec3, config: &ColorConfig) -> Vec3 {
+  let (buffer, width, height) = movie_still_frame();
+  let mut sampler = Sampler::new(SamplingConfig::default());
+  let mut light = LightColor::new(1);
+  light.shown = previous;
+  compute_color(
+    &buffer,
+    (width, height),
+    &mut sampler,
+    &mut light,
+    KEEP,
+    config,
+  )
+  .unwrap()
+}
+
+fn assert_close(actual: Vec3, expected: Vec3) {
+  assert!(
+    (actual - expected).abs().max_element() < 1e-3,
+    "{:?} != {:?}",
+    actual,
+    expected
+  );
+}
+
+/// Locks in the output of the default pipeline, so refactoring the loop can't change it
+#[test]
+fn fades_halfway_to_the_corrected_color() {
+  let color = movie_still(Vec3::splat(0.5), &config(fade()));
+  assert_close(color, Vec3::new(0.6622, 0.4510, 0.3514));
+}
+
+#[test]
+fn snaps_and_limits_the_step() {
+  let snapping = config(FadeConfig {
+    snap_threshold: Some(40.0),
+    ..fade()
+  });
+  let limited = config(FadeConfig {
+    max_delta: Some(10.0),
+    ..fade()
+  });
+  // Far from gray, so it jumps to the corrected color
+  let target = movie_still(Vec3::splat(0.5), &snapping);
+  assert_close(target, Vec3::new(0.8245, 0.4020, 0.2029));
+  // The fade would move every channel further than 10/255
+  let step = 10.0 / 255.0;
+  assert_close(
+    movie_still(Vec3::splat(0.5), &limited),
+    Vec3::new(0.5 + step, 0.5 - step, 0.5 - step),
+  );
+}
+
+/// The light keeps its shown color and smoothing between calls, so repeated frames of the same
+/// scene settle on the corrected color
+#[test]
+fn state_carries_across_frames() {
+  let (buffer, width, height) = movie_still_frame();
+  let config = config(fade());
+  let mut sampler = Sampler::new(SamplingConfig::default());
+  let mut light = LightColor::new(4);
+  light.shown = Vec3::splat(0.5);
+  for _ in 0..30 {
+    compute_color(
+      &buffer,
+      (width, height),
+      &mut sampler,
+      &mut light,
+      KEEP,
+      &config,
+    )
+    .unwrap();
+  }
+  assert_close(light.corrected, Vec3::new(0.8245, 0.4020, 0.2029));
+  assert_close(light.shown, light.corrected);
+}
+
+/// From black to a white target the shown color climbs by at most `max_delta` per update, on
+/// every channel, until it arrives at white
+#[test]
+fn limits_every_step_from_black_to_white() {
+  const CAP: f32 = 10.0;
+  let config = ColorConfig {
+    pipeline: Pipeline::parse(&[], CorrectionSpace::Hsl).unwrap(),
+    fade: FadeConfig {
+      max_delta: Some(CAP),
+      ..fade()
+    },
+  };
+  let mut light = LightColor::new(1);
+  light.sampled = Vec3::ONE;
+  let mut updates = 0;
+  while light.shown != Vec3::ONE {
+    let previous = light.shown;
+    // Without the cap the fade would jump straight to white.
+    light.step(&config, 0.0, false);
+    let delta = (light.shown - previous) * 255.0;
+    assert!(
+      delta.max_element() <= CAP + 1e-3 && delta.min_element() > 0.0,
+      "update {} moved by {:?}",
+      updates,
+      delta
+    );
+    updates += 1;
+    assert!(updates <= 26, "white not reached after {} updates", updates);
+  }
+  assert_eq!(updates, 26);
+}
+
+#[test]
+fn samplers_share_one_pool() {
+  let (buffer, width, height) = movie_still_frame();
+  let config = SamplingConfig {
+    algorithm: ColorSamplingAlgorithm::SquaredAverage { sample_rate: 0.5 },
+    ..SamplingConfig::default()
+  };
+  let expected = Sampler::new(config).sample(&buffer, width, height).unwrap();
+
+  let pool = Arc::new(
+    rayon::ThreadPoolBuilder::new()
+      .num_threads(2)
+      .build()
+      .unwrap(),
+  );
+  let mut samplers: Vec<_> = (0..3)
+    .map(|_| Sampler::new(config).with_pool(pool.clone()))
+    .collect();
+  // The samplers hold on to the one pool instead of starting their own threads.
+  assert_eq!(Arc::strong_count(&pool), 4);
+  for sampler in &mut samplers {
+    assert_eq!(sampler.sample(&buffer, width, height).unwrap(), expected);
+  }
+}
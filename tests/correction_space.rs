@@ -0,0 +1,33 @@
+use glam::Vec3;
+use mini_ambilight_bluetooth::correction::{CorrectionSpace, Pipeline, TransformSpec};
+
+fn pipeline(name: &str, params: &[f32], space: CorrectionSpace) -> Pipeline {
+  Pipeline::parse(&[TransformSpec::new(name, params)], space).unwrap()
+}
+
+fn assert_close(actual: Vec3, expected: Vec3) {
+  assert!(
+    (actual - expected).abs().max_element() < 1e-4,
+    "{:?} != {:?}",
+    actual,
+    expected
+  );
+}
+
+/// Halving the saturation of pure red keeps its brightest channel in HSV but dims it in HSL
+#[test]
+fn hsv_desaturates_without_dimming() {
+  let red = Vec3::X;
+  let hsl = pipeline("saturation", &[0.5, 1.0], CorrectionSpace::Hsl).apply(red);
+  let hsv = pipeline("saturation", &[0.5, 1.0], CorrectionSpace::Hsv).apply(red);
+  assert_close(hsl, Vec3::new(0.75, 0.25, 0.25));
+  assert_close(hsv, Vec3::new(1.0, 0.5, 0.5));
+}
+
+/// `lightness` sets the HSV value, the brightest channel, keeping hue and saturation
+#[test]
+fn hsv_lightness_targets_the_value() {
+  let color = Vec3::new(0.4, 0.2, 0.1);
+  let result = pipeline("lightness", &[0.8, 1.0], CorrectionSpace::Hsv).apply(color);
+  assert_close(result, color * 2.0);
+}
@@ -0,0 +1,63 @@
+use color_thief::Color;
+use mini_ambilight_bluetooth::dominant::{sort_key, SortKeyAverager};
+
+/// White and near black are both grey with a key of 0, computing them must not overflow as the
+/// `u8` products would
+#[test]
+fn sort_key_of_extreme_greys() {
+  assert_eq!(sort_key(&Color::new(0xFF, 0xFF, 0xFF)), 0.0);
+  assert_eq!(sort_key(&Color::new(0x01, 0x01, 0x01)), 0.0);
+  assert_eq!(sort_key(&Color::new(0, 0, 0)), 0.0);
+}
+
+/// The palette orders by `(max² - min²) / max`: greys first, saturated primaries last
+#[test]
+fn sort_key_follows_the_documented_metric() {
+  let mut palette = vec![
+    Color::new(0xFF, 0x00, 0x00),
+    Color::new(0xFF, 0xFF, 0xFF),
+    Color::new(200, 100, 0),
+    Color::new(0x01, 0x01, 0x01),
+    Color::new(100, 50, 50),
+  ];
+  for color in &palette {
+    let max = color.r.max(color.g).max(color.b) as f32;
+    let min = color.r.min(color.g).min(color.b) as f32;
+    let expected = (max * max - min * min) / max;
+    assert!((sort_key(color) - expected).abs() < 1e-3, "{:?}", color);
+  }
+  palette.sort_by(|a, b| sort_key(a).total_cmp(&sort_key(b)));
+  assert_eq!(
+    palette,
+    vec![
+      Color::new(0xFF, 0xFF, 0xFF),
+      Color::new(0x01, 0x01, 0x01),
+      Color::new(100, 50, 50),
+      Color::new(200, 100, 0),
+      Color::new(0xFF, 0x00, 0x00),
+    ]
+  );
+}
+
+/// A red swatch whose key jitters around that of a steady blue one flips the per-frame pick, the
+/// averaged keys stay with the red that is lower on average
+#[test]
+fn averaged_keys_do_not_oscillate_on_near_ties() {
+  // Keys 99 and 102 against 101
+  let red = [Color::new(99, 0, 0), Color::new(102, 0, 0)];
+  let blue = Color::new(0, 0, 101);
+  let mut single = SortKeyAverager::default();
+  let mut averaged = SortKeyAverager::default();
+  let (mut single_picks, mut averaged_picks) = (Vec::new(), Vec::new());
+  for frame in 0..10 {
+    let palette = [red[frame % 2], blue];
+    single_picks.push(single.select(&palette, 1).unwrap());
+    averaged_picks.push(averaged.select(&palette, 4).unwrap());
+  }
+  assert!(single_picks.contains(&blue));
+  assert!(
+    averaged_picks.iter().all(|pick| *pick != blue),
+    "{:?}",
+    averaged_picks
+  );
+}
@@ -0,0 +1,44 @@
+use std::collections::BTreeMap;
+
+use image::{DynamicImage, Rgb, RgbImage};
+use mini_ambilight_bluetooth::vibrant::{DualTone, ToneRole};
+use mini_ambilight_bluetooth::Palette;
+
+/// A frame of orange on the left and blue on the right splits into an orange warm and a blue cool
+/// tone
+#[test]
+fn warm_and_cool_halves_split() {
+  let orange = Rgb([230, 120, 20]);
+  let blue = Rgb([20, 60, 220]);
+  let image = RgbImage::from_fn(64, 32, |x, _| if x < 32 { orange } else { blue });
+  let tone = DualTone::new(&DynamicImage::ImageRgb8(image));
+  let near = |color: Option<Rgb<u8>>, expected: Rgb<u8>| {
+    let color = color.unwrap();
+    (0..3).all(|c| (color[c] as i32 - expected[c] as i32).abs() <= 8)
+  };
+  assert!(near(tone.get(ToneRole::Warm), orange), "{:?}", tone);
+  assert!(near(tone.get(ToneRole::Cool), blue), "{:?}", tone);
+}
+
+/// A color held twice counts the pixels of both entries
+#[test]
+fn repeated_colors_count_together() {
+  let blue = Rgb([0, 0, 255]);
+  let azure = Rgb([0, 128, 255]);
+  let palette = Palette {
+    palette: vec![blue, azure, blue],
+    pixel_counts: BTreeMap::from([(0, 20), (1, 30), (2, 20)]),
+  };
+  assert_eq!(DualTone::from_palette(&palette).cool, Some(blue));
+}
+
+/// A frame of a single hue has no cool tone, so both roles fall back to the same color
+#[test]
+fn single_hue_gives_one_color_for_both_roles() {
+  let red = Rgb([220, 40, 30]);
+  let dark_red = Rgb([120, 20, 15]);
+  let image = RgbImage::from_fn(64, 32, |x, _| if x < 40 { red } else { dark_red });
+  let tone = DualTone::new(&DynamicImage::ImageRgb8(image));
+  assert!(tone.warm.is_some(), "{:?}", tone);
+  assert_eq!(tone.get(ToneRole::Warm), tone.get(ToneRole::Cool));
+}
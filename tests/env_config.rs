@@ -0,0 +1,212 @@
+use mini_ambilight_bluetooth::env_config::{self, ResolveError};
+use serde::Deserialize;
+use toml::value::{Table, Value};
+
+/// Keys of the configuration the variables below may set
+const KNOWN: &[&str] = &[
+  "brightness",
+  "fps",
+  "mqtt",
+  "light_mac",
+  "boot_flash_color",
+  "auto_brightness",
+  "gamma",
+  "status_addr",
+];
+
+fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+  pairs
+    .iter()
+    .map(|(name, value)| (name.to_string(), value.to_string()))
+    .collect()
+}
+
+fn args(args: &[&str]) -> Vec<String> {
+  args.iter().map(|arg| arg.to_string()).collect()
+}
+
+/// Set the keys of the `AMBILIGHT_*` variables among `vars` in `table`
+fn apply(table: &mut Table, vars: Vec<(String, String)>) -> Result<(), String> {
+  for item in env_config::from_env(vars, KNOWN)? {
+    item.apply(table)?;
+  }
+  Ok(())
+}
+
+/// A configuration rejecting unknown keys and brightness over 100, like the real one
+#[derive(Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Settings {
+  brightness: u8,
+  fps: u32,
+}
+
+impl Settings {
+  fn check(&self) -> Result<(), String> {
+    if self.brightness > 100 {
+      return Err(format!(
+        "brightness must be in 0...100, got {}",
+        self.brightness
+      ));
+    }
+    Ok(())
+  }
+}
+
+/// `Config::load` in short: the file, the variables on top and the command line above both
+fn load(file: &str, env: &[(&str, &str)], cli: &[&str]) -> Result<Settings, ResolveError> {
+  let table: Table = toml::from_str(file).unwrap();
+  let known = env_config::field_names::<Settings>();
+  let mut overrides = env_config::from_env(vars(env), known).unwrap();
+  overrides.extend(env_config::from_args(&args(cli), &[("--brightness", "brightness")]).unwrap());
+  env_config::resolve(&table, &overrides, Settings::check)
+}
+
+#[test]
+fn environment_overrides_the_file() {
+  let mut table: Table = toml::from_str(
+    r#"
+      brightness = 50
+      fps = 30
+      [mqtt]
+      host = "file"
+      topic = "ambilight/color"
+    "#,
+  )
+  .unwrap();
+  apply(
+    &mut table,
+    vars(&[
+      ("AMBILIGHT_BRIGHTNESS", "80"),
+      ("AMBILIGHT_MQTT__HOST", "env"),
+      ("AMBILIGHT_LIGHT_MAC", "FF:FF:3A:00:02:8F"),
+      ("PATH", "/usr/bin"),
+    ]),
+  )
+  .unwrap();
+
+  assert_eq!(table["brightness"], Value::Integer(80));
+  // Keys without a variable keep the value of the file, and unset ones their default.
+  assert_eq!(table["fps"], Value::Integer(30));
+  assert_eq!(table["mqtt"]["host"], Value::String("env".to_string()));
+  assert_eq!(
+    table["mqtt"]["topic"],
+    Value::String("ambilight/color".to_string())
+  );
+  assert_eq!(
+    table["light_mac"],
+    Value::String("FF:FF:3A:00:02:8F".to_string())
+  );
+  assert_eq!(table.len(), 4);
+}
+
+#[test]
+fn values_are_read_as_toml() {
+  let mut table = Table::new();
+  apply(
+    &mut table,
+    vars(&[
+      ("AMBILIGHT_BOOT_FLASH_COLOR", "[255, 0, 0]"),
+      ("AMBILIGHT_AUTO_BRIGHTNESS", "true"),
+      ("AMBILIGHT_GAMMA", "2.2"),
+      ("AMBILIGHT_STATUS_ADDR", "\"127.0.0.1:9899\""),
+    ]),
+  )
+  .unwrap();
+
+  assert_eq!(
+    table["boot_flash_color"],
+    Value::Array(vec![
+      Value::Integer(255),
+      Value::Integer(0),
+      Value::Integer(0)
+    ])
+  );
+  assert_eq!(table["auto_brightness"], Value::Boolean(true));
+  assert_eq!(table["gamma"], Value::Float(2.2));
+  assert_eq!(
+    table["status_addr"],
+    Value::String("127.0.0.1:9899".to_string())
+  );
+}
+
+#[test]
+fn stepping_into_a_value_is_an_error() {
+  let mut table: Table = toml::from_str("brightness = 50").unwrap();
+  assert!(apply(&mut table, vars(&[("AMBILIGHT_BRIGHTNESS__MAX", "1")])).is_err());
+  assert!(apply(&mut table, vars(&[("AMBILIGHT_", "1")])).is_err());
+}
+
+#[test]
+fn command_line_overrides_the_environment() {
+  let env = [("AMBILIGHT_BRIGHTNESS", "80"), ("AMBILIGHT_FPS", "60")];
+  assert_eq!(
+    load("brightness = 50\nfps = 30", &env, &["--brightness", "20"]),
+    Ok(Settings {
+      brightness: 20,
+      fps: 60
+    })
+  );
+  assert_eq!(
+    load("brightness = 50\nfps = 30", &env, &[]),
+    Ok(Settings {
+      brightness: 80,
+      fps: 60
+    })
+  );
+  // A valid flag also replaces an invalid variable
+  let env = [("AMBILIGHT_BRIGHTNESS", "150")];
+  assert_eq!(
+    load("", &env, &["--brightness", "20"]).map(|settings| settings.brightness),
+    Ok(20)
+  );
+  assert!(
+    env_config::from_args(&args(&["--brightness"]), &[("--brightness", "brightness")]).is_err()
+  );
+}
+
+#[test]
+fn unknown_variables_are_ignored() {
+  let env = [("AMBILIGHT_FOO", "1"), ("AMBILIGHT_BRIGHTNESS", "80")];
+  assert_eq!(
+    load("", &env, &[]),
+    Ok(Settings {
+      brightness: 80,
+      fps: 0
+    })
+  );
+  assert_eq!(
+    env_config::field_names::<Settings>(),
+    &["brightness", "fps"]
+  );
+}
+
+#[test]
+fn errors_name_the_source_at_fault() {
+  let env = [("AMBILIGHT_FPS", "fast")];
+  match load("brightness = 50", &env, &[]) {
+    Err(ResolveError::Override { source, .. }) => assert_eq!(source, "AMBILIGHT_FPS"),
+    other => panic!("{:?}", other),
+  }
+  // Only rejected by the check
+  match load("", &[("AMBILIGHT_BRIGHTNESS", "150")], &[]) {
+    Err(ResolveError::Override { source, error }) => {
+      assert_eq!(source, "AMBILIGHT_BRIGHTNESS");
+      assert_eq!(error, "brightness must be in 0...100, got 150");
+    }
+    other => panic!("{:?}", other),
+  }
+  match load("fps = 30", &[], &["--brightness", "200"]) {
+    Err(ResolveError::Override { source, .. }) => assert_eq!(source, "--brightness"),
+    other => panic!("{:?}", other),
+  }
+  // The file is blamed for its own mistakes, even with valid variables on top
+  assert!(matches!(
+    load("fps = \"fast\"", &[("AMBILIGHT_BRIGHTNESS", "80")], &[]),
+    Err(ResolveError::File(_))
+  ));
+  assert!(matches!(
+    load("colour = 1", &[], &[]),
+    Err(ResolveError::File(_))
+  ));
+}
@@ -0,0 +1,52 @@
+use std::time::Duration;
+
+use glam::Vec3;
+use mini_ambilight_bluetooth::correction::{FadeConfig, FadeSpace};
+
+const FRAME: Duration = Duration::from_millis(16);
+
+fn fade(motion_half_life_ms: Option<f32>) -> FadeConfig {
+  FadeConfig {
+    half_life_ms: 200.0,
+    motion_half_life_ms,
+    motion_full: 0.5,
+    space: FadeSpace::Rgb,
+    max_delta: None,
+    snap_threshold: None,
+  }
+}
+
+/// After one half-life of frames the color has covered half the way to its target
+#[test]
+fn half_life_halves_the_distance() {
+  let fade = fade(None);
+  let frames = (0..25).fold(Vec3::ZERO, |color, _| {
+    fade.step(color, Vec3::ONE, fade.keep(0.0, Duration::from_millis(8)))
+  });
+  assert!((frames.x - 0.5).abs() < 1e-3, "{:?}", frames);
+}
+
+/// With a shorter motion half-life a moving screen is followed faster than a still one, fully so
+/// from `motion_full` on
+#[test]
+fn motion_shortens_the_fade() {
+  let adaptive = fade(Some(50.0));
+  let still = adaptive.keep(0.0, FRAME);
+  let some_motion = adaptive.keep(0.25, FRAME);
+  let full_motion = adaptive.keep(0.5, FRAME);
+  assert!(still > some_motion && some_motion > full_motion);
+  assert_eq!(adaptive.keep(1.0, FRAME), full_motion);
+  // A quarter of the half-life keeps the fourth power per frame
+  assert!((full_motion - still.powf(4.0)).abs() < 1e-5);
+  // Without a motion half-life motion changes nothing
+  assert_eq!(fade(None).keep(1.0, FRAME), still);
+}
+
+#[test]
+fn zero_half_life_jumps() {
+  let fade = FadeConfig {
+    half_life_ms: 0.0,
+    ..fade(None)
+  };
+  assert_eq!(fade.keep(0.0, FRAME), 0.0);
+}
@@ -0,0 +1,57 @@
+use mini_ambilight_bluetooth::capture::Bgra8;
+use mini_ambilight_bluetooth::frame_diff::FrameDiff;
+
+const WIDTH: usize = 320;
+const HEIGHT: usize = 180;
+
+/// A dim horizontal gradient from 20 to 60, lifted by `lift` on every channel
+fn gradient(lift: u8) -> Vec<Bgra8> {
+  (0..WIDTH * HEIGHT)
+    .map(|i| {
+      let value = (20 + 40 * (i % WIDTH) / WIDTH) as u8 + lift;
+      Bgra8 {
+        b: value,
+        g: value,
+        r: value,
+        a: 255,
+      }
+    })
+    .collect()
+}
+
+fn diff(pixel_delta: u8) -> FrameDiff {
+  FrameDiff {
+    density: 8,
+    pixel_delta,
+    min_changed: 4,
+  }
+}
+
+/// A slow fade moving the whole gradient by 3 units per frame is seen by a pixel delta below
+/// that and missed by one above it
+#[test]
+fn subtle_fade_depends_on_the_pixel_delta() {
+  let (before, after) = (gradient(0), gradient(3));
+  for (pixel_delta, changed) in [(2, true), (4, false)] {
+    let diff = diff(pixel_delta);
+    let previous = diff.sample(&before, WIDTH, HEIGHT);
+    let current = diff.sample(&after, WIDTH, HEIGHT);
+    assert_eq!(
+      diff.changed(&previous, &current),
+      changed,
+      "pixel delta {}",
+      pixel_delta
+    );
+    let motion = if changed { 1.0 } else { 0.0 };
+    assert_eq!(diff.motion(&previous, &current), motion);
+  }
+}
+
+/// A still gradient never counts as changed, however sensitive the delta
+#[test]
+fn still_gradient_is_unchanged() {
+  let frame = gradient(0);
+  let diff = diff(0);
+  let samples = diff.sample(&frame, WIDTH, HEIGHT);
+  assert!(!diff.changed(&samples, &samples));
+}
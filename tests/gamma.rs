@@ -0,0 +1,79 @@
+use glam::Vec3;
+use mini_ambilight_bluetooth::correction::{
+  Calibration, CorrectionSpace, GammaLut, Pipeline, TransformSpec,
+};
+
+fn gamma(params: &[f32]) -> Result<Pipeline, String> {
+  Pipeline::parse(&[TransformSpec::new("gamma", params)], CorrectionSpace::Hsl)
+}
+
+/// Each channel is raised to the power of one over its own gamma
+#[test]
+fn gamma_per_channel() {
+  let color = gamma(&[1.0, 2.0, 0.5]).unwrap().apply(Vec3::splat(0.25));
+  assert!(
+    (color - Vec3::new(0.25, 0.5, 0.0625)).abs().max_element() < 1e-3,
+    "{:?}",
+    color
+  );
+}
+
+/// A single gamma applies to all three channels, the same as repeating it
+#[test]
+fn scalar_gamma_expands_to_all_channels() {
+  let color = Vec3::new(0.1, 0.5, 0.9);
+  let scalar = gamma(&[2.2]).unwrap().apply(color);
+  let channels = gamma(&[2.2, 2.2, 2.2]).unwrap().apply(color);
+  assert_eq!(scalar, channels);
+  assert!(gamma(&[1.0, 2.0]).is_err());
+  assert!(gamma(&[1.0, 0.0, 1.0]).is_err());
+}
+
+/// The table interpolates `powf` closely, except within the first steps above black where the
+/// curve is steepest
+#[test]
+fn lookup_table_matches_powf() {
+  for gamma in [0.45, 1.8, 2.2, 2.8] {
+    let lut = GammaLut::new(gamma);
+    for step in 1..=1000 {
+      let value = step as f32 / 1000.0;
+      if value < 0.02 {
+        continue;
+      }
+      let exact = value.powf(1.0 / gamma);
+      let delta = (lut.apply(value) - exact).abs();
+      assert!(
+        delta < 2e-3,
+        "gamma {} at {}: {} instead of {}",
+        gamma,
+        value,
+        lut.apply(value),
+        exact
+      );
+    }
+  }
+}
+
+/// Calibration gamma goes through the lookup table and stays within a byte of `powf`
+#[test]
+fn calibration_gamma_matches_powf() {
+  let gamma = [2.2, 1.0, 0.6];
+  let calibration = Calibration::from_rows(
+    [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+    Some(gamma),
+  );
+  for byte in 0..=255u8 {
+    let sent = calibration.apply([byte; 3]);
+    for channel in 0..3 {
+      let exact = (byte as f32 / 255.0).powf(gamma[channel]) * 255.0;
+      assert!(
+        (sent[channel] as f32 - exact).abs() <= 1.0,
+        "gamma {} of {}: {} instead of {}",
+        gamma[channel],
+        byte,
+        sent[channel],
+        exact
+      );
+    }
+  }
+}
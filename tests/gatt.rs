@@ -0,0 +1,68 @@
+use mini_ambilight_bluetooth::gatt::{
+  find_control, uuid_from_u16, CharacteristicInfo, ControlCharacteristic, ControlLookupError,
+};
+
+fn info(short: u16, write: bool, write_without_response: bool) -> CharacteristicInfo {
+  CharacteristicInfo {
+    uuid: uuid_from_u16(short),
+    write,
+    write_without_response,
+  }
+}
+
+#[test]
+fn no_characteristics_is_its_own_error() {
+  assert_eq!(
+    find_control(&[], uuid_from_u16(0xFFF1)),
+    Err(ControlLookupError::NoCharacteristics)
+  );
+}
+
+#[test]
+fn configured_uuid_is_used() {
+  let chars = [info(0xFFF2, true, true), info(0xFFF1, true, false)];
+  assert_eq!(
+    find_control(&chars, uuid_from_u16(0xFFF1)),
+    Ok(ControlCharacteristic::Configured(1))
+  );
+}
+
+#[test]
+fn clones_get_the_most_likely_writable_characteristic() {
+  let chars = [
+    info(0x2A19, false, false),
+    info(0x1234, true, false),
+    info(0xFFE1, true, false),
+    info(0xFFE9, true, true),
+  ];
+  // Nothing from the 0xFFF0 family, so write without response decides.
+  assert_eq!(
+    find_control(&chars, uuid_from_u16(0xFFF1)),
+    Ok(ControlCharacteristic::Guessed(3))
+  );
+
+  let chars = [info(0x1234, true, true), info(0xFFF3, true, false)];
+  assert_eq!(
+    find_control(&chars, uuid_from_u16(0xFFF1)),
+    Ok(ControlCharacteristic::Guessed(1))
+  );
+}
+
+#[test]
+fn read_only_lights_miss_the_characteristic() {
+  let chars = [info(0x2A19, false, false)];
+  assert_eq!(
+    find_control(&chars, uuid_from_u16(0xFFF1)),
+    Err(ControlLookupError::CharacteristicMissing(uuid_from_u16(
+      0xFFF1
+    )))
+  );
+}
+
+#[test]
+fn short_uuids_use_the_bluetooth_base() {
+  assert_eq!(
+    uuid_from_u16(0xFFF1).to_string(),
+    "0000fff1-0000-1000-8000-00805f9b34fb"
+  );
+}
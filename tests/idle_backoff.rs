@@ -0,0 +1,32 @@
+use std::time::Duration;
+
+use mini_ambilight_bluetooth::frame_diff::IdleBackoff;
+
+fn backoff() -> IdleBackoff {
+  IdleBackoff::new(3, Duration::from_millis(10), Duration::from_millis(25))
+}
+
+#[test]
+fn ramps_up_after_static_frames_up_to_the_maximum() {
+  let mut backoff = backoff();
+  let mut delays = Vec::new();
+  for _ in 0..7 {
+    backoff.frame(false);
+    delays.push(backoff.delay().as_millis());
+  }
+  assert_eq!(delays, [0, 0, 0, 10, 20, 25, 25]);
+}
+
+#[test]
+fn a_changed_frame_returns_to_full_rate() {
+  let mut backoff = backoff();
+  for _ in 0..10 {
+    backoff.frame(false);
+  }
+  backoff.frame(true);
+  assert_eq!(backoff.delay(), Duration::ZERO);
+  // A zero maximum never backs off
+  let mut disabled = IdleBackoff::new(0, Duration::from_millis(10), Duration::ZERO);
+  disabled.frame(false);
+  assert_eq!(disabled.delay(), Duration::ZERO);
+}
@@ -0,0 +1,36 @@
+use glam::Vec3;
+use mini_ambilight_bluetooth::kmeans::largest_cluster;
+
+/// A blob of 30 points around 20 and one of 10 points around 80, jittered by up to 2
+fn two_blobs() -> Vec<Vec3> {
+  let jitter = |i: usize| Vec3::new((i % 5) as f32, (i % 3) as f32, (i % 4) as f32) * 0.5 - 1.0;
+  (0..30)
+    .map(|i| Vec3::splat(20.0) + jitter(i))
+    .chain((0..10).map(|i| Vec3::splat(80.0) + jitter(i)))
+    .collect()
+}
+
+/// The seed is fixed, so the same points always give the same centroid
+#[test]
+fn runs_are_deterministic() {
+  let points = two_blobs();
+  assert_eq!(
+    largest_cluster(&points, 4, 10),
+    largest_cluster(&points, 4, 10)
+  );
+}
+
+#[test]
+fn largest_blob_wins() {
+  let centroid = largest_cluster(&two_blobs(), 2, 10).unwrap();
+  assert!(
+    (centroid - Vec3::splat(20.0)).abs().max_element() < 1.0,
+    "{:?}",
+    centroid
+  );
+}
+
+#[test]
+fn no_points_no_cluster() {
+  assert_eq!(largest_cluster(&[], 2, 10), None);
+}
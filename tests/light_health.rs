@@ -0,0 +1,128 @@
+use std::io;
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use mini_ambilight_bluetooth::health::{self, HealthState, LightHealth, RetryPolicy, Write};
+use mini_ambilight_bluetooth::light::{LightDriver, LightError};
+
+const FRAME: Duration = Duration::from_millis(100);
+
+const POLICY: RetryPolicy = RetryPolicy {
+  max_failures: 2,
+  retry_secs: 1.0,
+  max_retry_secs: 4.0,
+};
+
+/// Records every color it is asked to write, failing them while `fail` is set
+#[derive(Default)]
+struct MockLight {
+  tried: Mutex<Vec<[u8; 3]>>,
+  fail: AtomicBool,
+}
+
+impl MockLight {
+  fn tried(&self) -> Vec<[u8; 3]> {
+    self.tried.lock().unwrap().clone()
+  }
+}
+
+#[async_trait]
+impl LightDriver for MockLight {
+  async fn set_color(&self, rgb: [u8; 3], _brightness: u8) -> Result<(), LightError> {
+    self.tried.lock().unwrap().push(rgb);
+    if self.fail.load(Ordering::SeqCst) {
+      return Err(LightError::Udp(io::Error::other("light gone")));
+    }
+    Ok(())
+  }
+}
+
+fn write<'a>(light: &'a MockLight, health: &'a mut LightHealth, frame: u8) -> Write<'a> {
+  Write {
+    driver: light,
+    health,
+    rgb: [frame, 0, 0],
+    brightness: 100,
+  }
+}
+
+/// Write `frames` to both lights, one `FRAME` apart, with the frame number as red
+async fn run(
+  (steady, steady_health): (&MockLight, &mut LightHealth),
+  (flaky, flaky_health): (&MockLight, &mut LightHealth),
+  frames: RangeInclusive<u8>,
+) {
+  for frame in frames {
+    let results = health::write_active([
+      ("steady", write(steady, steady_health, frame)),
+      ("flaky", write(flaky, flaky_health, frame)),
+    ])
+    .await;
+    for (light, result) in results {
+      assert_eq!(result.is_ok(), light == "steady", "frame {}", frame);
+    }
+    tokio::time::advance(FRAME).await;
+  }
+}
+
+#[tokio::test]
+async fn failing_light_is_dropped_without_holding_up_the_other() {
+  tokio::time::pause();
+  let (steady, flaky) = (MockLight::default(), MockLight::default());
+  let (mut steady_health, mut flaky_health) = (LightHealth::new(POLICY), LightHealth::new(POLICY));
+  flaky.fail.store(true, Ordering::SeqCst);
+
+  // Two failures drop the flaky light, its retry is due a second later, at frame 12.
+  run(
+    (&steady, &mut steady_health),
+    (&flaky, &mut flaky_health),
+    1..=11,
+  )
+  .await;
+  assert_eq!(flaky.tried(), vec![[1, 0, 0], [2, 0, 0]]);
+  assert_eq!(flaky_health.state(), HealthState::Dropped);
+  assert_eq!(flaky_health.failures(), 2);
+
+  // The retry fails too, doubling the wait to two seconds, so the next one is at frame 32.
+  run(
+    (&steady, &mut steady_health),
+    (&flaky, &mut flaky_health),
+    12..=31,
+  )
+  .await;
+  assert_eq!(flaky.tried(), vec![[1, 0, 0], [2, 0, 0], [12, 0, 0]]);
+
+  // The steady light got every frame meanwhile.
+  assert_eq!(
+    steady.tried(),
+    (1..=31).map(|frame| [frame, 0, 0]).collect::<Vec<_>>()
+  );
+  assert_eq!(steady_health.state(), HealthState::Healthy);
+
+  // Back once a retry goes through.
+  flaky.fail.store(false, Ordering::SeqCst);
+  let results = health::write_active([("flaky", write(&flaky, &mut flaky_health, 32))]).await;
+  assert!(results[0].1.is_ok());
+  assert_eq!(flaky.tried().last(), Some(&[32, 0, 0]));
+  assert_eq!(flaky_health.state(), HealthState::Healthy);
+  assert_eq!(flaky_health.failures(), 0);
+}
+
+#[test]
+fn failures_below_the_limit_keep_the_light() {
+  let mut health = LightHealth::new(POLICY);
+  health.record_failure();
+  assert_eq!(health.state(), HealthState::Failing);
+  assert!(health.is_active());
+  health.record_success();
+  assert_eq!(health.state(), HealthState::Healthy);
+}
+
+#[test]
+fn retry_delay_doubles_up_to_the_maximum() {
+  let delays: Vec<_> = (0..5).map(|retries| POLICY.delay(retries)).collect();
+  assert_eq!(delays, [1, 2, 4, 4, 4].map(Duration::from_secs).to_vec());
+}
@@ -0,0 +1,135 @@
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use btleplug::api::WriteType;
+use mini_ambilight_bluetooth::light::{
+  BleLightDriver, CommandWriter, LightDriver, LightProtocol, TemplateToken, WhiteMode,
+  WriteOptions, FULL_BRIGHTNESS,
+};
+
+const COLOR: [u8; 3] = [200, 150, 100];
+
+fn rgbw(white: WhiteMode) -> LightProtocol {
+  LightProtocol::Rgbw { white }
+}
+
+#[test]
+fn rgb_and_rgbw_byte_layouts() {
+  assert_eq!(
+    LightProtocol::Rgb.command(COLOR, FULL_BRIGHTNESS),
+    vec![0x01, 200, 150, 100, FULL_BRIGHTNESS]
+  );
+  // The gray part, min(r, g, b), moves to the white LED
+  assert_eq!(
+    rgbw(WhiteMode::Extract).command(COLOR, FULL_BRIGHTNESS),
+    vec![0x01, 100, 50, 0, 100, FULL_BRIGHTNESS]
+  );
+  assert_eq!(
+    rgbw(WhiteMode::Add).command(COLOR, FULL_BRIGHTNESS),
+    vec![0x01, 200, 150, 100, 100, FULL_BRIGHTNESS]
+  );
+  assert_eq!(
+    rgbw(WhiteMode::Off).command(COLOR, FULL_BRIGHTNESS),
+    vec![0x01, 200, 150, 100, 0, FULL_BRIGHTNESS]
+  );
+  // Pure white is carried by the white LED alone
+  assert_eq!(
+    rgbw(WhiteMode::Extract).command([255; 3], 50),
+    vec![0x01, 0, 0, 0, 255, 50]
+  );
+}
+
+#[test]
+fn custom_template_with_checksum() {
+  let protocol: LightProtocol = toml::from_str(
+    r#"
+      type = "custom"
+      command_template = ["0x56", "{r}", "{g}", "{b}", "{checksum}"]
+    "#,
+  )
+  .unwrap();
+  assert_eq!(
+    protocol,
+    LightProtocol::Custom {
+      command_template: vec![
+        TemplateToken::Byte(0x56),
+        TemplateToken::Red,
+        TemplateToken::Green,
+        TemplateToken::Blue,
+        TemplateToken::Checksum,
+      ]
+    }
+  );
+  assert_eq!(
+    protocol.command([0x10, 0x20, 0x30], FULL_BRIGHTNESS),
+    vec![0x56, 0x10, 0x20, 0x30, 0xB6]
+  );
+  // The checksum keeps the low byte of the sum: 0x56 + 200 + 150 + 100 = 0x218
+  assert_eq!(
+    protocol.command(COLOR, FULL_BRIGHTNESS),
+    vec![0x56, 200, 150, 100, 0x18]
+  );
+}
+
+#[test]
+fn template_tokens() {
+  let parse = |token: &str| TemplateToken::try_from(token.to_string());
+  assert_eq!(parse("0xF0"), Ok(TemplateToken::Byte(0xF0)));
+  assert_eq!(parse("0Xaa"), Ok(TemplateToken::Byte(0xAA)));
+  assert_eq!(parse("240"), Ok(TemplateToken::Byte(240)));
+  assert_eq!(parse(" {brightness} "), Ok(TemplateToken::Brightness));
+  for invalid in ["0x100", "256", "{w}", "", "red"] {
+    assert!(parse(invalid).is_err(), "{:?} parsed", invalid);
+  }
+
+  let protocol = LightProtocol::Custom {
+    command_template: vec![TemplateToken::Brightness, TemplateToken::Checksum],
+  };
+  assert_eq!(protocol.command(COLOR, 0x32), vec![0x32, 0x32]);
+}
+
+/// Stands in for a light's control characteristic, keeping every command written to it
+#[derive(Clone, Default)]
+struct MockLight(Arc<Mutex<Vec<Vec<u8>>>>);
+
+#[async_trait]
+impl CommandWriter for MockLight {
+  async fn write_command(&self, command: &[u8], _: WriteType) -> Result<(), btleplug::Error> {
+    self.0.lock().unwrap().push(command.to_vec());
+    Ok(())
+  }
+}
+
+#[tokio::test]
+async fn each_light_frames_the_color_with_its_own_protocol() {
+  let options = WriteOptions {
+    with_response: false,
+    attempts: 1,
+  };
+  let custom = LightProtocol::Custom {
+    command_template: vec![
+      TemplateToken::Byte(0x56),
+      TemplateToken::Red,
+      TemplateToken::Green,
+      TemplateToken::Blue,
+      TemplateToken::Checksum,
+    ],
+  };
+  let (left, right) = (MockLight::default(), MockLight::default());
+  let drivers = [
+    BleLightDriver::with_writer(left.clone(), rgbw(WhiteMode::Extract), options),
+    BleLightDriver::with_writer(right.clone(), custom, options),
+  ];
+  for driver in &drivers {
+    driver.set_color(COLOR, FULL_BRIGHTNESS).await.unwrap();
+  }
+
+  assert_eq!(
+    *left.0.lock().unwrap(),
+    vec![vec![0x01, 100, 50, 0, 100, FULL_BRIGHTNESS]]
+  );
+  assert_eq!(
+    *right.0.lock().unwrap(),
+    vec![vec![0x56, 200, 150, 100, 0x18]]
+  );
+}
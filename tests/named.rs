@@ -0,0 +1,15 @@
+use mini_ambilight_bluetooth::named::{nearest_named_color, NAMED_COLORS};
+
+#[test]
+fn exact_colors_find_their_name() {
+  for (name, rgb) in NAMED_COLORS {
+    assert_eq!(nearest_named_color(*rgb), *name);
+  }
+}
+
+#[test]
+fn close_colors_find_the_nearest_name() {
+  assert_eq!(nearest_named_color([200, 30, 40]), "firebrick");
+  assert_eq!(nearest_named_color([250, 2, 3]), "red");
+  assert_eq!(nearest_named_color([20, 20, 24]), "black");
+}
@@ -0,0 +1,59 @@
+use std::collections::BTreeMap;
+
+use image::{DynamicImage, Rgb, RgbImage};
+use mini_ambilight_bluetooth::vibrant::PaletteOptions;
+use mini_ambilight_bluetooth::Palette;
+
+fn palette() -> Palette {
+  Palette {
+    palette: vec![Rgb([255, 0, 0]), Rgb([0, 255, 0]), Rgb([0, 0, 255])],
+    pixel_counts: BTreeMap::from([(0, 5), (1, 50), (2, 20)]),
+  }
+}
+
+#[test]
+fn dominant_colors_are_the_most_frequent() {
+  let palette = palette();
+  assert_eq!(
+    palette.dominant_colors(2),
+    vec![(Rgb([0, 255, 0]), 50), (Rgb([0, 0, 255]), 20)]
+  );
+  assert_eq!(palette.dominant_colors(10).len(), 3);
+}
+
+#[test]
+fn sort_by_frequency_puts_the_most_frequent_first() {
+  let sorted = palette().sort_by_frequency();
+  assert_eq!(
+    sorted.palette,
+    vec![Rgb([0, 255, 0]), Rgb([0, 0, 255]), Rgb([255, 0, 0])]
+  );
+  assert_eq!(
+    sorted.pixel_counts,
+    BTreeMap::from([(0, 50), (1, 20), (2, 5)])
+  );
+}
+
+/// The most frequent swatch of an all white frame quantized with `options`
+fn white_frame_swatch(options: PaletteOptions) -> Rgb<u8> {
+  let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(16, 16, Rgb([255, 255, 255])));
+  Palette::with_options(&image, 16, 10, &options).dominant_colors(1)[0].0
+}
+
+/// White is left out of the quantization by the default cutoff, kept once the cutoff is raised
+/// past it or the filter is skipped
+#[test]
+fn white_cutoff_options() {
+  let white = Rgb([255, 255, 255]);
+  assert_ne!(white_frame_swatch(PaletteOptions::default()), white);
+  let raised = PaletteOptions {
+    max_color: 255,
+    ..PaletteOptions::default()
+  };
+  assert_eq!(white_frame_swatch(raised), white);
+  let unfiltered = PaletteOptions {
+    skip_boring: false,
+    ..PaletteOptions::default()
+  };
+  assert_eq!(white_frame_swatch(unfiltered), white);
+}
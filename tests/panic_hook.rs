@@ -0,0 +1,43 @@
+use std::panic;
+use std::sync::{Arc, Mutex};
+
+use mini_ambilight_bluetooth::panic_hook::{self, SharedTargets};
+
+const OFF: [u8; 5] = [0x01, 0, 0, 0, 0];
+const RGBW_OFF: [u8; 6] = [0x01, 0, 0, 0, 0, 0];
+
+/// Panic hooks are process wide, so all cases share one test.
+#[test]
+fn panic_hook_writes_off_to_the_lights() {
+  let lights = SharedTargets::<(&str, Vec<u8>)>::default();
+  let written = Arc::new(Mutex::new(Vec::new()));
+  let recorder = written.clone();
+  panic_hook::install(lights.clone(), move |targets| {
+    recorder.lock().unwrap().extend_from_slice(targets);
+  });
+
+  // Nothing connected yet
+  let _ = panic::catch_unwind(|| panic!("before connecting"));
+  assert!(written.lock().unwrap().is_empty());
+
+  // Each light gets the off command of its own protocol
+  let targets = vec![("left", OFF.to_vec()), ("right", RGBW_OFF.to_vec())];
+  *lights.lock().unwrap() = targets.clone();
+  let _ = panic::catch_unwind(|| panic!("while running"));
+  assert_eq!(*written.lock().unwrap(), targets);
+
+  // Panicking with the lights locked must not deadlock the hook
+  written.lock().unwrap().clear();
+  let _ = panic::catch_unwind(|| {
+    let _guard = lights.lock().unwrap();
+    panic!("while locked");
+  });
+  assert!(written.lock().unwrap().is_empty());
+
+  // Poisoned by the last panic, the lights are still switched off
+  assert!(lights.is_poisoned());
+  let _ = panic::catch_unwind(|| panic!("after poisoning"));
+  assert_eq!(written.lock().unwrap().len(), 2);
+
+  let _ = panic::take_hook();
+}
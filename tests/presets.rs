@@ -0,0 +1,84 @@
+use glam::Vec3;
+use mini_ambilight_bluetooth::correction::{ColorPreset, CorrectionSpace, Pipeline, TransformSpec};
+use palette::{Hsl, IntoColor, Srgb};
+
+fn pipeline(transforms: &[TransformSpec]) -> Pipeline {
+  Pipeline::parse(transforms, CorrectionSpace::Hsl).unwrap()
+}
+
+fn max_change(pipeline: &Pipeline, color: Vec3) -> f32 {
+  (pipeline.apply(color) - color).abs().max_element()
+}
+
+/// A soft pull barely moves a color close to the target, where the flat one moves it the most
+/// it can
+#[test]
+fn soft_pulls_leave_near_target_colors_alone() {
+  let near = Vec3::new(0.9, 0.1, 0.1);
+  let flat = pipeline(&[TransformSpec::new("saturation", &[1.0, 0.9])]);
+  let soft = pipeline(&[TransformSpec::new("soft_saturation", &[1.0, 0.9])]);
+  assert!(max_change(&soft, near) < 0.02);
+  assert!(max_change(&flat, near) > 4.0 * max_change(&soft, near));
+}
+
+#[test]
+fn neutral_changes_nothing() {
+  let neutral = pipeline(&ColorPreset::Neutral.transforms());
+  for color in [Vec3::ZERO, Vec3::ONE, Vec3::new(0.2, 0.5, 0.7)] {
+    assert_eq!(neutral.apply(color), color);
+  }
+}
+
+/// The presets order by strength on a dull color
+#[test]
+fn presets_correct_increasingly() {
+  let dull = Vec3::new(0.3, 0.25, 0.2);
+  let change = |preset: ColorPreset| max_change(&pipeline(&preset.transforms()), dull);
+  assert!(change(ColorPreset::Natural) > 0.0);
+  assert!(change(ColorPreset::Natural) < change(ColorPreset::Vivid));
+  assert!(change(ColorPreset::Vivid) <= change(ColorPreset::Legacy));
+}
+
+/// Pulling the saturation of black first has nothing to saturate, the lightness pull then only
+/// lifts it to gray. In the other order the lifted color takes on the saturation.
+#[test]
+fn transform_order_changes_the_output() {
+  let lightness = TransformSpec::new("lightness", &[0.5, 0.9]);
+  let saturation = TransformSpec::new("saturation", &[1.0, 0.9]);
+  let lightness_first = pipeline(&[lightness.clone(), saturation.clone()]).apply(Vec3::ZERO);
+  let saturation_first = pipeline(&[saturation, lightness]).apply(Vec3::ZERO);
+  assert!(
+    (lightness_first - saturation_first).abs().max_element() > 0.1,
+    "{:?} and {:?}",
+    lightness_first,
+    saturation_first
+  );
+  assert!(saturation_first.max_element() - saturation_first.min_element() < 1e-4);
+}
+
+/// The legacy preset pulls lightness and saturation in a single HSL conversion, like the historic
+/// correction
+#[test]
+fn legacy_matches_the_historic_correction() {
+  let mix = |x: f32, y: f32, weight: f32| (x * x * (1.0 - weight) + y * y * weight).sqrt();
+  let legacy = pipeline(&ColorPreset::Legacy.transforms());
+  for color in [
+    Vec3::new(0.3, 0.25, 0.2),
+    Vec3::new(0.9, 0.1, 0.1),
+    Vec3::new(0.05, 0.6, 0.95),
+  ] {
+    let mut hsl: Hsl = Srgb::new(color.x, color.y, color.z).into_color();
+    hsl.lightness = mix(hsl.lightness, 0.5, 0.9);
+    hsl.saturation = mix(hsl.saturation, 1.0, 0.9);
+    let rgb: Srgb = hsl.into_color();
+    let expected = Vec3::new(rgb.red, rgb.green, rgb.blue);
+    let corrected = legacy.apply(color);
+    assert!(
+      (corrected - expected).abs().max_element() < 1e-5,
+      "{:?}: {:?} instead of {:?}",
+      color,
+      corrected,
+      expected
+    );
+  }
+}
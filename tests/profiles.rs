@@ -0,0 +1,104 @@
+use std::collections::BTreeMap;
+
+use mini_ambilight_bluetooth::light::{LightConfig, LightOutput};
+use mini_ambilight_bluetooth::profile::{plan_switch, LightSwitch, Profile};
+use mini_ambilight_bluetooth::Zone;
+
+const PROFILES: &str = r#"
+  [movie]
+  lights = [
+    { mac = "FF:FF:3A:00:02:01", zone = { x = 0.0, y = 0.0, w = 0.2, h = 1.0 } },
+    { mac = "FF:FF:3A:00:02:02", zone = { x = 0.8, y = 0.0, w = 0.2, h = 1.0 } },
+    { mac = "FF:FF:3A:00:02:03", zone = { x = 0.0, y = 0.0, w = 1.0, h = 0.2 } },
+    { output = { type = "wled", addr = "192.168.1.50:21324", leds = 60 } },
+  ]
+
+  [reading]
+  lights = [{ mac = "FF:FF:3A:00:02:03" }]
+  brightness = 40
+  static_color = [255, 180, 110]
+"#;
+
+fn profiles() -> BTreeMap<String, Profile> {
+  toml::from_str(PROFILES).unwrap()
+}
+
+/// The devices driven after switching from `active` to `next` as `plan` says
+fn switched(active: &[LightConfig], next: &[LightConfig], plan: &LightSwitch) -> Vec<String> {
+  let mut devices: Vec<_> = active
+    .iter()
+    .enumerate()
+    .filter(|(index, _)| !plan.dropped.contains(index))
+    .map(|(_, light)| light.to_string())
+    .collect();
+  for (light, reused) in next.iter().zip(&plan.reused) {
+    if reused.is_none() {
+      devices.push(light.to_string());
+    }
+  }
+  devices.sort();
+  devices
+}
+
+#[test]
+fn switching_profiles_changes_the_active_lights() {
+  let profiles = profiles();
+  let (movie, reading) = (&profiles["movie"].lights, &profiles["reading"].lights);
+  assert_eq!(reading[0].zone, Zone::default());
+  assert_eq!(profiles["reading"].brightness, Some(40));
+  assert_eq!(profiles["reading"].static_color, Some([255, 180, 110]));
+
+  // Reading keeps the top light connected, following the whole screen now, and drops the rest.
+  let plan = plan_switch(movie, reading);
+  assert_eq!(
+    plan,
+    LightSwitch {
+      reused: vec![Some(2)],
+      dropped: vec![0, 1, 3],
+    }
+  );
+  assert_eq!(switched(movie, reading, &plan), vec!["FF:FF:3A:00:02:03"]);
+
+  // Back to the movie, only the lights reading left out are connected again.
+  let plan = plan_switch(reading, movie);
+  assert_eq!(
+    plan,
+    LightSwitch {
+      reused: vec![None, None, Some(0), None],
+      dropped: vec![],
+    }
+  );
+  assert_eq!(
+    switched(reading, movie, &plan),
+    vec![
+      "FF:FF:3A:00:02:01",
+      "FF:FF:3A:00:02:02",
+      "FF:FF:3A:00:02:03",
+      "WLED 192.168.1.50:21324",
+    ]
+  );
+}
+
+#[test]
+fn lights_match_by_device_not_zone() {
+  let profiles = profiles();
+  let movie = &profiles["movie"].lights;
+  let mut moved = movie[3].clone();
+  moved.zone = Zone::default();
+  assert!(moved.same_device(&movie[3]));
+  assert!(!movie[0].same_device(&movie[1]));
+
+  // A strip driven from another start index is another device.
+  let mut other = movie[3].clone();
+  other.output = LightOutput::Wled {
+    addr: "192.168.1.50:21324".to_string(),
+    start: 60,
+    leds: 60,
+  };
+  assert!(!other.same_device(&movie[3]));
+
+  // A device listed twice is only handed over once.
+  let plan = plan_switch(&movie[..1], &[movie[0].clone(), movie[0].clone()]);
+  assert_eq!(plan.reused, vec![Some(0), None]);
+  assert!(plan.dropped.is_empty());
+}
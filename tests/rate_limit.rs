@@ -0,0 +1,88 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use mini_ambilight_bluetooth::light::{LightDriver, LightError, RateLimitedDriver};
+
+const INTERVAL: Duration = Duration::from_millis(100);
+
+/// Records every color written, or fails while `fail` is set
+#[derive(Default)]
+struct Recorder {
+  written: Mutex<Vec<[u8; 3]>>,
+  fail: AtomicBool,
+}
+
+impl Recorder {
+  fn written(&self) -> Vec<[u8; 3]> {
+    self.written.lock().unwrap().clone()
+  }
+}
+
+#[async_trait]
+impl LightDriver for Recorder {
+  async fn set_color(&self, rgb: [u8; 3], _brightness: u8) -> Result<(), LightError> {
+    if self.fail.load(Ordering::SeqCst) {
+      return Err(LightError::Udp(io::Error::other("light gone")));
+    }
+    self.written.lock().unwrap().push(rgb);
+    Ok(())
+  }
+}
+
+fn limited() -> (Arc<Recorder>, RateLimitedDriver) {
+  let recorder = Arc::new(Recorder::default());
+  let driver = RateLimitedDriver::new(recorder.clone(), INTERVAL);
+  (recorder, driver)
+}
+
+#[tokio::test]
+async fn burst_writes_first_and_latest() {
+  tokio::time::pause();
+  let (recorder, driver) = limited();
+
+  driver.set_color([1, 0, 0], 100).await.unwrap();
+  driver.set_color([2, 0, 0], 100).await.unwrap();
+  driver.set_color([3, 0, 0], 100).await.unwrap();
+  // Only the first went out right away, the others are held.
+  assert_eq!(recorder.written(), vec![[1, 0, 0]]);
+
+  tokio::time::sleep(INTERVAL / 2).await;
+  driver.set_color([4, 0, 0], 100).await.unwrap();
+  assert_eq!(recorder.written(), vec![[1, 0, 0]]);
+
+  tokio::time::sleep(INTERVAL).await;
+  assert_eq!(recorder.written(), vec![[1, 0, 0], [4, 0, 0]]);
+  driver.flush().await.unwrap();
+  assert_eq!(recorder.written(), vec![[1, 0, 0], [4, 0, 0]]);
+
+  // Once the interval passed without writes the next color goes out right away again.
+  tokio::time::sleep(INTERVAL * 2).await;
+  driver.set_color([5, 0, 0], 100).await.unwrap();
+  assert_eq!(recorder.written(), vec![[1, 0, 0], [4, 0, 0], [5, 0, 0]]);
+}
+
+#[tokio::test]
+async fn held_write_errors_surface_on_the_next_call() {
+  tokio::time::pause();
+  let (recorder, driver) = limited();
+
+  driver.set_color([1, 0, 0], 100).await.unwrap();
+  recorder.fail.store(true, Ordering::SeqCst);
+  // Held, so the failure can only show up later
+  driver.set_color([2, 0, 0], 100).await.unwrap();
+  tokio::time::sleep(INTERVAL * 2).await;
+  assert!(driver.set_color([3, 0, 0], 100).await.is_err());
+  // Reported once, the next color is written again.
+  recorder.fail.store(false, Ordering::SeqCst);
+  tokio::time::sleep(INTERVAL).await;
+  driver.set_color([4, 0, 0], 100).await.unwrap();
+  assert_eq!(recorder.written(), vec![[1, 0, 0], [4, 0, 0]]);
+
+  recorder.fail.store(true, Ordering::SeqCst);
+  driver.set_color([5, 0, 0], 100).await.unwrap();
+  assert!(driver.flush().await.is_err());
+  assert!(driver.flush().await.is_ok());
+}
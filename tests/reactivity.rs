@@ -0,0 +1,55 @@
+use glam::Vec3;
+use mini_ambilight_bluetooth::correction::{ColorTransform, CorrectionSpace, ReactivityCurve};
+use palette::{Hsv, IntoColor, Srgb};
+
+/// The curve of the example config: washed out colors stay near white, colorful ones saturate
+fn curve() -> ReactivityCurve {
+  ReactivityCurve::new(&[0.0, 0.1, 0.3, 0.4, 0.8, 1.0], CorrectionSpace::Hsv).unwrap()
+}
+
+fn saturation(color: Vec3) -> f32 {
+  let hsv: Hsv = Srgb::new(color.x, color.y, color.z).into_color();
+  hsv.saturation
+}
+
+/// Points are interpolated linearly and clamped to the first and last one
+#[test]
+fn curve_interpolates_between_points() {
+  let curve = curve();
+  for (chroma, expected) in [
+    (0.0, 0.1),
+    (0.15, 0.25),
+    (0.3, 0.4),
+    (0.55, 0.7),
+    (0.8, 1.0),
+    (1.0, 1.0),
+  ] {
+    assert!(
+      (curve.saturation(chroma) - expected).abs() < 1e-5,
+      "chroma {}: {}",
+      chroma,
+      curve.saturation(chroma)
+    );
+  }
+  assert_eq!(curve.saturation(f32::NAN), 1.0);
+}
+
+/// A dull color of low chroma comes out nearly white, a colorful one fully saturated
+#[test]
+fn low_chroma_stays_pale_and_high_chroma_saturates() {
+  let curve = curve();
+  let dull = curve.apply(Vec3::new(0.6, 0.55, 0.5));
+  // Chroma 0.1 sits a third of the way to the second point
+  assert!((saturation(dull) - 0.2).abs() < 1e-3, "{:?}", dull);
+  let vivid = curve.apply(Vec3::new(0.9, 0.1, 0.3));
+  assert!((saturation(vivid) - 1.0).abs() < 1e-5, "{:?}", vivid);
+}
+
+#[test]
+fn invalid_curves_are_rejected() {
+  let space = CorrectionSpace::Hsv;
+  assert!(ReactivityCurve::new(&[0.0, 0.1], space).is_err());
+  assert!(ReactivityCurve::new(&[0.0, 0.1, 0.5], space).is_err());
+  assert!(ReactivityCurve::new(&[0.5, 0.1, 0.2, 0.4], space).is_err());
+  assert!(ReactivityCurve::new(&[0.0, 0.1, 1.0, 1.5], space).is_err());
+}
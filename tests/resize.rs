@@ -0,0 +1,22 @@
+use image::imageops::{self, FilterType};
+use image::{Rgb, RgbImage};
+use mini_ambilight_bluetooth::sampling::resize_linear;
+
+/// A black and white checkerboard emits half the light of white. Averaged in linear light that is
+/// sRGB 188, averaging the sRGB values gives a far too dark 128.
+#[test]
+fn linear_downsample_of_a_checkerboard_is_brighter() {
+  let checkerboard = RgbImage::from_fn(64, 64, |x, y| {
+    if (x + y) % 2 == 0 {
+      Rgb([255, 255, 255])
+    } else {
+      Rgb([0, 0, 0])
+    }
+  });
+  let linear = resize_linear(&checkerboard, 8, 8, FilterType::Triangle);
+  let gamma = imageops::resize(&checkerboard, 8, 8, FilterType::Triangle);
+  for (linear, gamma) in linear.pixels().zip(gamma.pixels()) {
+    assert!((linear[0] as i32 - 188).abs() <= 2, "{:?}", linear);
+    assert!((gamma[0] as i32 - 128).abs() <= 2, "{:?}", gamma);
+  }
+}
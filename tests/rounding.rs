@@ -0,0 +1,26 @@
+use glam::Vec3;
+use mini_ambilight_bluetooth::correction::{to_bytes, Rounding};
+
+/// 127.9 of 255 is cut off to 127 by the historic truncation and rounded up to 128 by `Nearest`
+#[test]
+fn nearest_rounds_where_truncate_cuts_off() {
+  let color = Vec3::splat(127.9 / 255.0);
+  assert_eq!(to_bytes(color, Rounding::Truncate), [127; 3]);
+  assert_eq!(to_bytes(color, Rounding::Nearest), [128; 3]);
+  assert_eq!(to_bytes(color, Rounding::Floor), [127; 3]);
+  assert_eq!(to_bytes(color, Rounding::Ceil), [128; 3]);
+}
+
+/// Values outside of 0...1 are clamped before rounding
+#[test]
+fn out_of_range_values_clamp() {
+  let color = Vec3::new(-0.5, 1.5, 1.0);
+  for rounding in [
+    Rounding::Truncate,
+    Rounding::Nearest,
+    Rounding::Floor,
+    Rounding::Ceil,
+  ] {
+    assert_eq!(to_bytes(color, rounding), [0, 255, 255]);
+  }
+}
@@ -0,0 +1,32 @@
+#![cfg(feature = "serde")]
+
+use std::collections::BTreeMap;
+
+use image::Rgb;
+use mini_ambilight_bluetooth::{Palette, Vibrancy};
+
+/// Vibrancy survives a round trip, including the unset swatches
+#[test]
+fn vibrancy_round_trip() {
+  let vibrancy = Vibrancy {
+    primary: Some(Rgb([220, 40, 40])),
+    dark: Some(Rgb([120, 10, 10])),
+    light: None,
+    muted: Some(Rgb([140, 110, 100])),
+    dark_muted: None,
+    light_muted: Some(Rgb([210, 200, 190])),
+  };
+  let text = toml::to_string(&vibrancy).unwrap();
+  assert_eq!(toml::from_str::<Vibrancy>(&text).unwrap(), vibrancy);
+}
+
+/// The palette keeps its swatches and the pixel count of each
+#[test]
+fn palette_round_trip() {
+  let palette = Palette {
+    palette: vec![Rgb([255, 0, 0]), Rgb([0, 255, 0]), Rgb([0, 0, 255])],
+    pixel_counts: BTreeMap::from([(0, 5), (2, 20)]),
+  };
+  let text = toml::to_string(&palette).unwrap();
+  assert_eq!(toml::from_str::<Palette>(&text).unwrap(), palette);
+}
@@ -0,0 +1,31 @@
+use mini_ambilight_bluetooth::correction::SkipMetric;
+
+/// Bright reds a few units apart, which look the same
+const BRIGHT: ([u8; 3], [u8; 3]) = ([200, 0, 0], [205, 0, 2]);
+/// Black and a faint blue, which is clearly visible in a dark room
+const DARK: ([u8; 3], [u8; 3]) = ([0, 0, 0], [0, 0, 10]);
+
+#[test]
+fn rgb_ranks_the_dark_pair_closer() {
+  let bright = SkipMetric::Rgb.distance(BRIGHT.0, BRIGHT.1);
+  let dark = SkipMetric::Rgb.distance(DARK.0, DARK.1);
+  assert_eq!(bright, 5.0);
+  assert_eq!(dark, 10.0);
+}
+
+#[test]
+fn delta_e_ranks_the_bright_pair_closer() {
+  let bright = SkipMetric::DeltaE.distance(BRIGHT.0, BRIGHT.1);
+  let dark = SkipMetric::DeltaE.distance(DARK.0, DARK.1);
+  assert!(bright < 2.0, "{}", bright);
+  assert!(dark > 2.0 * bright, "{} vs {}", dark, bright);
+}
+
+#[test]
+fn identical_colors_have_no_distance() {
+  for metric in [SkipMetric::Rgb, SkipMetric::DeltaE] {
+    for rgb in [[0, 0, 0], [255, 255, 255], [12, 200, 99]] {
+      assert!(metric.distance(rgb, rgb).abs() < 1e-3);
+    }
+  }
+}
@@ -0,0 +1,82 @@
+use std::collections::BTreeMap;
+
+use glam::Vec3;
+use image::Rgb;
+use mini_ambilight_bluetooth::correction::Smoother;
+use mini_ambilight_bluetooth::vibrant::PaletteSmoother;
+use mini_ambilight_bluetooth::Palette;
+
+const RED: Vec3 = Vec3::X;
+const BLUE: Vec3 = Vec3::Z;
+
+/// Colors flipping every frame settle close to their mean once the window is full
+#[test]
+fn alternating_colors_converge() {
+  let mut smoother = Smoother::new(8);
+  let mean = (RED + BLUE) / 2.0;
+  let outputs: Vec<Vec3> = (0..32)
+    .map(|frame| smoother.push(if frame % 2 == 0 { RED } else { BLUE }))
+    .collect();
+  for output in &outputs[8..] {
+    let error = (*output - mean).abs().max_element();
+    assert!(
+      error < 0.1,
+      "{:?} is {} away from {:?}",
+      output,
+      error,
+      mean
+    );
+  }
+}
+
+#[test]
+fn reset_snaps_to_the_new_color() {
+  let mut smoother = Smoother::new(8);
+  for _ in 0..8 {
+    smoother.push(RED);
+  }
+  smoother.reset();
+  assert_eq!(smoother.push(BLUE), BLUE);
+}
+
+#[test]
+fn single_frame_passes_through() {
+  let mut smoother = Smoother::new(1);
+  smoother.push(RED);
+  assert_eq!(smoother.push(BLUE), BLUE);
+}
+
+fn palette(swatch: [u8; 3]) -> Palette {
+  Palette {
+    palette: vec![Rgb(swatch)],
+    pixel_counts: BTreeMap::from([(0, 1)]),
+  }
+}
+
+/// A swatch moving within the threshold closes half the remaining gap every frame, converging on
+/// the new color without overshooting
+#[test]
+fn palette_swatch_converges() {
+  let mut smoother = PaletteSmoother::new(0.5, 24.0);
+  assert_eq!(
+    smoother.smooth(&palette([100, 0, 0])).palette,
+    [Rgb([100, 0, 0])]
+  );
+  let reds: Vec<u8> = (0..8)
+    .map(|_| smoother.smooth(&palette([120, 0, 0])).palette[0][0])
+    .collect();
+  assert_eq!(reds[..3], [110, 115, 118]);
+  assert!(reds.windows(2).all(|pair| pair[0] <= pair[1]), "{:?}", reds);
+  assert_eq!(*reds.last().unwrap(), 120);
+}
+
+/// A swatch further than the threshold from every smoothed one is a new color, taken as it is
+#[test]
+fn palette_jump_passes_through() {
+  let mut smoother = PaletteSmoother::new(0.5, 24.0);
+  smoother.smooth(&palette([100, 0, 0]));
+  assert_eq!(
+    smoother.smooth(&palette([0, 0, 200])).palette,
+    [Rgb([0, 0, 200])]
+  );
+}
@@ -0,0 +1,88 @@
+use std::net::SocketAddr;
+
+use mini_ambilight_bluetooth::gatt::DeviceInfo;
+use mini_ambilight_bluetooth::health::HealthState;
+use mini_ambilight_bluetooth::status::{self, LightStatus, SharedStatus};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Status line and body of the response to `GET path`
+async fn get(addr: SocketAddr, path: &str) -> (String, String) {
+  let mut socket = TcpStream::connect(addr).await.unwrap();
+  let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+  socket.write_all(request.as_bytes()).await.unwrap();
+  let mut response = String::new();
+  socket.read_to_string(&mut response).await.unwrap();
+  let (head, body) = response.split_once("\r\n\r\n").unwrap();
+  (head.lines().next().unwrap().to_string(), body.to_string())
+}
+
+async fn serve(lights: Vec<LightStatus>) -> SocketAddr {
+  let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+  let addr = listener.local_addr().unwrap();
+  let status = SharedStatus::default();
+  *status.lock().unwrap() = lights;
+  tokio::spawn(status::serve(listener, status));
+  addr
+}
+
+#[tokio::test]
+async fn serves_color_and_stats_as_json() {
+  let addr = serve(vec![
+    LightStatus {
+      name: "FF:FF:3A:00:02:8F".to_string(),
+      rgb: [255, 128, 0],
+      connected: true,
+      health: HealthState::Healthy,
+      failures: 0,
+      battery_level: Some(80),
+      device_info: DeviceInfo {
+        manufacturer: Some("Triones \"Mini\"".to_string()),
+        model: None,
+        firmware: Some("1.2".to_string()),
+      },
+    },
+    LightStatus {
+      name: "WLED 192.168.1.50:21324".to_string(),
+      rgb: [0, 0, 0],
+      connected: false,
+      health: HealthState::Dropped,
+      failures: 4,
+      battery_level: None,
+      device_info: DeviceInfo::default(),
+    },
+  ])
+  .await;
+
+  let (status, body) = get(addr, "/color").await;
+  assert_eq!(status, "HTTP/1.1 200 OK");
+  assert_eq!(body, r#"{"r":255,"g":128,"b":0}"#);
+
+  let (status, body) = get(addr, "/stats").await;
+  assert_eq!(status, "HTTP/1.1 200 OK");
+  // Nothing in this process records metrics, so the counters are all zero.
+  assert_eq!(
+    body,
+    concat!(
+      r#"{"fps":0.0,"frames_processed":0,"writes_sent":0,"writes_skipped":0,"write_failures":0,"#,
+      r#""reconnects":0,"lights":["#,
+      r#"{"name":"FF:FF:3A:00:02:8F","color":{"r":255,"g":128,"b":0},"connected":true,"#,
+      r#""health":"healthy","failures":0,"battery_level":80,"manufacturer":"Triones \"Mini\"","model":null,"firmware":"1.2"},"#,
+      r#"{"name":"WLED 192.168.1.50:21324","color":{"r":0,"g":0,"b":0},"connected":false,"#,
+      r#""health":"dropped","failures":4,"battery_level":null,"manufacturer":null,"model":null,"firmware":null}]}"#
+    )
+  );
+}
+
+#[tokio::test]
+async fn unknown_paths_and_no_lights() {
+  let addr = serve(Vec::new()).await;
+
+  let (status, body) = get(addr, "/color").await;
+  assert_eq!(status, "HTTP/1.1 200 OK");
+  assert_eq!(body, "null");
+
+  let (status, body) = get(addr, "/metrics").await;
+  assert_eq!(status, "HTTP/1.1 404 Not Found");
+  assert_eq!(body, "");
+}
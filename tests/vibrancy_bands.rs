@@ -0,0 +1,153 @@
+use std::collections::BTreeMap;
+
+use hsl::HSL;
+use image::Rgb;
+use mini_ambilight_bluetooth::vibrant::{
+  generate_varation_colors, BandSpace, VariationRange, VariationRanges, VibrancyConfig,
+};
+use mini_ambilight_bluetooth::{Palette, Vibrancy};
+use proptest::prelude::*;
+
+/// Slack for swatches derived from another variation, whose lightness is moved and then rounded
+/// back to bytes
+const ROUNDING: f64 = 0.02;
+
+/// Palettes of up to 16 swatches given by HSL, each with a non-zero population
+fn palettes() -> impl Strategy<Value = Palette> {
+  prop::collection::vec(
+    (0.0..360.0_f64, 0.0..=1.0_f64, 0.0..=1.0_f64, 1..1000_usize),
+    1..16,
+  )
+  .prop_map(|swatches| {
+    let palette = swatches
+      .iter()
+      .map(|&(h, s, l, _)| {
+        let (r, g, b) = HSL { h, s, l }.to_rgb();
+        Rgb([r, g, b])
+      })
+      .collect();
+    let pixel_counts = swatches
+      .iter()
+      .enumerate()
+      .map(|(index, &(_, _, _, count))| (index, count))
+      .collect::<BTreeMap<_, _>>();
+    Palette {
+      palette,
+      pixel_counts,
+    }
+  })
+}
+
+fn assert_in_range(name: &str, swatch: Option<Rgb<u8>>, range: &VariationRange) {
+  let Some(swatch) = swatch else {
+    return;
+  };
+  let HSL { s, l, .. } = HSL::from_rgb(&swatch.0);
+  assert!(
+    l >= range.luma.min - ROUNDING && l <= range.luma.max + ROUNDING,
+    "{} {:?} has luma {} outside {}...{}",
+    name,
+    swatch,
+    l,
+    range.luma.min,
+    range.luma.max
+  );
+  assert!(
+    s >= range.saturation.min - ROUNDING && s <= range.saturation.max + ROUNDING,
+    "{} {:?} has saturation {} outside {}...{}",
+    name,
+    swatch,
+    s,
+    range.saturation.min,
+    range.saturation.max
+  );
+}
+
+proptest! {
+  /// Every variation found lies in its luma and saturation band, e.g. `dark` at most
+  /// `MAX_DARK_LUMA` and the muted ones at most `MAX_MUTED_SATURATION`
+  #[test]
+  fn variations_stay_in_their_bands(palette in palettes()) {
+    let ranges = VariationRanges::default();
+    let vibrancy =
+      generate_varation_colors(&palette, &ranges, &VibrancyConfig::default(), None);
+    assert_in_range("primary", vibrancy.primary, &ranges.primary);
+    assert_in_range("light", vibrancy.light, &ranges.light);
+    assert_in_range("dark", vibrancy.dark, &ranges.dark);
+    assert_in_range("muted", vibrancy.muted, &ranges.muted);
+    assert_in_range("light_muted", vibrancy.light_muted, &ranges.light_muted);
+    assert_in_range("dark_muted", vibrancy.dark_muted, &ranges.dark_muted);
+  }
+
+  /// Variations only ever pick swatches of the palette, apart from the derived primary and dark
+  #[test]
+  fn variations_come_from_the_palette(palette in palettes()) {
+    let vibrancy = generate_varation_colors(
+      &palette,
+      &VariationRanges::default(),
+      &VibrancyConfig::default(),
+      None,
+    );
+    for swatch in [vibrancy.light, vibrancy.muted, vibrancy.light_muted, vibrancy.dark_muted]
+      .into_iter()
+      .flatten()
+    {
+      prop_assert!(palette.palette.contains(&swatch));
+    }
+  }
+}
+
+/// Variations of a palette holding only `swatch`
+fn single_swatch(swatch: Rgb<u8>, config: &VibrancyConfig) -> Vibrancy {
+  let palette = Palette {
+    palette: vec![swatch],
+    pixel_counts: BTreeMap::from([(0, 100)]),
+  };
+  Vibrancy::from_palette(&palette, config, None)
+}
+
+/// The swatch with its HSL lightness replaced, as the derived variations are built
+fn relit(swatch: Rgb<u8>, l: f64) -> Rgb<u8> {
+  let HSL { h, s, .. } = HSL::from_rgb(&swatch.0);
+  let (r, g, b) = HSL { h, s, l }.to_rgb();
+  Rgb([r, g, b])
+}
+
+/// Only a dark vibrant swatch: the primary is derived from it at normal lightness
+#[test]
+fn primary_is_filled_from_dark() {
+  let dark = Rgb([120, 10, 10]);
+  let vibrancy = single_swatch(dark, &VibrancyConfig::default());
+  assert_eq!(vibrancy.dark, Some(dark));
+  assert_eq!(vibrancy.primary, Some(relit(dark, 0.5)));
+}
+
+/// Only a primary swatch: the dark vibrant is derived from it at dark lightness
+#[test]
+fn dark_is_filled_from_primary() {
+  let primary = Rgb([220, 40, 40]);
+  let vibrancy = single_swatch(primary, &VibrancyConfig::default());
+  assert_eq!(vibrancy.primary, Some(primary));
+  assert_eq!(vibrancy.dark, Some(relit(primary, 0.26)));
+}
+
+/// Yellow sits at half lightness in HSL, a primary, but is nearly white by Oklab lightness, a
+/// light vibrant
+#[test]
+fn band_spaces_classify_yellow_differently() {
+  let yellow = Rgb([255, 255, 0]);
+  let hsl = single_swatch(
+    yellow,
+    &VibrancyConfig {
+      band_space: BandSpace::Hsl,
+    },
+  );
+  assert_eq!((hsl.primary, hsl.light), (Some(yellow), None));
+  let oklab = single_swatch(
+    yellow,
+    &VibrancyConfig {
+      band_space: BandSpace::Oklab,
+    },
+  );
+  assert_eq!((oklab.primary, oklab.light), (None, Some(yellow)));
+}